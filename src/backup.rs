@@ -0,0 +1,379 @@
+//! 在线备份与快照
+//!
+//! 使用 SQLite 的 [online backup API] 在服务器运行时生成一致的快照，
+//! 按小时 / 天 / 周三级保留策略轮换旧快照，并可选上传到 S3。
+//!
+//! [online backup API]: https://sqlite.org/backup.html
+
+use crate::{AppError, AppResult, AppState, ServerConfig, crypto::SseCustomerKey, db};
+use axum::Extension;
+use chrono::{Local, NaiveDateTime};
+use rusqlite::backup::Backup;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_EXT: &str = ".sqlite3";
+// 带毫秒，同一秒内的两次备份不会互相覆盖
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S%.3f";
+// 旧版本生成的快照名只精确到秒
+const LEGACY_TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct BackupConfig {
+    // 是否启用定时备份；手动触发 (POST /api/admin/backup) 不受此开关影响
+    #[serde(default)]
+    pub(crate) enable: bool,
+    #[serde(default = "default_backup_dir")]
+    pub(crate) dir: PathBuf,
+    #[serde(default = "default_interval_minutes")]
+    pub(crate) interval_minutes: u64,
+    // 保留策略：每小时 / 每天 / 每周各保留最近的若干份
+    #[serde(default = "default_keep_hourly")]
+    pub(crate) keep_hourly: usize,
+    #[serde(default = "default_keep_daily")]
+    pub(crate) keep_daily: usize,
+    #[serde(default = "default_keep_weekly")]
+    pub(crate) keep_weekly: usize,
+    // 是否把快照上传到 [s3] 配置的 bucket
+    #[serde(default)]
+    pub(crate) upload_s3: bool,
+    #[serde(default = "default_s3_prefix")]
+    pub(crate) s3_prefix: String,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            dir: default_backup_dir(),
+            interval_minutes: default_interval_minutes(),
+            keep_hourly: default_keep_hourly(),
+            keep_daily: default_keep_daily(),
+            keep_weekly: default_keep_weekly(),
+            upload_s3: false,
+            s3_prefix: default_s3_prefix(),
+        }
+    }
+}
+
+fn default_backup_dir() -> PathBuf {
+    PathBuf::from("./backups")
+}
+fn default_interval_minutes() -> u64 {
+    60
+}
+fn default_keep_hourly() -> usize {
+    24
+}
+fn default_keep_daily() -> usize {
+    7
+}
+fn default_keep_weekly() -> usize {
+    4
+}
+fn default_s3_prefix() -> String {
+    "backups/".to_string()
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct BackupReport {
+    file: String,
+    size: u64,
+    removed: Vec<String>,
+    s3_key: Option<String>,
+}
+
+/// 使用 online backup API 生成一份快照 (阻塞操作)
+fn create_snapshot(server: &ServerConfig, cfg: &BackupConfig) -> AppResult<PathBuf> {
+    std::fs::create_dir_all(&cfg.dir)
+        .map_err(|e| AppError::Database(format!("Failed to create backup dir {:?}: {}", cfg.dir, e)))?;
+
+    let name = format!("{}{}{}", SNAPSHOT_PREFIX, Local::now().format(TIMESTAMP_FORMAT), SNAPSHOT_EXT);
+    let target = cfg.dir.join(&name);
+    if target.exists() {
+        return Err(AppError::Database(format!("Snapshot {:?} already exists", target)));
+    }
+    let partial = db::sibling_path(&target, "partial");
+    let _ = std::fs::remove_file(&partial);
+
+    let passphrase = server.db_passphrase.as_deref();
+    let src = db::open_connection(&server.db_path, passphrase)?;
+    // 加密数据库的快照使用同样的密码
+    let mut dst = db::open_connection(&partial, passphrase)?;
+    Backup::new(&src, &mut dst)
+        .and_then(|b| b.run_to_completion(256, Duration::from_millis(5), None))
+        .map_err(|e| AppError::Database(format!("Backup failed: {}", e)))?;
+    drop(dst);
+
+    std::fs::rename(&partial, &target)
+        .map_err(|e| AppError::Database(format!("Failed to finalize snapshot: {}", e)))?;
+    tracing::info!("Created database snapshot {:?}", target);
+    Ok(target)
+}
+
+/// 按保留策略删除多余的快照，返回被删除的文件
+fn rotate(cfg: &BackupConfig) -> AppResult<Vec<PathBuf>> {
+    let mut snapshots: Vec<(NaiveDateTime, PathBuf)> = std::fs::read_dir(&cfg.dir)
+        .map_err(|e| AppError::Database(format!("Failed to list {:?}: {}", cfg.dir, e)))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let stamp = name.strip_prefix(SNAPSHOT_PREFIX)?.strip_suffix(SNAPSHOT_EXT)?;
+            let time = NaiveDateTime::parse_from_str(stamp, TIMESTAMP_FORMAT)
+                .or_else(|_| NaiveDateTime::parse_from_str(stamp, LEGACY_TIMESTAMP_FORMAT))
+                .ok()?;
+            Some((time, entry.path()))
+        })
+        .collect();
+    // 最新的在前
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.0));
+
+    let mut keep: HashSet<usize> = HashSet::new();
+    keep.insert(0);
+    for (format, limit) in [("%Y%m%d%H", cfg.keep_hourly), ("%Y%m%d", cfg.keep_daily), ("%G%V", cfg.keep_weekly)] {
+        let mut seen = HashSet::new();
+        for (idx, (time, _)) in snapshots.iter().enumerate() {
+            if seen.len() >= limit {
+                break;
+            }
+            if seen.insert(time.format(format).to_string()) {
+                keep.insert(idx);
+            }
+        }
+    }
+
+    let mut removed = Vec::new();
+    for (idx, (_, path)) in snapshots.into_iter().enumerate() {
+        if keep.contains(&idx) {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed.push(path),
+            Err(e) => tracing::warn!("Failed to remove old snapshot {:?}: {}", path, e),
+        }
+    }
+    if !removed.is_empty() {
+        tracing::info!("Rotated {} old snapshot(s)", removed.len());
+    }
+    Ok(removed)
+}
+
+async fn upload_snapshot(state: &AppState, cfg: &BackupConfig, path: &Path) -> AppResult<Option<String>> {
+    let client = match &state.s3_client {
+        Some(c) => c,
+        None => {
+            tracing::warn!("Snapshot upload requested, but S3 is not enabled");
+            return Ok(None);
+        }
+    };
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let key = format!("{}{}", cfg.s3_prefix, name);
+    let body = aws_sdk_s3::primitives::ByteStream::from_path(path)
+        .await
+        .map_err(|e| AppError::Response(format!("Failed to read snapshot: {}", e)))?;
+
+    let mut req = client
+        .put_object()
+        .bucket(&state.bucket_name)
+        .key(&key)
+        .content_type("application/vnd.sqlite3")
+        .body(body);
+    if let Some(sse) = &state.sse_c {
+        req = req
+            .sse_customer_algorithm(SseCustomerKey::ALGORITHM)
+            .sse_customer_key(&sse.key_b64)
+            .sse_customer_key_md5(&sse.key_md5_b64);
+    }
+    req.send()
+        .await
+        .map_err(|e| AppError::Response(format!("Snapshot upload failed: {}", e)))?;
+
+    tracing::info!("Uploaded snapshot to S3: {}", key);
+    Ok(Some(key))
+}
+
+/// 生成快照、轮换旧快照，并按配置上传
+pub(crate) async fn run_backup(server: ServerConfig, cfg: BackupConfig, state: Arc<AppState>) -> AppResult<BackupReport> {
+    let blocking_cfg = cfg.clone();
+    let (path, removed) = tokio::task::spawn_blocking(move || {
+        let path = create_snapshot(&server, &blocking_cfg)?;
+        let removed = rotate(&blocking_cfg)?;
+        Ok::<_, AppError>((path, removed))
+    })
+    .await
+    .map_err(|e| AppError::Database(format!("Backup task panicked: {}", e)))??;
+
+    let s3_key = if cfg.upload_s3 { upload_snapshot(&state, &cfg, &path).await? } else { None };
+    let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+
+    Ok(BackupReport {
+        file: path.display().to_string(),
+        size,
+        removed: removed.iter().map(|p| p.display().to_string()).collect(),
+        s3_key,
+    })
+}
+
+/// 启动定时备份任务
+pub(crate) fn spawn_scheduler(server: ServerConfig, cfg: BackupConfig, state: Arc<AppState>) {
+    let period = Duration::from_secs(cfg.interval_minutes.max(1) * 60);
+    tracing::info!("Scheduled backups every {} minute(s) into {:?}", cfg.interval_minutes.max(1), cfg.dir);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        // 第一次 tick 立即返回，跳过它，避免每次启动都生成快照
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if let Err(e) = run_backup(server.clone(), cfg.clone(), state.clone()).await {
+                tracing::error!("Scheduled backup failed: {:?}", e);
+            }
+        }
+    });
+}
+
+/// 用快照覆盖当前数据库 (离线操作，需先停止服务器)。
+/// 覆盖前会把当前数据库另存为 `<db_path>.pre-restore`。
+pub(crate) fn restore(server: &ServerConfig, snapshot: &Path) -> AppResult<()> {
+    // 打开不存在的文件会创建一个空数据库，它能通过完整性检查
+    if !snapshot.is_file() {
+        return Err(AppError::Database(format!("Snapshot {:?} does not exist", snapshot)));
+    }
+    let passphrase = server.db_passphrase.as_deref();
+    let src = db::open_connection(snapshot, passphrase)?;
+    let check: String = src
+        .query_row("PRAGMA integrity_check", [], |r| r.get(0))
+        .map_err(AppError::from)?;
+    if check != "ok" {
+        return Err(AppError::Database(format!("Snapshot {:?} failed integrity check: {}", snapshot, check)));
+    }
+    let has_tiddlers: bool = src
+        .query_row("SELECT count(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'tiddlers'", [], |r| r.get(0))
+        .map_err(AppError::from)?;
+    if !has_tiddlers {
+        return Err(AppError::Database(format!("{:?} is not a wiki database (no tiddlers table)", snapshot)));
+    }
+
+    if server.db_path.exists() {
+        let safety = db::sibling_path(&server.db_path, "pre-restore");
+        let _ = std::fs::remove_file(&safety);
+        let current = db::open_connection(&server.db_path, passphrase)?;
+        let mut copy = db::open_connection(&safety, passphrase)?;
+        Backup::new(&current, &mut copy)
+            .and_then(|b| b.run_to_completion(256, Duration::from_millis(0), None))
+            .map_err(|e| AppError::Database(format!("Failed to save current database: {}", e)))?;
+        tracing::info!("Saved current database to {:?}", safety);
+    }
+
+    let mut dst = db::open_connection(&server.db_path, passphrase)?;
+    Backup::new(&src, &mut dst)
+        .and_then(|b| b.run_to_completion(256, Duration::from_millis(0), None))
+        .map_err(|e| AppError::Database(format!("Restore failed: {}", e)))?;
+    tracing::info!("Restored {:?} from {:?}", server.db_path, snapshot);
+    Ok(())
+}
+
+// --- Handler: POST /api/admin/backup ---
+pub(crate) async fn backup_now(
+    Extension(server): Extension<ServerConfig>,
+    Extension(cfg): Extension<Arc<BackupConfig>>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> AppResult<axum::Json<BackupReport>> {
+    let report = run_backup(server, cfg.as_ref().clone(), state).await?;
+    Ok(axum::Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Tiddler, testutil};
+    use serde_json::json;
+
+    fn backup_config(dir: &Path) -> BackupConfig {
+        BackupConfig { dir: dir.to_path_buf(), keep_hourly: 2, keep_daily: 2, keep_weekly: 1, ..Default::default() }
+    }
+
+    #[test]
+    fn rotation_keeps_newest_per_period() {
+        let tmp = testutil::TempDir::new("rotate");
+        let names = [
+            "snapshot-20250110-120000.000.sqlite3",
+            // 旧格式的文件名也参与轮换
+            "snapshot-20250110-113000.sqlite3",
+            "snapshot-20250110-100000.250.sqlite3",
+            "snapshot-20250109-230000.000.sqlite3",
+            "snapshot-20250108-080000.000.sqlite3",
+            "snapshot-20250101-080000.000.sqlite3",
+            "snapshot-garbage.sqlite3",
+            "notes.txt",
+        ];
+        for name in names {
+            std::fs::write(tmp.path().join(name), b"").unwrap();
+        }
+        let mut removed: Vec<String> = rotate(&backup_config(tmp.path()))
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        removed.sort();
+        assert_eq!(
+            removed,
+            ["snapshot-20250101-080000.000.sqlite3", "snapshot-20250108-080000.000.sqlite3", "snapshot-20250110-100000.250.sqlite3"]
+        );
+        let mut left: Vec<String> =
+            std::fs::read_dir(tmp.path()).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        left.sort();
+        assert_eq!(left.len(), names.len() - 3);
+        assert!(left.contains(&"snapshot-garbage.sqlite3".to_string()));
+    }
+
+    async fn put(ds: &crate::DataStore, title: &str) {
+        let tiddler = Tiddler::from_value(json!({ "title": title, "text": "x" })).unwrap();
+        ds.write(move |t| t.put(tiddler)).await.unwrap();
+    }
+
+    async fn exists(ds: &crate::DataStore, title: &str) -> bool {
+        let title = title.to_string();
+        ds.read(move |t| t.get(&title)).await.unwrap().is_some()
+    }
+
+    #[tokio::test]
+    async fn snapshot_and_restore() {
+        let tmp = testutil::TempDir::new("restore");
+        let server = testutil::server_config(tmp.path());
+        let cfg = backup_config(&tmp.path().join("backups"));
+        let ds = testutil::datastore(&server);
+        put(&ds, "Before").await;
+        let first = create_snapshot(&server, &cfg).unwrap();
+        let second = create_snapshot(&server, &cfg).unwrap();
+        assert_ne!(first, second);
+        put(&ds, "After").await;
+        drop(ds);
+
+        restore(&server, &first).unwrap();
+        assert!(db::sibling_path(&server.db_path, "pre-restore").exists());
+        let ds = testutil::datastore(&server);
+        assert!(exists(&ds, "Before").await);
+        assert!(!exists(&ds, "After").await);
+    }
+
+    #[test]
+    fn restore_rejects_missing_and_foreign_files() {
+        let tmp = testutil::TempDir::new("restore-bad");
+        let server = testutil::server_config(tmp.path());
+        let missing = tmp.path().join("typo.sqlite3");
+        assert!(restore(&server, &missing).is_err());
+        assert!(!missing.exists());
+        assert!(!server.db_path.exists());
+
+        let other = tmp.path().join("other.sqlite3");
+        rusqlite::Connection::open(&other).unwrap().execute_batch("CREATE TABLE x (y)").unwrap();
+        assert!(restore(&server, &other).is_err());
+        assert!(!server.db_path.exists());
+    }
+}
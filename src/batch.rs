@@ -0,0 +1,297 @@
+//! 批量写入：`POST /api/tiddlers/batch`
+//!
+//! 请求体是一个操作数组，例如
+//! `[{"op": "put", "tiddler": {...}}, {"op": "delete", "title": "..."}]`。
+//! 所有操作先逐项校验，任何一项无效则全部不执行 (422)；校验通过后在一个 SQLite
+//! 事务中执行，要么全部生效，要么全部回滚。
+
+use crate::{
+    AppError, AppResult, AppState, DataStore, ServerConfig, StagedFiles, Tiddler, try_delete_associated_file,
+};
+use axum::{Extension, http::StatusCode, response::IntoResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum BatchOp {
+    Put { tiddler: Value },
+    Delete { title: String },
+}
+
+/// 校验通过、等待执行的操作
+enum Planned {
+    Put(Tiddler),
+    Delete(String),
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct ItemResult {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    op: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revision: Option<u64>,
+    // 删除时条目不存在
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    missing: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct BatchResponse {
+    applied: bool,
+    results: Vec<ItemResult>,
+}
+
+fn plan(index: usize, raw: Value) -> (ItemResult, Option<Planned>) {
+    let mut result = ItemResult { index, ..Default::default() };
+    let op = match serde_json::from_value::<BatchOp>(raw) {
+        Ok(op) => op,
+        Err(e) => {
+            result.error = Some(format!("invalid operation: {}", e));
+            return (result, None);
+        }
+    };
+    match op {
+        BatchOp::Put { tiddler } => {
+            result.op = Some("put");
+            match Tiddler::from_value(tiddler) {
+                Ok(t) => {
+                    result.title = Some(t.title.clone());
+                    (result, Some(Planned::Put(t)))
+                }
+                Err(AppError::Serialization(e) | AppError::Database(e) | AppError::Response(e)) => {
+                    result.error = Some(e);
+                    (result, None)
+                }
+            }
+        }
+        BatchOp::Delete { title } => {
+            result.op = Some("delete");
+            result.title = Some(title.clone());
+            (result, Some(Planned::Delete(title)))
+        }
+    }
+}
+
+// --- Handler: POST /api/tiddlers/batch ---
+pub(crate) async fn apply_batch(
+    Extension(ds): Extension<DataStore>,
+    Extension(config): Extension<ServerConfig>,
+    Extension(state): Extension<Arc<AppState>>,
+    axum::Json(ops): axum::Json<Vec<Value>>,
+) -> AppResult<axum::response::Response> {
+    let (mut results, planned): (Vec<ItemResult>, Vec<Option<Planned>>) =
+        ops.into_iter().enumerate().map(|(idx, raw)| plan(idx, raw)).unzip();
+    if results.iter().any(|r| r.error.is_some()) {
+        let body = BatchResponse { applied: false, results };
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, axum::Json(body)).into_response());
+    }
+
+    // 与 put_tiddler 相同：二进制内容先落盘，条目中只保留引用；事务回滚时还原这些文件
+    let mut planned: Vec<Planned> = planned.into_iter().flatten().collect();
+    let mut staged = StagedFiles::default();
+    for op in planned.iter_mut() {
+        if let Planned::Put(t) = op {
+            staged.offload(&config, &state, &t.title, &mut t.meta).await;
+        }
+    }
+
+    let count = planned.len();
+    let outcomes = ds
+        .write(move |tiddlers| {
+            tiddlers.transaction(|tiddlers| {
+                let mut outcomes = Vec::with_capacity(planned.len());
+                for op in planned {
+                    match op {
+                        Planned::Put(mut t) => {
+                            if tiddlers.get(&t.title)?.is_some() {
+                                t.revision += 1;
+                            }
+                            let revision = t.revision;
+                            tiddlers.put(t)?;
+                            outcomes.push((Some(revision), None));
+                        }
+                        Planned::Delete(title) => {
+                            let deleted = tiddlers.pop(&title)?;
+                            outcomes.push((deleted.as_ref().map(|t| t.revision), deleted));
+                        }
+                    }
+                }
+                // 先删除再写入同名二进制条目时，新条目用的是同一个文件，不能删
+                outcomes
+                    .into_iter()
+                    .map(|(revision, deleted)| {
+                        let Some(old) = deleted else { return Ok((revision, None)) };
+                        let uri = old.field("_canonical_uri");
+                        let reused = uri.is_some()
+                            && tiddlers.get(&old.title)?.is_some_and(|t| t.field("_canonical_uri") == uri);
+                        Ok((revision, Some((old, !reused))))
+                    })
+                    .collect::<AppResult<Vec<_>>>()
+            })
+        })
+        .await;
+    staged.finish(outcomes.is_ok()).await;
+
+    for (result, (revision, deleted)) in results.iter_mut().zip(outcomes?) {
+        result.revision = revision;
+        result.missing = result.op == Some("delete") && deleted.is_none();
+        if let Some((tiddler, true)) = deleted {
+            let (state, config) = (state.clone(), config.clone());
+            tokio::spawn(async move {
+                try_delete_associated_file(tiddler, state, config).await;
+            });
+        }
+    }
+    tracing::info!("Applied batch of {} operation(s)", count);
+    Ok(axum::Json(BatchResponse { applied: true, results }).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use axum::body::to_bytes;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    struct Fixture {
+        _tmp: testutil::TempDir,
+        ds: DataStore,
+        config: ServerConfig,
+        state: Arc<AppState>,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let tmp = testutil::TempDir::new(name);
+            let config = testutil::server_config(tmp.path());
+            let ds = testutil::datastore(&config);
+            Self { _tmp: tmp, ds, config, state: Arc::new(testutil::app_state()) }
+        }
+
+        async fn apply(&self, ops: Value) -> (StatusCode, Value) {
+            let ops = serde_json::from_value(ops).unwrap();
+            let resp = apply_batch(
+                Extension(self.ds.clone()),
+                Extension(self.config.clone()),
+                Extension(self.state.clone()),
+                axum::Json(ops),
+            )
+            .await
+            .unwrap();
+            let status = resp.status();
+            let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice(&body).unwrap())
+        }
+
+        async fn try_apply(&self, ops: Value) -> bool {
+            let ops = serde_json::from_value(ops).unwrap();
+            apply_batch(
+                Extension(self.ds.clone()),
+                Extension(self.config.clone()),
+                Extension(self.state.clone()),
+                axum::Json(ops),
+            )
+            .await
+            .is_ok()
+        }
+
+        async fn get(&self, title: &str) -> Option<Tiddler> {
+            let title = title.to_string();
+            self.ds.read(move |t| t.get(&title)).await.unwrap()
+        }
+
+        fn file(&self, title: &str) -> PathBuf {
+            self.config.files_dir.join(crate::offload_file_name(title, "image/png"))
+        }
+    }
+
+    fn put(title: &str, text: &str) -> Value {
+        json!({"op": "put", "tiddler": {"title": title, "text": text}})
+    }
+
+    fn put_png(title: &str, data: &[u8]) -> Value {
+        use base64::{Engine, engine::general_purpose};
+        let text = general_purpose::STANDARD.encode(data);
+        json!({"op": "put", "tiddler": {"title": title, "type": "image/png", "text": text}})
+    }
+
+    #[tokio::test]
+    async fn invalid_items_reject_the_whole_batch() {
+        let fx = Fixture::new("batch-invalid");
+        let (status, body) =
+            fx.apply(json!([put("A", "a"), {"op": "move", "title": "B"}, {"op": "put", "tiddler": {"text": "x"}}])).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["applied"], false);
+        let errors: Vec<bool> = body["results"].as_array().unwrap().iter().map(|r| r.get("error").is_some()).collect();
+        assert_eq!(errors, [false, true, true]);
+        assert!(fx.get("A").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn puts_and_deletes() {
+        let fx = Fixture::new("batch-apply");
+        assert_eq!(fx.apply(json!([put("A", "a"), put("B", "b")])).await.0, StatusCode::OK);
+        let (status, body) = fx.apply(json!([put("A", "a2"), {"op": "delete", "title": "B"}, {"op": "delete", "title": "C"}])).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["applied"], true);
+        assert_eq!(body["results"][0]["revision"], 1);
+        assert_eq!(body["results"][1]["revision"], 0);
+        assert!(body["results"][1].get("missing").is_none());
+        assert_eq!(body["results"][2]["missing"], true);
+        assert_eq!(fx.get("A").await.unwrap().field("text"), Some("a2"));
+        assert!(fx.get("B").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn rollback_restores_files() {
+        let fx = Fixture::new("batch-rollback");
+        assert_eq!(fx.apply(json!([put_png("Logo.png", b"old")])).await.0, StatusCode::OK);
+        assert_eq!(std::fs::read(fx.file("Logo.png")).unwrap(), b"old");
+        fx.ds
+            .write(|t| {
+                t.cxn
+                    .execute_batch(
+                        "CREATE TRIGGER boom BEFORE INSERT ON tiddlers WHEN NEW.title = 'Boom'
+                         BEGIN SELECT RAISE(ABORT, 'boom'); END",
+                    )
+                    .map_err(|e| AppError::Database(e.to_string()))
+            })
+            .await
+            .unwrap();
+
+        assert!(!fx.try_apply(json!([put_png("Logo.png", b"new"), put_png("New.png", b"new"), put("Boom", "x")])).await);
+        assert_eq!(std::fs::read(fx.file("Logo.png")).unwrap(), b"old");
+        assert!(!fx.file("New.png").exists());
+        assert_eq!(fx.get("Logo.png").await.unwrap().revision, 0);
+        assert!(fx.get("New.png").await.is_none());
+        let leftovers = std::fs::read_dir(&fx.config.files_dir).unwrap().count();
+        assert_eq!(leftovers, 1);
+    }
+
+    #[tokio::test]
+    async fn delete_then_put_keeps_the_new_file() {
+        let fx = Fixture::new("batch-mixed");
+        assert_eq!(fx.apply(json!([put_png("Logo.png", b"old")])).await.0, StatusCode::OK);
+        let (status, _) = fx.apply(json!([{"op": "delete", "title": "Logo.png"}, put_png("Logo.png", b"new")])).await;
+        assert_eq!(status, StatusCode::OK);
+        // 删除旧条目时的文件清理在后台执行
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(std::fs::read(fx.file("Logo.png")).unwrap(), b"new");
+        assert!(fx.get("Logo.png").await.is_some());
+
+        // 写入后再删除：文件随条目一起删除
+        let (status, _) = fx.apply(json!([put_png("Tmp.png", b"tmp"), {"op": "delete", "title": "Tmp.png"}])).await;
+        assert_eq!(status, StatusCode::OK);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!fx.file("Tmp.png").exists());
+        assert!(fx.get("Tmp.png").await.is_none());
+    }
+}
@@ -0,0 +1,285 @@
+//! 预渲染 Wiki 页面的内存缓存
+//!
+//! 每个 tiddler 序列化后的 JSON 片段按标题缓存，写入 / 删除时只更新对应片段；
+//! 拼接好的整页 HTML 及其 gzip / br / zstd 压缩版本在第一次请求时生成并缓存，
+//! 直到下一次修改为止。页面使用内容哈希作为强 ETag。
+
+use crate::{AppError, AppResult, LazyLoad, Tiddler, WikiTemplate, store::Change};
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+/// 客户端可接受的内容编码
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+    /// 从 `Accept-Encoding` 中挑选压缩率最好的编码 (br > zstd > gzip)
+    pub(crate) fn negotiate(accept: &str) -> Self {
+        let accepted = |name: &str| {
+            accept.split(',').any(|part| {
+                let mut pieces = part.trim().split(';');
+                let coding = pieces.next().unwrap_or("").trim();
+                let q_zero = pieces.any(|p| matches!(p.trim(), "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+                coding.eq_ignore_ascii_case(name) && !q_zero
+            })
+        };
+        if accepted("br") {
+            Encoding::Brotli
+        } else if accepted("zstd") {
+            Encoding::Zstd
+        } else if accepted("gzip") {
+            Encoding::Gzip
+        } else {
+            Encoding::Identity
+        }
+    }
+
+    pub(crate) fn header_value(&self) -> Option<&'static str> {
+        match self {
+            Encoding::Identity => None,
+            Encoding::Gzip => Some("gzip"),
+            Encoding::Brotli => Some("br"),
+            Encoding::Zstd => Some("zstd"),
+        }
+    }
+}
+
+/// 一份完整的、可直接返回的 Wiki 页面
+pub(crate) struct RenderedPage {
+    pub(crate) etag: String,
+    body: Bytes,
+    gzip: OnceLock<Bytes>,
+    br: OnceLock<Bytes>,
+    zstd: OnceLock<Bytes>,
+}
+
+impl RenderedPage {
+    pub(crate) fn new(body: Vec<u8>) -> Self {
+        let etag = format!("\"{}\"", &hex::encode(Sha256::digest(&body))[..32]);
+        Self {
+            etag,
+            body: Bytes::from(body),
+            gzip: OnceLock::new(),
+            br: OnceLock::new(),
+            zstd: OnceLock::new(),
+        }
+    }
+
+    /// 返回指定编码的页面内容；压缩版本在第一次使用时生成 (阻塞操作)
+    pub(crate) fn encoded(&self, encoding: Encoding) -> Bytes {
+        let compress = |f: fn(&[u8]) -> std::io::Result<Vec<u8>>| match f(&self.body) {
+            Ok(v) => Bytes::from(v),
+            Err(e) => {
+                tracing::error!("Failed to precompress wiki page: {}", e);
+                self.body.clone()
+            }
+        };
+        match encoding {
+            Encoding::Identity => self.body.clone(),
+            Encoding::Gzip => self.gzip.get_or_init(|| compress(gzip)).clone(),
+            Encoding::Brotli => self.br.get_or_init(|| compress(brotli)).clone(),
+            Encoding::Zstd => self.zstd.get_or_init(|| compress(zstd)).clone(),
+        }
+    }
+}
+
+fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(6));
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+fn brotli(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    {
+        let mut writer = brotli::CompressorWriter::new(&mut out, 4096, 9, 22);
+        writer.write_all(data)?;
+    }
+    Ok(out)
+}
+
+fn zstd(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    zstd::bulk::compress(data, 9)
+}
+
+/// 把一个 tiddler 序列化为可嵌入 `<script>` 的 JSON 片段；
+/// 按懒加载模式决定是否只嵌入不含正文的 skinny tiddler
+pub(crate) fn fragment(tiddler: &Tiddler, lazy: LazyLoad) -> AppResult<String> {
+    let value = if lazy.is_skinny(tiddler) { tiddler.as_embedded_skinny_value() } else { tiddler.as_value() };
+    let json = serde_json::to_string(&value)
+        .map_err(|e| AppError::Serialization(format!("error serializing tiddler: {}", e)))?;
+    Ok(json.replace("</script>", "<\\/script>"))
+}
+
+/// 拼接模板与片段，得到完整页面
+pub(crate) fn assemble<'a>(template: &WikiTemplate, fragments: impl Iterator<Item = &'a String>) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(template.prefix.len() + template.suffix.len());
+    buffer.extend(template.prefix.as_bytes());
+    for f in fragments {
+        buffer.push(b',');
+        buffer.extend(f.as_bytes());
+    }
+    buffer.extend(template.suffix.as_bytes());
+    buffer
+}
+
+#[derive(Default)]
+struct CacheState {
+    // 每次修改都会递增，用于发现加载期间发生的并发写入
+    generation: u64,
+    fragments: Option<BTreeMap<String, String>>,
+    page: Option<Arc<RenderedPage>>,
+}
+
+pub(crate) struct WikiCache {
+    state: RwLock<CacheState>,
+    lazy: LazyLoad,
+}
+
+impl WikiCache {
+    pub(crate) fn new(lazy: LazyLoad) -> Self {
+        Self { state: RwLock::default(), lazy }
+    }
+
+    /// 已缓存的整页
+    pub(crate) fn page(&self) -> Option<Arc<RenderedPage>> {
+        self.state.read().ok()?.page.clone()
+    }
+
+    /// 用已缓存的片段拼出整页；片段尚未加载时返回 `None`
+    pub(crate) fn assemble_page(&self, template: &WikiTemplate) -> Option<Arc<RenderedPage>> {
+        let (generation, page) = {
+            let state = self.state.read().ok()?;
+            let fragments = state.fragments.as_ref()?;
+            (state.generation, Arc::new(RenderedPage::new(assemble(template, fragments.values()))))
+        };
+        self.install(generation, None, page.clone());
+        Some(page)
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.state.read().map(|s| s.generation).unwrap_or_default()
+    }
+
+    /// 保存从数据库完整加载的片段和页面；如果加载期间有写入 (generation 变化)，则放弃
+    pub(crate) fn install(&self, generation: u64, fragments: Option<BTreeMap<String, String>>, page: Arc<RenderedPage>) {
+        if let Ok(mut state) = self.state.write()
+            && state.generation == generation
+        {
+            if fragments.is_some() {
+                state.fragments = fragments;
+            }
+            state.page = Some(page);
+        }
+    }
+
+    /// 根据写入记录增量更新片段
+    pub(crate) fn apply(&self, changes: &[Change]) {
+        if changes.is_empty() {
+            return;
+        }
+        let mut state = match self.state.write() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+        state.generation += 1;
+        state.page = None;
+        let mut fragments = match state.fragments.take() {
+            Some(f) => f,
+            None => return,
+        };
+        for change in changes {
+            match change {
+                Change::Put(t) | Change::Inbox(t) => match fragment(t, self.lazy) {
+                    Ok(f) => {
+                        fragments.insert(t.title.clone(), f);
+                    }
+                    Err(e) => {
+                        // 无法增量更新时丢弃所有片段，下次请求重新加载
+                        tracing::error!("Dropping wiki cache: {:?}", e);
+                        return;
+                    }
+                },
+                Change::Delete(t) => {
+                    fragments.remove(&t.title);
+                }
+            }
+        }
+        state.fragments = Some(fragments);
+    }
+
+    /// 只丢弃整页 (例如模板被替换)，片段仍然有效
+    pub(crate) fn invalidate_page(&self) {
+        if let Ok(mut state) = self.state.write() {
+            state.generation += 1;
+            state.page = None;
+        }
+    }
+
+    /// 丢弃所有缓存，下次请求时从数据库重新加载
+    pub(crate) fn invalidate(&self) {
+        if let Ok(mut state) = self.state.write() {
+            state.generation += 1;
+            state.page = None;
+            state.fragments = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn negotiate_prefers_best_compression() {
+        assert_eq!(Encoding::negotiate("gzip, deflate, br, zstd"), Encoding::Brotli);
+        assert_eq!(Encoding::negotiate("gzip, zstd"), Encoding::Zstd);
+        assert_eq!(Encoding::negotiate("deflate, gzip;q=0.5"), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate("BR"), Encoding::Brotli);
+    }
+
+    #[test]
+    fn negotiate_respects_refusals() {
+        assert_eq!(Encoding::negotiate("br;q=0, gzip"), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate("br ; q=0.0, zstd;q=0.000, gzip;q=0.001"), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate("gzip;q=0"), Encoding::Identity);
+    }
+
+    #[test]
+    fn negotiate_without_known_codings() {
+        for accept in ["", "identity", "deflate", "compress, x-gzip", "brotli", "gzipx"] {
+            assert_eq!(Encoding::negotiate(accept), Encoding::Identity, "{:?}", accept);
+        }
+        assert_eq!(Encoding::Identity.header_value(), None);
+        assert_eq!(Encoding::Zstd.header_value(), Some("zstd"));
+    }
+
+    #[test]
+    fn encoded_pages_decompress_to_the_body() {
+        let body = "<html>".repeat(1000).into_bytes();
+        let page = RenderedPage::new(body.clone());
+        assert_eq!(page.etag.len(), 34);
+        assert_eq!(page.encoded(Encoding::Identity), body);
+
+        let mut gunzipped = Vec::new();
+        flate2::read::GzDecoder::new(&page.encoded(Encoding::Gzip)[..]).read_to_end(&mut gunzipped).unwrap();
+        assert_eq!(gunzipped, body);
+        let mut unbrotli = Vec::new();
+        brotli::Decompressor::new(&page.encoded(Encoding::Brotli)[..], 4096).read_to_end(&mut unbrotli).unwrap();
+        assert_eq!(unbrotli, body);
+        assert_eq!(zstd::decode_all(&page.encoded(Encoding::Zstd)[..]).unwrap(), body);
+        // 压缩结果被缓存
+        assert_eq!(page.encoded(Encoding::Brotli).as_ptr(), page.encoded(Encoding::Brotli).as_ptr());
+    }
+}
@@ -0,0 +1,83 @@
+//! 增量同步：按修改序号返回自某个游标以来新增 / 修改 / 删除的 tiddler
+//!
+//! 每次写入或删除都会在 `changes` 表中为该标题分配一个新的、单调递增的序号
+//! (删除保留为墓碑)。客户端保存返回的 `cursor`，下次请求 `GET /api/changes?since=<cursor>`
+//! 即可只拿到这期间的变化，而不必每次都下载全部 skinny tiddler。
+
+use crate::{AppError, AppResult, DataStore, Tiddlers};
+use axum::{Extension, extract};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub(crate) struct ChangesQuery {
+    #[serde(default)]
+    since: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct ChangedTiddler {
+    title: String,
+    revision: Option<u64>,
+    timestamp: String,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct ChangeSet {
+    modified: Vec<ChangedTiddler>,
+    deleted: Vec<ChangedTiddler>,
+    // 下次请求时作为 since 传回
+    cursor: i64,
+}
+
+impl Tiddlers {
+    /// 为 `title` 分配新的修改序号 (在写连接上与写入本身一起执行)
+    pub(crate) fn record_change(&self, title: &str, revision: Option<u64>, deleted: bool) -> AppResult<()> {
+        const RECORD: &str = r#"
+            INSERT OR REPLACE INTO changes (title, revision, deleted) VALUES (:title, :revision, :deleted)
+        "#;
+        let mut stmt = self
+            .cxn
+            .prepare_cached(RECORD)
+            .map_err(|e| AppError::Database(format!("Error preparing statement: {}", e)))?;
+        stmt.execute(rusqlite::named_params! {
+            ":title": title,
+            ":revision": revision,
+            ":deleted": deleted,
+        })
+        .map_err(|e| AppError::Database(format!("Error recording change of '{}': {}", title, e)))?;
+        Ok(())
+    }
+
+    pub(crate) fn changes_since(&self, since: i64) -> AppResult<ChangeSet> {
+        const GET: &str = r#"
+            SELECT seq, title, revision, deleted, timestamp FROM changes WHERE seq > ? ORDER BY seq
+        "#;
+        let mut stmt = self.cxn.prepare_cached(GET).map_err(AppError::from)?;
+        let mut rows = stmt.query([since]).map_err(AppError::from)?;
+        let mut set = ChangeSet { modified: Vec::new(), deleted: Vec::new(), cursor: since.max(0) };
+        while let Some(row) = rows.next().map_err(AppError::from)? {
+            let seq: i64 = row.get(0).map_err(AppError::from)?;
+            let change = ChangedTiddler {
+                title: row.get(1).map_err(AppError::from)?,
+                revision: row.get(2).map_err(AppError::from)?,
+                timestamp: row.get(4).map_err(AppError::from)?,
+            };
+            if row.get::<_, bool>(3).map_err(AppError::from)? {
+                set.deleted.push(change);
+            } else {
+                set.modified.push(change);
+            }
+            set.cursor = set.cursor.max(seq);
+        }
+        Ok(set)
+    }
+}
+
+// --- Handler: GET /api/changes?since=N ---
+pub(crate) async fn changes_since(
+    Extension(ds): Extension<DataStore>,
+    extract::Query(query): extract::Query<ChangesQuery>,
+) -> AppResult<axum::Json<ChangeSet>> {
+    let set = ds.read(move |tiddlers| tiddlers.changes_since(query.since)).await?;
+    Ok(axum::Json(set))
+}
@@ -0,0 +1,246 @@
+//! 文件加密 (Envelope Encryption)
+//!
+//! 服务器落盘的二进制文件使用信封加密：每个文件随机生成一个数据密钥 (DEK)，
+//! 用它以 AES-256-GCM 加密文件内容，再用配置中的主密钥 (KEK) 包裹 DEK，
+//! 一并写在文件头中。更换主密钥时只需重新包裹 DEK，无需重写整个文件。
+//!
+//! 文件布局：
+//!
+//! ```text
+//! MAGIC(6) | kek_nonce(12) | wrapped_dek(48) | data_nonce(12) | ciphertext
+//! ```
+
+use aes_gcm::{
+    Aes256Gcm, Key, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, rand_core::RngCore},
+};
+use hmac::{Hmac, Mac};
+use base64::{Engine as _, engine::general_purpose};
+use md5::{Digest, Md5};
+use std::path::Path;
+
+/// 加密文件的魔数，用来区分旧的明文文件
+pub(crate) const MAGIC: &[u8; 6] = b"TWENC\x01";
+
+const NONCE_LEN: usize = 12;
+const WRAPPED_KEY_LEN: usize = 32 + 16;
+const HEADER_LEN: usize = MAGIC.len() + NONCE_LEN + WRAPPED_KEY_LEN + NONCE_LEN;
+
+/// 从配置的字符串或密钥文件中解析出 32 字节的密钥。
+///
+/// 支持 base64 或 hex 编码；密钥文件也可以直接存放 32 字节原始数据。
+pub(crate) fn load_key(inline: Option<&str>, key_file: Option<&Path>) -> Result<[u8; 32], String> {
+    let raw = match (inline, key_file) {
+        (Some(k), _) => k.trim().as_bytes().to_vec(),
+        (None, Some(path)) => std::fs::read(path)
+            .map_err(|e| format!("Failed to read key file {:?}: {}", path, e))?,
+        (None, None) => return Err("either `key` or `key_file` must be set".to_string()),
+    };
+
+    if raw.len() == 32 && inline.is_none() {
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&raw);
+        return Ok(key);
+    }
+
+    let text = String::from_utf8(raw).map_err(|_| "key is not valid UTF-8 text".to_string())?;
+    let text = text.trim();
+    // 64 位 hex 同时也是合法的 base64 (解码为 48 字节)，必须先按 hex 解析
+    let decoded = if text.len() == 64 && text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        hex::decode(text).map_err(|_| "key must be base64 or hex encoded".to_string())?
+    } else {
+        general_purpose::STANDARD
+            .decode(text)
+            .map_err(|_| "key must be base64 or hex encoded".to_string())?
+    };
+
+    <[u8; 32]>::try_from(decoded.as_slice())
+        .map_err(|_| format!("key must be 32 bytes (got {})", decoded.len()))
+}
+
+/// 本地文件的信封加密器
+pub(crate) struct FileCipher {
+    kek: Aes256Gcm,
+}
+
+impl FileCipher {
+    pub(crate) fn new(key: &[u8; 32]) -> Self {
+        Self { kek: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)) }
+    }
+
+    /// 判断一段数据是否是由 [`FileCipher::seal`] 生成的
+    pub(crate) fn is_sealed(data: &[u8]) -> bool {
+        data.len() >= HEADER_LEN && data.starts_with(MAGIC)
+    }
+
+    /// 加密文件解密后的长度
+    pub(crate) fn plaintext_len(sealed_len: usize) -> usize {
+        sealed_len.saturating_sub(HEADER_LEN + 16)
+    }
+
+    pub(crate) fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        let dek = Aes256Gcm::generate_key(OsRng);
+        let kek_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let wrapped = self
+            .kek
+            .encrypt(&kek_nonce, dek.as_slice())
+            .map_err(|_| "failed to wrap data key".to_string())?;
+
+        let data_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = Aes256Gcm::new(&dek)
+            .encrypt(&data_nonce, plaintext)
+            .map_err(|_| "failed to encrypt file".to_string())?;
+
+        let mut out = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&kek_nonce);
+        out.extend_from_slice(&wrapped);
+        out.extend_from_slice(&data_nonce);
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    pub(crate) fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, String> {
+        if !Self::is_sealed(sealed) {
+            return Err("data is not an encrypted file".to_string());
+        }
+        let (kek_nonce, rest) = sealed[MAGIC.len()..].split_at(NONCE_LEN);
+        let (wrapped, rest) = rest.split_at(WRAPPED_KEY_LEN);
+        let (data_nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let dek = self
+            .kek
+            .decrypt(Nonce::from_slice(kek_nonce), wrapped)
+            .map_err(|_| "failed to unwrap data key (wrong master key?)".to_string())?;
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&dek))
+            .decrypt(Nonce::from_slice(data_nonce), ciphertext)
+            .map_err(|_| "file is corrupted or has been tampered with".to_string())
+    }
+}
+
+/// S3 SSE-C 所需的客户密钥及其 MD5 (均为 base64)
+#[derive(Clone)]
+pub(crate) struct SseCustomerKey {
+    pub(crate) key_b64: String,
+    pub(crate) key_md5_b64: String,
+}
+
+impl SseCustomerKey {
+    pub(crate) const ALGORITHM: &'static str = "AES256";
+
+    pub(crate) fn new(key: &[u8; 32]) -> Self {
+        Self {
+            key_b64: general_purpose::STANDARD.encode(key),
+            key_md5_b64: general_purpose::STANDARD.encode(Md5::digest(key)),
+        }
+    }
+}
+
+/// 随机生成 `len` 字节，以 hex 编码返回
+pub(crate) fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+fn hmac_sha256(key: &[u8]) -> Hmac<sha2::Sha256> {
+    // HMAC 接受任意长度的密钥
+    <Hmac<sha2::Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length")
+}
+
+/// HMAC-SHA256 签名
+pub(crate) fn sign(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = hmac_sha256(key);
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// 以常量时间校验 HMAC-SHA256 签名
+pub(crate) fn verify(key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let mut mac = hmac_sha256(key);
+    mac.update(message);
+    mac.verify_slice(signature).is_ok()
+}
+
+/// 以常量时间比较两个字节串 (用于比较令牌)
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn load_key_encodings() {
+        let hex_key = hex::encode(KEY);
+        let b64_key = general_purpose::STANDARD.encode(KEY);
+        assert_eq!(load_key(Some(&hex_key), None).unwrap(), KEY);
+        assert_eq!(load_key(Some(&hex_key.to_uppercase()), None).unwrap(), KEY);
+        assert_eq!(load_key(Some(&format!("  {}\n", b64_key)), None).unwrap(), KEY);
+    }
+
+    #[test]
+    fn load_key_errors() {
+        assert!(load_key(None, None).is_err());
+        assert!(load_key(Some("not a key!"), None).unwrap_err().contains("base64 or hex"));
+        let short = general_purpose::STANDARD.encode([1u8; 16]);
+        assert_eq!(load_key(Some(&short), None).unwrap_err(), "key must be 32 bytes (got 16)");
+        // 内联的 32 个字符不是原始密钥
+        assert!(load_key(Some(&"a".repeat(32)), None).is_err());
+    }
+
+    #[test]
+    fn load_key_files() {
+        let dir = std::env::temp_dir().join(format!("tw-key-{}", random_hex(8)));
+        std::fs::create_dir_all(&dir).unwrap();
+        let raw = dir.join("raw");
+        std::fs::write(&raw, KEY).unwrap();
+        assert_eq!(load_key(None, Some(&raw)).unwrap(), KEY);
+        let text = dir.join("hex");
+        std::fs::write(&text, format!("{}\n", hex::encode(KEY))).unwrap();
+        assert_eq!(load_key(None, Some(&text)).unwrap(), KEY);
+        // 同时设置时以内联密钥为准
+        let inline = hex::encode([9u8; 32]);
+        assert_eq!(load_key(Some(&inline), Some(&raw)).unwrap(), [9u8; 32]);
+        assert!(load_key(None, Some(&dir.join("missing"))).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn seal_and_open() {
+        let cipher = FileCipher::new(&KEY);
+        for plaintext in [&b""[..], b"hello", &[0u8; 100_000]] {
+            let sealed = cipher.seal(plaintext).unwrap();
+            assert!(FileCipher::is_sealed(&sealed));
+            assert_eq!(FileCipher::plaintext_len(sealed.len()), plaintext.len());
+            assert_eq!(cipher.open(&sealed).unwrap(), plaintext);
+        }
+        // 每次加密使用新的数据密钥和 nonce
+        assert_ne!(cipher.seal(b"same").unwrap(), cipher.seal(b"same").unwrap());
+    }
+
+    #[test]
+    fn open_rejects_bad_input() {
+        let cipher = FileCipher::new(&KEY);
+        let mut sealed = cipher.seal(b"secret").unwrap();
+        assert!(FileCipher::new(&[8; 32]).open(&sealed).unwrap_err().contains("wrong master key"));
+        assert!(cipher.open(b"plain file contents that are long enough to look like a header............").is_err());
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(cipher.open(&sealed).unwrap_err().contains("tampered"));
+    }
+
+    #[test]
+    fn signatures() {
+        let signature = sign(b"k", b"message");
+        assert!(verify(b"k", b"message", &signature));
+        assert!(!verify(b"k", b"other", &signature));
+        assert!(!verify(b"other key", b"message", &signature));
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
@@ -0,0 +1,732 @@
+//! WebDAV 接口 (`/dav/`)：把 Wiki 挂载为网络驱动器
+//!
+//! 每个条目显示为根目录下的一个 `.tid` 文件 (含多行字段或插件时为 `.json`，与 `export-folder` 一致)，
+//! 本地转存的文件显示在只读的 `files/` 目录中。读写直接映射到 `Tiddlers::get` / `put` / `pop`：
+//! 写入时 revision 加一，删除条目时一并删除关联文件。
+//!
+//! 编辑器保存时产生的临时文件 (`.swp`、`~` 备份、`.DS_Store` 等) 只保存在内存中，
+//! 这样 "写临时文件再 `MOVE` 成 `.tid`" 的保存方式也能正常工作。
+
+use crate::{
+    AppError, AppResult, AppState, DataStore, ServerConfig, Tiddler,
+    crypto::{self, FileCipher},
+    folder, offload_binary, render,
+    rename::{self, MovedFile},
+    try_delete_associated_file,
+};
+use axum::{
+    Extension,
+    body::Bytes,
+    extract::Request,
+    http::{HeaderMap, Method, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+const PREFIX: &str = "/dav/";
+const ALLOW: &str = "OPTIONS, PROPFIND, GET, HEAD, PUT, DELETE, MOVE, LOCK, UNLOCK";
+
+// 上传 .tid 时保留的服务器字段 (导出时被去掉了)，前提是 `_canonical_uri` 没有变
+const KEPT_FIELDS: &[&str] = &["_file_storage", "_s3_key", "_s3_bucket"];
+
+// 内存中临时文件的数量和总大小上限
+const MAX_SCRATCH_FILES: usize = 64;
+const MAX_SCRATCH_BYTES: usize = 512 * 1024 * 1024;
+
+/// 编辑器的临时文件，只保存在内存中
+#[derive(Default)]
+pub(crate) struct Scratch(Mutex<HashMap<String, (Bytes, DateTime<Utc>)>>);
+
+impl Scratch {
+    fn get(&self, name: &str) -> Option<(Bytes, DateTime<Utc>)> {
+        self.0.lock().unwrap().get(name).cloned()
+    }
+
+    /// 返回是否为新建；超出数量或总大小上限时不保存，返回 `None`
+    fn insert(&self, name: &str, data: Bytes) -> Option<bool> {
+        let mut files = self.0.lock().unwrap();
+        let others = files.iter().filter(|(n, _)| n.as_str() != name);
+        let (count, size) = others.fold((0, 0), |(count, size), (_, (d, _))| (count + 1, size + d.len()));
+        if count >= MAX_SCRATCH_FILES || size + data.len() > MAX_SCRATCH_BYTES {
+            return None;
+        }
+        Some(files.insert(name.to_string(), (data, Utc::now())).is_none())
+    }
+
+    fn remove(&self, name: &str) -> Option<Bytes> {
+        self.0.lock().unwrap().remove(name).map(|(data, _)| data)
+    }
+
+    fn list(&self) -> Vec<(String, usize, DateTime<Utc>)> {
+        self.0.lock().unwrap().iter().map(|(name, (data, time))| (name.clone(), data.len(), *time)).collect()
+    }
+}
+
+/// 路径对应的资源
+enum Node {
+    Root,
+    Tiddler { title: String, json: bool },
+    Scratch(String),
+    Files,
+    File(String),
+}
+
+impl Node {
+    fn resolve(path: &str) -> Option<Node> {
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            return Some(Node::Root);
+        }
+        if path == "files" {
+            return Some(Node::Files);
+        }
+        if let Some(name) = path.strip_prefix("files/") {
+            if name.contains('/') || name.contains('\\') || name.contains("..") {
+                return None;
+            }
+            return Some(Node::File(name.to_string()));
+        }
+        if path.contains('/') {
+            return None;
+        }
+        if let Some(stem) = path.strip_suffix(".tid") {
+            return Some(Node::Tiddler { title: decode_name(stem)?, json: false });
+        }
+        if let Some(stem) = path.strip_suffix(".json") {
+            return Some(Node::Tiddler { title: decode_name(stem)?, json: true });
+        }
+        Some(Node::Scratch(path.to_string()))
+    }
+}
+
+fn decode_name(stem: &str) -> Option<String> {
+    urlencoding::decode(stem).ok().map(|s| s.into_owned()).filter(|s| !s.is_empty())
+}
+
+/// 解析上传的 `.tid` / `.json` 内容
+fn parse_upload(body: &[u8], json: bool) -> Option<Map<String, Value>> {
+    // 有些客户端先创建空文件再写入内容
+    if body.is_empty() {
+        return Some(Map::new());
+    }
+    if json {
+        return match serde_json::from_slice(body).ok()? {
+            Value::Object(map) => Some(map),
+            Value::Array(items) => match items.into_iter().next()? {
+                Value::Object(map) => Some(map),
+                _ => None,
+            },
+            _ => None,
+        };
+    }
+    let content = std::str::from_utf8(body).ok()?;
+    let (mut fields, text) = folder::parse_tid(content);
+    fields.insert("text".to_string(), Value::String(text.unwrap_or_default()));
+    Some(fields)
+}
+
+/// TiddlyWiki 的日期 (`YYYYMMDDHHMMSSmmm`，UTC)
+fn modified(tiddler: &Tiddler) -> DateTime<Utc> {
+    tiddler
+        .field("modified")
+        .or_else(|| tiddler.field("created"))
+        .and_then(|v| NaiveDateTime::parse_from_str(v.get(..14)?, "%Y%m%d%H%M%S").ok())
+        .map(|d| d.and_utc())
+        .unwrap_or(DateTime::UNIX_EPOCH)
+}
+
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+struct Entry {
+    href: String,
+    collection: bool,
+    length: usize,
+    content_type: String,
+    modified: DateTime<Utc>,
+    etag: Option<String>,
+}
+
+impl Entry {
+    fn collection(href: &str) -> Self {
+        Self {
+            href: href.to_string(),
+            collection: true,
+            length: 0,
+            content_type: String::new(),
+            modified: DateTime::UNIX_EPOCH,
+            etag: None,
+        }
+    }
+
+    fn xml(&self) -> String {
+        use render::escape;
+        let name = self.href.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+        let name = urlencoding::decode(name).map(|s| s.into_owned()).unwrap_or_default();
+        let mut prop = format!("<D:displayname>{}</D:displayname>", escape(&name));
+        if self.collection {
+            prop.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+        } else {
+            prop.push_str("<D:resourcetype/>");
+            prop.push_str(&format!("<D:getcontentlength>{}</D:getcontentlength>", self.length));
+            prop.push_str(&format!("<D:getcontenttype>{}</D:getcontenttype>", escape(&self.content_type)));
+        }
+        prop.push_str(&format!("<D:getlastmodified>{}</D:getlastmodified>", http_date(self.modified)));
+        if let Some(etag) = &self.etag {
+            prop.push_str(&format!("<D:getetag>\"{}\"</D:getetag>", escape(etag)));
+        }
+        // macOS Finder 只有看到 supportedlock 才允许写入
+        prop.push_str(
+            "<D:supportedlock><D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+             <D:locktype><D:write/></D:locktype></D:lockentry></D:supportedlock>",
+        );
+        format!(
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop>{}</D:prop>\
+             <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+            escape(&self.href),
+            prop
+        )
+    }
+}
+
+fn tiddler_entry(tiddler: &Tiddler) -> AppResult<Entry> {
+    let json = folder::is_json(tiddler);
+    Ok(Entry {
+        href: format!("{}{}", PREFIX, urlencoding::encode(&folder::file_name(tiddler))),
+        collection: false,
+        length: folder::serialize(tiddler)?.len(),
+        content_type: if json { "application/json" } else { "application/x-tiddler" }.to_string(),
+        modified: modified(tiddler),
+        etag: Some(tiddler.revision.to_string()),
+    })
+}
+
+fn scratch_entry(name: &str, length: usize, modified: DateTime<Utc>) -> Entry {
+    Entry {
+        href: format!("{}{}", PREFIX, urlencoding::encode(name)),
+        collection: false,
+        length,
+        content_type: "application/octet-stream".to_string(),
+        modified,
+        etag: None,
+    }
+}
+
+/// `files/` 中单个文件的属性；启用加密时长度为解密后的长度
+async fn file_entry(config: &ServerConfig, state: &AppState, name: &str) -> Option<Entry> {
+    use tokio::io::AsyncReadExt;
+    let path = config.files_dir.join(name);
+    let meta = tokio::fs::metadata(&path).await.ok().filter(|m| m.is_file())?;
+    let mut length = meta.len() as usize;
+    if state.file_cipher.is_some() {
+        let mut head = [0u8; crypto::MAGIC.len()];
+        if let Ok(mut f) = tokio::fs::File::open(&path).await
+            && f.read_exact(&mut head).await.is_ok()
+            && &head == crypto::MAGIC
+        {
+            length = FileCipher::plaintext_len(length);
+        }
+    }
+    Some(Entry {
+        href: format!("{}files/{}", PREFIX, urlencoding::encode(name)),
+        collection: false,
+        length,
+        content_type: mime_guess::from_path(name).first_or_octet_stream().to_string(),
+        modified: meta.modified().map(DateTime::<Utc>::from).unwrap_or(DateTime::UNIX_EPOCH),
+        etag: None,
+    })
+}
+
+fn multistatus(entries: &[Entry]) -> Response {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n");
+    for entry in entries {
+        xml.push_str(&entry.xml());
+    }
+    xml.push_str("</D:multistatus>\n");
+    (StatusCode::MULTI_STATUS, [(header::CONTENT_TYPE, "application/xml; charset=utf-8")], xml).into_response()
+}
+
+/// 请求用到的扩展
+struct Dav {
+    ds: DataStore,
+    state: Arc<AppState>,
+    config: ServerConfig,
+    scratch: Arc<Scratch>,
+}
+
+impl Dav {
+    async fn propfind(&self, node: Node, headers: &HeaderMap) -> AppResult<Response> {
+        // Depth: 0 只返回自身；1 和 infinity 都按 1 处理
+        let shallow = headers.get("depth").and_then(|d| d.to_str().ok()) == Some("0");
+        let mut entries = Vec::new();
+        match node {
+            Node::Root => {
+                entries.push(Entry::collection(PREFIX));
+                if !shallow {
+                    let all = self.ds.read(|tiddlers| tiddlers.all()).await?;
+                    for tiddler in &all {
+                        entries.push(tiddler_entry(tiddler)?);
+                    }
+                    entries.push(Entry::collection(&format!("{}files/", PREFIX)));
+                    for (name, length, time) in self.scratch.list() {
+                        entries.push(scratch_entry(&name, length, time));
+                    }
+                }
+            }
+            Node::Tiddler { title, json } => match self.ds.read(move |tiddlers| tiddlers.get(&title)).await? {
+                Some(tiddler) if folder::is_json(&tiddler) == json => entries.push(tiddler_entry(&tiddler)?),
+                _ => return Ok(StatusCode::NOT_FOUND.into_response()),
+            },
+            Node::Scratch(name) => match self.scratch.get(&name) {
+                Some((data, time)) => entries.push(scratch_entry(&name, data.len(), time)),
+                None => return Ok(StatusCode::NOT_FOUND.into_response()),
+            },
+            Node::Files => {
+                entries.push(Entry::collection(&format!("{}files/", PREFIX)));
+                if !shallow && let Ok(mut dir) = tokio::fs::read_dir(&self.config.files_dir).await {
+                    while let Ok(Some(item)) = dir.next_entry().await {
+                        if let Some(name) = item.file_name().to_str()
+                            && let Some(entry) = file_entry(&self.config, &self.state, name).await
+                        {
+                            entries.push(entry);
+                        }
+                    }
+                }
+            }
+            Node::File(name) => match file_entry(&self.config, &self.state, &name).await {
+                Some(entry) => entries.push(entry),
+                None => return Ok(StatusCode::NOT_FOUND.into_response()),
+            },
+        }
+        Ok(multistatus(&entries))
+    }
+
+    async fn get(&self, node: Node) -> AppResult<Response> {
+        match node {
+            Node::Tiddler { title, json } => {
+                let tiddler = match self.ds.read(move |tiddlers| tiddlers.get(&title)).await? {
+                    Some(t) if folder::is_json(&t) == json => t,
+                    _ => return Ok(StatusCode::NOT_FOUND.into_response()),
+                };
+                let content_type = if json { "application/json" } else { "text/plain; charset=utf-8" };
+                let headers = [
+                    (header::CONTENT_TYPE, content_type.to_string()),
+                    (header::ETAG, format!("\"{}\"", tiddler.revision)),
+                    (header::LAST_MODIFIED, http_date(modified(&tiddler))),
+                ];
+                Ok((headers, folder::serialize(&tiddler)?).into_response())
+            }
+            Node::Scratch(name) => match self.scratch.get(&name) {
+                Some((data, _)) => Ok(([(header::CONTENT_TYPE, "application/octet-stream")], data).into_response()),
+                None => Ok(StatusCode::NOT_FOUND.into_response()),
+            },
+            Node::File(name) => {
+                let data = match tokio::fs::read(self.config.files_dir.join(&name)).await {
+                    Ok(data) => data,
+                    Err(_) => return Ok(StatusCode::NOT_FOUND.into_response()),
+                };
+                let data = match &self.state.file_cipher {
+                    Some(cipher) if FileCipher::is_sealed(&data) => cipher.open(&data).map_err(AppError::Response)?,
+                    _ => data,
+                };
+                let mime = mime_guess::from_path(&name).first_or_octet_stream();
+                Ok(([(header::CONTENT_TYPE, mime.to_string())], data).into_response())
+            }
+            Node::Root | Node::Files => Ok((StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response()),
+        }
+    }
+
+    /// 写入一个条目，返回是否为新建
+    async fn put_tiddler(&self, title: String, mut fields: Map<String, Value>) -> AppResult<bool> {
+        // 路径里的标题为准
+        fields.insert("title".to_string(), Value::String(title.clone()));
+        fields.remove("revision");
+        let mut v = Value::Object(fields);
+        offload_binary(&self.config, &self.state, &title, &mut v).await;
+        let mut tiddler = Tiddler::from_value(v)?;
+        self.ds
+            .write(move |tiddlers| {
+                let existing = tiddlers.get(&title)?;
+                if let Some(old) = &existing {
+                    tiddler.revision = old.revision + 1;
+                    // 读取时 revision 取自 meta，这里同步写入
+                    tiddler.set_field("revision", Value::String(tiddler.revision.to_string()));
+                    if old.field("_canonical_uri").is_some() && old.field("_canonical_uri") == tiddler.field("_canonical_uri") {
+                        for key in KEPT_FIELDS {
+                            if let Some(value) = old.field(key).filter(|_| tiddler.field(key).is_none()) {
+                                tiddler.set_field(key, Value::String(value.to_string()));
+                            }
+                        }
+                    }
+                }
+                tiddlers.put(tiddler)?;
+                Ok(existing.is_none())
+            })
+            .await
+    }
+
+    async fn put(&self, node: Node, body: Bytes) -> AppResult<Response> {
+        let created = match node {
+            Node::Tiddler { title, json } => {
+                let Some(fields) = parse_upload(&body, json) else {
+                    return Ok((StatusCode::BAD_REQUEST, "Invalid tiddler file").into_response());
+                };
+                self.put_tiddler(title, fields).await?
+            }
+            Node::Scratch(name) => match self.scratch.insert(&name, body) {
+                Some(created) => created,
+                None => return Ok(StatusCode::INSUFFICIENT_STORAGE.into_response()),
+            },
+            _ => return Ok(StatusCode::FORBIDDEN.into_response()),
+        };
+        Ok(if created { StatusCode::CREATED } else { StatusCode::NO_CONTENT }.into_response())
+    }
+
+    async fn delete(&self, node: Node) -> AppResult<Response> {
+        match node {
+            Node::Tiddler { title, .. } => {
+                let target = title.clone();
+                let Some(tiddler) = self.ds.write(move |tiddlers| tiddlers.pop(&target)).await? else {
+                    return Ok(StatusCode::NOT_FOUND.into_response());
+                };
+                let (state, config) = (self.state.clone(), self.config.clone());
+                tokio::spawn(async move {
+                    try_delete_associated_file(tiddler, state, config).await;
+                });
+                tracing::info!("Deleted tiddler over WebDAV: {}", title);
+                Ok(StatusCode::NO_CONTENT.into_response())
+            }
+            Node::Scratch(name) => match self.scratch.remove(&name) {
+                Some(_) => Ok(StatusCode::NO_CONTENT.into_response()),
+                None => Ok(StatusCode::NOT_FOUND.into_response()),
+            },
+            _ => Ok(StatusCode::FORBIDDEN.into_response()),
+        }
+    }
+
+    /// 重命名：条目之间直接改标题 (保留服务器字段，转存的文件随之改名)，与临时文件之间按内容转换；
+    /// 条目改名为临时文件时条目本身不变
+    async fn rename(&self, from: Node, headers: &HeaderMap) -> AppResult<Response> {
+        let Some(to) = headers
+            .get("destination")
+            .and_then(|d| d.to_str().ok())
+            .and_then(|d| d.parse::<Uri>().ok())
+            .and_then(|uri| uri.path().strip_prefix(PREFIX).map(str::to_string))
+            .and_then(|path| urlencoding::decode(&path).ok().map(|p| p.into_owned()))
+            .and_then(|path| Node::resolve(&path))
+        else {
+            return Ok(StatusCode::BAD_REQUEST.into_response());
+        };
+        let overwrite = headers.get("overwrite").and_then(|o| o.to_str().ok()) != Some("F");
+
+        match (from, to) {
+            (Node::Tiddler { title: from, .. }, Node::Tiddler { title: to, .. }) => {
+                if from == to {
+                    return Ok(StatusCode::FORBIDDEN.into_response());
+                }
+                let files_dir = self.config.files_dir.clone();
+                let result = self
+                    .ds
+                    .write(move |tiddlers| {
+                        // 转存的文件在事务内按新标题移动；提交失败时移回原处
+                        let mut moved = None;
+                        let result = tiddlers.transaction(|store| {
+                            let existing = store.get(&to)?;
+                            if existing.is_some() && !overwrite {
+                                return Ok(Err(StatusCode::PRECONDITION_FAILED));
+                            }
+                            let Some(mut tiddler) = store.pop(&from)? else {
+                                return Ok(Err(StatusCode::NOT_FOUND));
+                            };
+                            let file = rename::rekey_file(&mut tiddler, &files_dir, &from, &to);
+                            tiddler.title = to.clone();
+                            tiddler.set_field("title", Value::String(to.clone()));
+                            tiddler.revision = existing.as_ref().map_or(0, |t| t.revision + 1);
+                            tiddler.set_field("revision", Value::String(tiddler.revision.to_string()));
+                            let status = if existing.is_some() { StatusCode::NO_CONTENT } else { StatusCode::CREATED };
+                            // 被覆盖条目的文件不再被引用；与移动过来的文件同名时已被替换掉
+                            let uri = tiddler.field("_canonical_uri").map(str::to_string);
+                            let replaced = existing.filter(|old| {
+                                old.field("_canonical_uri").is_some() && old.field("_canonical_uri") != uri.as_deref()
+                            });
+                            store.put(tiddler)?;
+                            if let Some((old_path, new_path)) = file {
+                                moved = Some(MovedFile::perform(old_path, new_path)?);
+                            }
+                            Ok(Ok((status, replaced)))
+                        });
+                        if let Some(moved) = moved {
+                            moved.finish(result.is_ok());
+                        }
+                        result
+                    })
+                    .await?;
+                match result {
+                    Ok((status, replaced)) => {
+                        if let Some(tiddler) = replaced {
+                            let (state, config) = (self.state.clone(), self.config.clone());
+                            tokio::spawn(async move {
+                                try_delete_associated_file(tiddler, state, config).await;
+                            });
+                        }
+                        Ok(status.into_response())
+                    }
+                    Err(status) => Ok(status.into_response()),
+                }
+            }
+            (Node::Scratch(from), Node::Tiddler { title, json }) => {
+                let Some((data, _)) = self.scratch.get(&from) else {
+                    return Ok(StatusCode::NOT_FOUND.into_response());
+                };
+                let Some(fields) = parse_upload(&data, json) else {
+                    return Ok((StatusCode::BAD_REQUEST, "Invalid tiddler file").into_response());
+                };
+                let target = title.clone();
+                let exists = self.ds.read(move |tiddlers| tiddlers.get(&target)).await?.is_some();
+                if exists && !overwrite {
+                    return Ok(StatusCode::PRECONDITION_FAILED.into_response());
+                }
+                self.scratch.remove(&from);
+                let created = self.put_tiddler(title, fields).await?;
+                Ok(if created { StatusCode::CREATED } else { StatusCode::NO_CONTENT }.into_response())
+            }
+            (Node::Tiddler { title, json }, Node::Scratch(to)) => {
+                if self.scratch.get(&to).is_some() && !overwrite {
+                    return Ok(StatusCode::PRECONDITION_FAILED.into_response());
+                }
+                // 编辑器把原文件改名为备份：只把内容复制到临时文件，条目保留到随后写回的 PUT 覆盖它，
+                // 这样编辑器中途崩溃也不会丢失内容
+                let target = title.clone();
+                let tiddler = match self.ds.read(move |tiddlers| tiddlers.get(&target)).await? {
+                    Some(t) if folder::is_json(&t) == json => t,
+                    _ => return Ok(StatusCode::NOT_FOUND.into_response()),
+                };
+                Ok(match self.scratch.insert(&to, Bytes::from(folder::serialize(&tiddler)?)) {
+                    Some(true) => StatusCode::CREATED.into_response(),
+                    Some(false) => StatusCode::NO_CONTENT.into_response(),
+                    None => StatusCode::INSUFFICIENT_STORAGE.into_response(),
+                })
+            }
+            (Node::Scratch(from), Node::Scratch(to)) => {
+                if self.scratch.get(&to).is_some() && !overwrite {
+                    return Ok(StatusCode::PRECONDITION_FAILED.into_response());
+                }
+                let Some(data) = self.scratch.remove(&from) else {
+                    return Ok(StatusCode::NOT_FOUND.into_response());
+                };
+                // 先移除了原文件，总量不会超出上限
+                let created = self.scratch.insert(&to, data).unwrap_or_default();
+                Ok(if created { StatusCode::CREATED } else { StatusCode::NO_CONTENT }.into_response())
+            }
+            _ => Ok(StatusCode::FORBIDDEN.into_response()),
+        }
+    }
+
+    async fn handle(&self, method: Method, path: &str, headers: &HeaderMap, body: Bytes) -> AppResult<Response> {
+        let Some(node) = Node::resolve(path) else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+        match method.as_str() {
+            "OPTIONS" => Ok(([("dav", "1, 2"), ("allow", ALLOW), ("ms-author-via", "DAV")]).into_response()),
+            "PROPFIND" => self.propfind(node, headers).await,
+            "GET" | "HEAD" => self.get(node).await,
+            "PUT" => self.put(node, body).await,
+            "DELETE" => self.delete(node).await,
+            "MOVE" => self.rename(node, headers).await,
+            "LOCK" => Ok(fake_lock()),
+            "UNLOCK" => Ok(StatusCode::NO_CONTENT.into_response()),
+            _ => Ok((StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, ALLOW)]).into_response()),
+        }
+    }
+}
+
+/// 不真正加锁：条目的并发修改由 revision 处理，这里只是让需要锁的客户端 (Finder、Office) 能够写入
+fn fake_lock() -> Response {
+    let token = format!("opaquelocktoken:{}", crypto::random_hex(16));
+    let xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery><D:activelock>\
+         <D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope><D:depth>0</D:depth>\
+         <D:timeout>Second-3600</D:timeout><D:locktoken><D:href>{}</D:href></D:locktoken>\
+         </D:activelock></D:lockdiscovery></D:prop>\n",
+        token
+    );
+    (
+        [(header::CONTENT_TYPE, "application/xml; charset=utf-8".to_string()), (header::HeaderName::from_static("lock-token"), format!("<{}>", token))],
+        xml,
+    )
+        .into_response()
+}
+
+// 单次上传的大小上限 (拖入大文件时比全局限制宽松)
+const MAX_BODY: usize = 256 * 1024 * 1024;
+
+// --- Handler: /dav/{*path} (任意方法) ---
+pub(crate) async fn dav_handler(
+    Extension(ds): Extension<DataStore>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(config): Extension<ServerConfig>,
+    Extension(scratch): Extension<Arc<Scratch>>,
+    request: Request,
+) -> AppResult<Response> {
+    let (parts, body) = request.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, MAX_BODY).await else {
+        return Ok(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+    };
+    let path = parts.uri.path().strip_prefix("/dav").unwrap_or("");
+    let Ok(path) = urlencoding::decode(path) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    Dav { ds, state, config, scratch }.handle(parts.method, &path, &parts.headers, body).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{offload_file_name, testutil};
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    struct Fixture {
+        _tmp: testutil::TempDir,
+        dav: Dav,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let tmp = testutil::TempDir::new(name);
+            let config = testutil::server_config(tmp.path());
+            let ds = testutil::datastore(&config);
+            let dav = Dav { ds, state: Arc::new(testutil::app_state()), config, scratch: Arc::default() };
+            Self { _tmp: tmp, dav }
+        }
+
+        async fn request(&self, method: &str, path: &str, headers: &[(&'static str, &str)], body: &str) -> Response {
+            let mut map = HeaderMap::new();
+            for (name, value) in headers {
+                map.insert(*name, HeaderValue::from_str(value).unwrap());
+            }
+            let method = Method::from_bytes(method.as_bytes()).unwrap();
+            self.dav.handle(method, path, &map, Bytes::from(body.to_string())).await.unwrap()
+        }
+
+        async fn status(&self, method: &str, path: &str, headers: &[(&'static str, &str)], body: &str) -> StatusCode {
+            self.request(method, path, headers, body).await.status()
+        }
+
+        async fn body(&self, path: &str) -> String {
+            let resp = self.request("GET", path, &[], "").await;
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        }
+
+        async fn get(&self, title: &str) -> Option<Tiddler> {
+            let title = title.to_string();
+            self.dav.ds.read(move |t| t.get(&title)).await.unwrap()
+        }
+
+        /// 放入一个已转存到本地的图片条目
+        async fn put_image(&self, title: &str, data: &[u8]) -> std::path::PathBuf {
+            let name = offload_file_name(title, "image/png");
+            let path = self.dav.config.files_dir.join(&name);
+            std::fs::write(&path, data).unwrap();
+            let uri = format!("/files/{}", name);
+            let t = Tiddler::from_value(json!({"title": title, "type": "image/png", "text": "", "_canonical_uri": uri, "_file_storage": "local"}))
+                .unwrap();
+            self.dav.ds.write(move |tiddlers| tiddlers.put(t)).await.unwrap();
+            path
+        }
+    }
+
+    fn destination(name: &str) -> (&'static str, String) {
+        ("destination", format!("http://localhost{}{}", PREFIX, name))
+    }
+
+    #[tokio::test]
+    async fn put_get_and_delete() {
+        let fx = Fixture::new("dav-put");
+        assert_eq!(fx.status("PUT", "/Note.tid", &[], "tags: a\n\nhello").await, StatusCode::CREATED);
+        assert_eq!(fx.status("PUT", "/Note.tid", &[], "tags: a\n\nhello again").await, StatusCode::NO_CONTENT);
+        assert!(fx.body("/Note.tid").await.ends_with("hello again"));
+        assert_eq!(fx.get("Note").await.unwrap().revision, 1);
+        assert_eq!(fx.status("GET", "/Note.json", &[], "").await, StatusCode::NOT_FOUND);
+        assert_eq!(fx.status("DELETE", "/Note.tid", &[], "").await, StatusCode::NO_CONTENT);
+        assert!(fx.get("Note").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn rename_to_scratch_keeps_the_tiddler() {
+        let fx = Fixture::new("dav-backup");
+        fx.status("PUT", "/Note.tid", &[], "title: Note\n\nkeep me").await;
+        let (name, dest) = destination("Note.tid~");
+        // 扩展名与条目的格式不符
+        assert_eq!(fx.status("MOVE", "/Note.json", &[(name, &dest)], "").await, StatusCode::NOT_FOUND);
+        assert_eq!(fx.status("MOVE", "/Note.tid", &[(name, &dest)], "").await, StatusCode::CREATED);
+        assert!(fx.body("/Note.tid~").await.ends_with("keep me"));
+        let note = fx.get("Note").await.unwrap();
+        assert_eq!((note.revision, note.field("text")), (0, Some("keep me")));
+    }
+
+    #[tokio::test]
+    async fn move_renames_offloaded_files() {
+        let fx = Fixture::new("dav-move");
+        let old_file = fx.put_image("A.png", b"a").await;
+        let (name, dest) = destination("B.png.tid");
+        assert_eq!(fx.status("MOVE", "/A.png.tid", &[(name, &dest)], "").await, StatusCode::CREATED);
+        let new_file = fx.dav.config.files_dir.join(offload_file_name("B.png", "image/png"));
+        assert!(!old_file.exists());
+        assert_eq!(std::fs::read(&new_file).unwrap(), b"a");
+        assert!(fx.get("A.png").await.is_none());
+        let uri = format!("/files/{}", offload_file_name("B.png", "image/png"));
+        assert_eq!(fx.get("B.png").await.unwrap().field("_canonical_uri"), Some(uri.as_str()));
+        assert_eq!(fx.status("MOVE", "/B.png.tid", &[(name, &dest)], "").await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn move_over_an_existing_tiddler() {
+        let fx = Fixture::new("dav-overwrite");
+        fx.put_image("A.png", b"a").await;
+        let b_file = fx.put_image("B.png", b"b").await;
+        fx.status("PUT", "/Note.tid", &[], "title: Note\n\nnote").await;
+
+        let (name, dest) = destination("B.png.tid");
+        let no_overwrite = [(name, dest.as_str()), ("overwrite", "F")];
+        assert_eq!(fx.status("MOVE", "/A.png.tid", &no_overwrite, "").await, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(std::fs::read(&b_file).unwrap(), b"b");
+
+        // 移动过来的文件替换了被覆盖条目的文件
+        assert_eq!(fx.status("MOVE", "/A.png.tid", &[(name, &dest)], "").await, StatusCode::NO_CONTENT);
+        assert_eq!(std::fs::read(&b_file).unwrap(), b"a");
+        assert_eq!(fx.get("B.png").await.unwrap().revision, 1);
+
+        // 没有文件的条目覆盖了有文件的条目：旧文件被删除
+        assert_eq!(fx.status("MOVE", "/Note.tid", &[(name, &dest)], "").await, StatusCode::NO_CONTENT);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!b_file.exists());
+        assert_eq!(std::fs::read_dir(&fx.dav.config.files_dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn scratch_is_capped() {
+        let fx = Fixture::new("dav-scratch");
+        for i in 0..MAX_SCRATCH_FILES {
+            assert_eq!(fx.status("PUT", &format!("/.swap{}", i), &[], "x").await, StatusCode::CREATED);
+        }
+        assert_eq!(fx.status("PUT", "/.one-more", &[], "x").await, StatusCode::INSUFFICIENT_STORAGE);
+        // 覆盖已有的临时文件不受数量限制
+        assert_eq!(fx.status("PUT", "/.swap0", &[], "y").await, StatusCode::NO_CONTENT);
+        let (name, dest) = destination(".swap1");
+        assert_eq!(fx.status("MOVE", "/.swap0", &[(name, &dest)], "").await, StatusCode::NO_CONTENT);
+        assert_eq!(fx.body("/.swap1").await, "y");
+
+        let scratch = Scratch::default();
+        assert_eq!(scratch.insert("big", Bytes::from(vec![0; MAX_SCRATCH_BYTES])), Some(true));
+        assert_eq!(scratch.insert("more", Bytes::from_static(b"x")), None);
+        assert_eq!(scratch.insert("big", Bytes::from_static(b"x")), Some(false));
+    }
+}
@@ -0,0 +1,152 @@
+//! 数据库连接、加密与迁移
+//!
+//! 加密存储基于 [SQLCipher]，需要以 `--features sqlcipher` 编译。密码在启动时
+//! 通过 `--db-passphrase` / `TW_DB_PASSPHRASE` 环境变量，或配置中的
+//! `server.db_passphrase_file` 提供。
+//!
+//! [SQLCipher]: https://www.zetetic.net/sqlcipher/
+
+use crate::{AppError, AppResult};
+use rusqlite::{Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+
+/// 打开数据库连接；如果提供了密码，先设置 SQLCipher 密钥并验证其正确性
+pub(crate) fn open_connection(path: &Path, passphrase: Option<&str>) -> AppResult<Connection> {
+    let cxn = Connection::open(path).map_err(AppError::from)?;
+    if let Some(pass) = passphrase {
+        ensure_sqlcipher(&cxn)?;
+        cxn.pragma_update(None, "key", pass).map_err(AppError::from)?;
+    }
+    // 密钥错误或缺少密钥时，第一次读取 schema 就会失败
+    cxn.query_row("SELECT count(*) FROM sqlite_master", [], |r| r.get::<_, i64>(0))
+        .map_err(|e| {
+            let hint = if passphrase.is_some() { "wrong passphrase?" } else { "encrypted database without a passphrase?" };
+            AppError::Database(format!("Unable to open {:?} ({}): {}", path, hint, e))
+        })?;
+    Ok(cxn)
+}
+
+fn ensure_sqlcipher(cxn: &Connection) -> AppResult<()> {
+    let version: Option<String> = cxn
+        .query_row("PRAGMA cipher_version", [], |r| r.get(0))
+        .optional()
+        .map_err(AppError::from)?;
+    match version {
+        Some(v) => {
+            tracing::debug!("SQLCipher version {}", v);
+            Ok(())
+        }
+        None => Err(AppError::Database(
+            "A database passphrase was given, but this build has no SQLCipher support (rebuild with `--features sqlcipher`)".to_string(),
+        )),
+    }
+}
+
+/// 内嵌的迁移脚本，按顺序执行；第 N 个脚本执行后 `PRAGMA user_version` 为 N。
+/// 已发布的脚本不要修改，新的 schema 变更请追加新的脚本。
+const MIGRATIONS: &[(&str, &str)] = &[
+    ("create tiddlers table", include_str!("./migrations/0001_init.sql")),
+    ("create changes table", include_str!("./migrations/0002_changes.sql")),
+    ("create links table", include_str!("./migrations/0003_links.sql")),
+    ("create shares table", include_str!("./migrations/0004_shares.sql")),
+    ("create webhook deliveries table", include_str!("./migrations/0005_webhooks.sql")),
+    ("store titles of single-tiddler shares", include_str!("./migrations/0006_share_titles.sql")),
+];
+
+/// 当前程序支持的 schema 版本
+pub(crate) fn schema_version() -> i64 {
+    MIGRATIONS.len() as i64
+}
+
+/// 在一个事务中依次执行尚未应用的迁移，返回迁移前的版本。
+/// 如果数据库的版本比程序更新，拒绝继续，以免旧程序损坏新数据。
+pub(crate) fn migrate(cxn: &mut Connection) -> AppResult<i64> {
+    let current: i64 = cxn
+        .query_row("PRAGMA user_version", [], |r| r.get(0))
+        .map_err(AppError::from)?;
+    let latest = schema_version();
+
+    if current > latest {
+        return Err(AppError::Database(format!(
+            "Database schema version {} is newer than this server supports ({}); please upgrade the server",
+            current, latest
+        )));
+    }
+    if current == latest {
+        tracing::debug!("Database schema is up to date (version {})", current);
+        return Ok(current);
+    }
+
+    let tx = cxn.transaction().map_err(AppError::from)?;
+    for (idx, (description, sql)) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = idx as i64 + 1;
+        tx.execute_batch(sql)
+            .map_err(|e| AppError::Database(format!("Migration {} ({}) failed: {}", version, description, e)))?;
+        tx.pragma_update(None, "user_version", version).map_err(AppError::from)?;
+        tracing::info!("Applied database migration {}: {}", version, description);
+    }
+    tx.commit().map_err(AppError::from)?;
+    tracing::info!("Database schema upgraded from version {} to {}", current, latest);
+    Ok(current)
+}
+
+/// 把数据库导出为使用 `to_key` 加密（空字符串表示明文）的新文件，再原地替换旧文件
+fn rekey_in_place(path: &Path, from_key: Option<&str>, to_key: &str) -> AppResult<()> {
+    if !path.exists() {
+        return Err(AppError::Database(format!("Database {:?} does not exist", path)));
+    }
+    let cxn = open_connection(path, from_key)?;
+    ensure_sqlcipher(&cxn)?;
+    // 先把 WAL 中的内容写回主文件
+    cxn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);").map_err(AppError::from)?;
+
+    let tmp_path = sibling_path(path, "rekey");
+    let _ = std::fs::remove_file(&tmp_path);
+    let user_version: i64 = cxn
+        .query_row("PRAGMA user_version", [], |r| r.get(0))
+        .map_err(AppError::from)?;
+
+    cxn.execute("ATTACH DATABASE ?1 AS target KEY ?2", rusqlite::params![tmp_path.to_string_lossy(), to_key])
+        .map_err(AppError::from)?;
+    cxn.query_row("SELECT sqlcipher_export('target')", [], |_| Ok(()))
+        .map_err(AppError::from)?;
+    cxn.execute_batch(&format!("PRAGMA target.user_version = {};", user_version))
+        .map_err(AppError::from)?;
+    // sqlcipher_export 不复制 journal_mode；读连接池依赖 WAL
+    cxn.query_row("PRAGMA target.journal_mode = WAL", [], |_| Ok(()))
+        .map_err(AppError::from)?;
+    cxn.execute_batch("DETACH DATABASE target;").map_err(AppError::from)?;
+    drop(cxn);
+
+    for suffix in ["-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+    std::fs::rename(&tmp_path, path)
+        .map_err(|e| AppError::Database(format!("Failed to replace {:?}: {}", path, e)))?;
+    Ok(())
+}
+
+/// 离线加密一个明文数据库
+pub(crate) fn encrypt_database(path: &Path, passphrase: &str) -> AppResult<()> {
+    if passphrase.is_empty() {
+        return Err(AppError::Database("passphrase must not be empty".to_string()));
+    }
+    rekey_in_place(path, None, passphrase)?;
+    tracing::info!("Database {:?} is now encrypted", path);
+    Ok(())
+}
+
+/// 离线把加密数据库还原为明文
+pub(crate) fn decrypt_database(path: &Path, passphrase: &str) -> AppResult<()> {
+    rekey_in_place(path, Some(passphrase), "")?;
+    tracing::info!("Database {:?} is now stored in plaintext", path);
+    Ok(())
+}
+
+/// 在数据库文件旁边生成一个临时文件名，如 `tiddlers.sqlite3.rekey`
+pub(crate) fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
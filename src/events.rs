@@ -0,0 +1,68 @@
+//! 修改推送：通过 Server-Sent Events 把 tiddler 的写入 / 删除实时广播给所有客户端
+//!
+//! 写操作结束后由 [`Store`](crate::store::Store) 发布事件，`GET /api/events` 的每个连接订阅同一个
+//! 广播通道。连接建立时经过与其它接口相同的认证中间件；空闲时定期发送注释行保持连接。
+
+use crate::{DataStore, store::Change};
+use axum::{
+    Extension,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{convert::Infallible, sync::Arc, time::Duration};
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream, wrappers::errors::BroadcastStreamRecvError};
+
+/// 广播通道的容量；客户端落后太多时会收到 `resync` 事件
+pub(crate) const CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EventKind {
+    Put,
+    Delete,
+    Inbox,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct TiddlerEvent {
+    #[serde(skip)]
+    pub(crate) kind: EventKind,
+    pub(crate) title: String,
+    pub(crate) revision: u64,
+    // 不含正文的字段 (删除时为删除前的字段)，供 webhook 使用
+    #[serde(skip)]
+    pub(crate) skinny: Arc<Value>,
+}
+
+impl TiddlerEvent {
+    pub(crate) fn from_change(change: &Change) -> Self {
+        let (kind, t) = match change {
+            Change::Put(t) => (EventKind::Put, t),
+            Change::Delete(t) => (EventKind::Delete, t),
+            Change::Inbox(t) => (EventKind::Inbox, t),
+        };
+        Self { kind, title: t.title.clone(), revision: t.revision, skinny: Arc::new(t.as_skinny_value()) }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self.kind {
+            EventKind::Put => "put",
+            EventKind::Delete => "delete",
+            EventKind::Inbox => "inbox",
+        }
+    }
+}
+
+// --- Handler: GET /api/events ---
+pub(crate) async fn subscribe(Extension(ds): Extension<DataStore>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(ds.subscribe()).map(|received| {
+        let event = match received {
+            Ok(e) => Event::default().event(e.name()).json_data(&e).unwrap_or_default(),
+            // 丢失了部分事件，客户端应当重新同步全部条目
+            Err(BroadcastStreamRecvError::Lagged(n)) => Event::default().event("resync").data(n.to_string()),
+        };
+        Ok(event)
+    });
+    Sse::new(stream).keep_alive(KeepAlive::new().interval(Duration::from_secs(15)))
+}
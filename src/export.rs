@@ -0,0 +1,202 @@
+//! 导出为独立的单文件 TiddlyWiki (`GET /api/export/html` 或 `export-html` 子命令)
+//!
+//! 导出的页面由当前模板和全部 tiddler 组成，但去掉了同步插件，离线打开时不会尝试连接服务器。
+//! 可选地把转存到本地或 S3 的文件重新内嵌 (二进制用 base64，SVG 等文本原样)，得到真正自包含的文件。
+
+use crate::{
+    AppConfig, AppError, AppResult, AppState, DataStore, LazyLoad, ServerConfig, SharedTemplate, Tiddler, WikiTemplate,
+    cache,
+    crypto::{self, SseCustomerKey},
+    folder,
+    template::{self, SYNC_PLUGIN},
+};
+use axum::{
+    Extension, extract,
+    http::{StatusCode, header},
+    response::Response,
+};
+use base64::{Engine as _, engine::general_purpose};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{path::Path, sync::Arc};
+
+/// 与同步插件配套、离线时没有意义的 tiddler
+fn is_server_only(title: &str) -> bool {
+    title == SYNC_PLUGIN || title.starts_with("$:/config/tiddlyweb/")
+}
+
+fn field_str(tiddler: &Tiddler, key: &str) -> Option<String> {
+    tiddler.field(key).map(str::to_string)
+}
+
+fn remove_field(tiddler: &mut Tiddler, key: &str) {
+    if let Some(map) = tiddler.meta.as_object_mut() {
+        map.remove(key);
+        if let Some(Value::Object(fields)) = map.get_mut("fields") {
+            fields.remove(key);
+        }
+    }
+}
+
+async fn s3_get(state: &AppState, bucket: &str, key: &str, sse: Option<&SseCustomerKey>) -> AppResult<Vec<u8>> {
+    let client = state
+        .s3_client
+        .as_ref()
+        .ok_or_else(|| AppError::Response("S3 is not enabled".to_string()))?;
+    let mut req = client.get_object().bucket(bucket).key(key);
+    if let Some(sse) = sse {
+        req = req
+            .sse_customer_algorithm(SseCustomerKey::ALGORITHM)
+            .sse_customer_key(&sse.key_b64)
+            .sse_customer_key_md5(&sse.key_md5_b64);
+    }
+    let obj = req
+        .send()
+        .await
+        .map_err(|e| AppError::Response(format!("S3 download of '{}' failed: {}", key, e)))?;
+    let data = obj
+        .body
+        .collect()
+        .await
+        .map_err(|e| AppError::Response(format!("S3 download of '{}' failed: {}", key, e)))?;
+    Ok(data.into_bytes().to_vec())
+}
+
+/// 读取条目 `_canonical_uri` 指向的文件内容；指向外部地址时返回 `None`
+pub(crate) async fn load_asset(tiddler: &Tiddler, state: &AppState, config: &ServerConfig) -> AppResult<Option<Vec<u8>>> {
+    let uri = match field_str(tiddler, "_canonical_uri") {
+        Some(u) => u,
+        None => return Ok(None),
+    };
+
+    if field_str(tiddler, "_file_storage").as_deref() == Some("s3")
+        && let Some(key) = field_str(tiddler, "_s3_key")
+    {
+        let bucket = field_str(tiddler, "_s3_bucket").unwrap_or_else(|| state.bucket_name.clone());
+        return s3_get(state, &bucket, &key, None).await.map(Some);
+    }
+    if let Some(key) = uri.strip_prefix("/files/s3/") {
+        return s3_get(state, &state.bucket_name, key, state.sse_c.as_ref()).await.map(Some);
+    }
+    if let Some(name) = uri.strip_prefix("/files/") {
+        if name.contains("..") || name.contains('\\') {
+            return Ok(None);
+        }
+        let data = tokio::fs::read(config.files_dir.join(name))
+            .await
+            .map_err(|e| AppError::Response(format!("Failed to read {}: {}", uri, e)))?;
+        return match &state.file_cipher {
+            Some(cipher) if data.starts_with(crypto::MAGIC) => cipher.open(&data).map(Some).map_err(AppError::Response),
+            _ => Ok(Some(data)),
+        };
+    }
+    if state.s3_client.is_some() && !state.public_url_base.is_empty() && uri.starts_with(&state.public_url_base) {
+        let key = uri[state.public_url_base.len()..].trim_start_matches('/');
+        return s3_get(state, &state.bucket_name, key, None).await.map(Some);
+    }
+    Ok(None)
+}
+
+/// 内嵌时的 `text`：二进制类型用 base64，SVG 等文本类型直接保存 UTF-8 文本；不是合法 UTF-8 时返回 `None`
+fn asset_text(mime: &str, data: Vec<u8>) -> Option<String> {
+    if folder::is_binary_type(mime) {
+        Some(general_purpose::STANDARD.encode(&data))
+    } else {
+        String::from_utf8(data).ok()
+    }
+}
+
+/// 生成导出的 HTML
+pub(crate) async fn build_html(
+    ds: &DataStore,
+    template: &WikiTemplate,
+    state: &AppState,
+    config: &ServerConfig,
+    inline_assets: bool,
+) -> AppResult<Vec<u8>> {
+    let template = template
+        .without(&[SYNC_PLUGIN])
+        .map_err(|e| AppError::Response(format!("Invalid template: {}", e)))?;
+    let mut tiddlers = ds.read(|tiddlers| tiddlers.all()).await?;
+    tiddlers.retain(|t| !is_server_only(&t.title));
+
+    if inline_assets {
+        for tiddler in tiddlers.iter_mut() {
+            let mime = field_str(tiddler, "type").unwrap_or_default();
+            match load_asset(tiddler, state, config).await {
+                Ok(Some(data)) => {
+                    let Some(text) = asset_text(&mime, data) else {
+                        tracing::warn!("Could not inline asset of '{}': not valid UTF-8 for type {}", tiddler.title, mime);
+                        continue;
+                    };
+                    tiddler.set_field("text", Value::String(text));
+                    for key in ["_canonical_uri", "_file_storage", "_s3_key", "_s3_bucket"] {
+                        remove_field(tiddler, key);
+                    }
+                }
+                Ok(None) => {}
+                // 单个文件失败时保留外部引用，不影响整体导出
+                Err(e) => tracing::warn!("Could not inline asset of '{}': {:?}", tiddler.title, e),
+            }
+        }
+    }
+
+    let fragments = tiddlers
+        .iter()
+        .map(|t| cache::fragment(t, LazyLoad::Off))
+        .collect::<AppResult<Vec<_>>>()?;
+    Ok(cache::assemble(&template, fragments.iter()))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ExportQuery {
+    #[serde(default)]
+    inline_assets: bool,
+}
+
+// --- Handler: GET /api/export/html ---
+pub(crate) async fn export_html(
+    Extension(ds): Extension<DataStore>,
+    Extension(shared): Extension<SharedTemplate>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(config): Extension<ServerConfig>,
+    extract::Query(query): extract::Query<ExportQuery>,
+) -> AppResult<Response> {
+    let template = template::current(&shared);
+    let html = build_html(&ds, &template, &state, &config, query.inline_assets).await?;
+    let filename = format!("wiki-{}.html", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
+        .body(axum::body::Body::from(html))
+        .map_err(|e| AppError::Response(format!("error building response: {}", e)))
+}
+
+/// `export-html` 子命令
+pub(crate) async fn run_cli(config: &AppConfig, output: &Path, inline_assets: bool) -> Result<(), String> {
+    let ds = crate::initialize_datastore(&config.server).map_err(|e| format!("Error initializing datastore: {:?}", e))?;
+    let template = template::load(&config.server).map_err(|e| format!("Error loading wiki template: {:?}", e))?;
+    let state = crate::build_app_state(config).await?;
+    let html = build_html(&ds, &template, &state, &config.server, inline_assets)
+        .await
+        .map_err(|e| format!("Export failed: {:?}", e))?;
+    std::fs::write(output, &html).map_err(|e| format!("Failed to write {:?}: {}", output, e))?;
+    tracing::info!("Exported wiki to {:?} ({} bytes)", output, html.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asset_text_by_type() {
+        assert_eq!(asset_text("image/png", vec![0x89, b'P', b'N', b'G']).as_deref(), Some("iVBORw=="));
+        assert_eq!(asset_text("application/pdf", b"%PDF".to_vec()).as_deref(), Some("JVBERg=="));
+        let svg = "<svg xmlns=\"http://www.w3.org/2000/svg\"/>";
+        assert_eq!(asset_text("image/svg+xml", svg.as_bytes().to_vec()).as_deref(), Some(svg));
+        assert_eq!(asset_text("text/plain", "ok".as_bytes().to_vec()).as_deref(), Some("ok"));
+        assert_eq!(asset_text("image/svg+xml", vec![0xff, 0xfe]), None);
+    }
+}
@@ -0,0 +1,224 @@
+//! Atom 订阅：最近修改的条目 (`/feeds/recent.atom`) 和带某个标签的条目 (`/feeds/tag/{tag}.atom`)
+//!
+//! 条目按 `modified` (没有时用 `created`) 倒序排列，内容为渲染后的 HTML 或原始文本。
+//! 大多数阅读器不支持 HTTP 认证，因此除了正常登录之外，也可以在 URL 中带上为某个 feed 配置的令牌。
+
+use crate::{
+    AppResult, Authenticated, DataStore, Status, Tiddler, crypto,
+    filter::Wiki,
+    render::{self, Renderer},
+    wikitext,
+};
+use axum::{
+    Extension, extract,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::{collections::HashMap, sync::Arc};
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FeedContent {
+    /// 服务器端渲染的 HTML
+    #[default]
+    Html,
+    /// 原始 wikitext
+    Text,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct FeedsConfig {
+    #[serde(default = "default_limit")]
+    pub(crate) limit: usize,
+    // 不输出 `$:/` 系统条目
+    #[serde(default = "default_exclude_system")]
+    pub(crate) exclude_system: bool,
+    #[serde(default)]
+    pub(crate) content: FeedContent,
+    // 生成绝对链接用的地址，如 `https://wiki.example.com`；未设置时根据请求的 Host 推断
+    #[serde(default)]
+    pub(crate) base_url: Option<String>,
+    // 令牌 -> 允许访问的 feed (`recent`、`tag/<标签>`，`*` 表示全部)
+    #[serde(default)]
+    pub(crate) tokens: HashMap<String, Vec<String>>,
+}
+
+fn default_limit() -> usize {
+    50
+}
+
+fn default_exclude_system() -> bool {
+    true
+}
+
+impl Default for FeedsConfig {
+    fn default() -> Self {
+        Self {
+            limit: default_limit(),
+            exclude_system: default_exclude_system(),
+            content: FeedContent::default(),
+            base_url: None,
+            tokens: HashMap::new(),
+        }
+    }
+}
+
+impl FeedsConfig {
+    fn allows(&self, token: &str, feed: &str) -> bool {
+        self.tokens.iter().any(|(candidate, feeds)| {
+            crypto::constant_time_eq(candidate.as_bytes(), token.as_bytes())
+                && feeds.iter().any(|f| f == "*" || f == feed)
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FeedQuery {
+    token: Option<String>,
+}
+
+struct Feed {
+    // `recent` 或 `tag/<标签>`，与令牌配置中的名字一致
+    name: String,
+    title: String,
+    tag: Option<String>,
+}
+
+/// TiddlyWiki 的日期 (`YYYYMMDDHHMMSSmmm`，UTC) 转为 RFC 3339
+fn rfc3339(value: &str) -> Option<String> {
+    let date = chrono::NaiveDateTime::parse_from_str(value.get(..14)?, "%Y%m%d%H%M%S").ok()?;
+    Some(date.and_utc().format("%Y-%m-%dT%H:%M:%SZ").to_string())
+}
+
+fn updated(tiddler: &Tiddler) -> Option<String> {
+    tiddler.field("modified").or_else(|| tiddler.field("created")).and_then(rfc3339)
+}
+
+fn base_url(config: &FeedsConfig, headers: &HeaderMap) -> String {
+    if let Some(base) = &config.base_url {
+        return base.trim_end_matches('/').to_string();
+    }
+    let host = headers.get(header::HOST).and_then(|h| h.to_str().ok()).unwrap_or("localhost");
+    let scheme = headers.get("x-forwarded-proto").and_then(|h| h.to_str().ok()).unwrap_or("http");
+    format!("{}://{}", scheme, host)
+}
+
+fn build_atom(wiki: &Wiki, titles: &[String], feed: &Feed, config: &FeedsConfig, base: &str, author: &str) -> String {
+    use render::escape;
+    let tiddlers: Vec<&Tiddler> = titles.iter().filter_map(|t| wiki.get(t)).collect();
+    let self_url = format!("{}/feeds/{}.atom", base, urlencoding::encode(&feed.name).replace("%2F", "/"));
+    let feed_updated = tiddlers
+        .first()
+        .and_then(|t| updated(t))
+        .unwrap_or_else(|| chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string());
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("<title>{}</title>\n", escape(&feed.title)));
+    xml.push_str(&format!("<id>{}</id>\n", escape(&self_url)));
+    xml.push_str(&format!("<link rel=\"self\" href=\"{}\"/>\n", escape(&self_url)));
+    xml.push_str(&format!("<link rel=\"alternate\" href=\"{}/\"/>\n", escape(base)));
+    xml.push_str(&format!("<updated>{}</updated>\n", feed_updated));
+    xml.push_str(&format!("<author><name>{}</name></author>\n", escape(author)));
+
+    let render_base = format!("{}/render/", base);
+    let visible = |_: &str| true;
+    let renderer = Renderer::new(wiki, &visible, &render_base);
+    for tiddler in tiddlers {
+        // 条目链接指向 Wiki 中的永久链接
+        let url = format!("{}/#{}", base, urlencoding::encode(&tiddler.title));
+        xml.push_str("<entry>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape(&tiddler.title)));
+        xml.push_str(&format!("<id>{}</id>\n", escape(&url)));
+        xml.push_str(&format!("<link rel=\"alternate\" href=\"{}\"/>\n", escape(&url)));
+        if let Some(updated) = updated(tiddler) {
+            xml.push_str(&format!("<updated>{}</updated>\n", updated));
+        }
+        if let Some(published) = tiddler.field("created").and_then(rfc3339) {
+            xml.push_str(&format!("<published>{}</published>\n", published));
+        }
+        if let Some(name) = tiddler.field("modifier").or_else(|| tiddler.field("creator")) {
+            xml.push_str(&format!("<author><name>{}</name></author>\n", escape(name)));
+        }
+        for tag in wikitext::tags(tiddler) {
+            xml.push_str(&format!("<category term=\"{}\"/>\n", escape(&tag)));
+        }
+        let content = match config.content {
+            FeedContent::Html => format!("<content type=\"html\">{}</content>\n", escape(&renderer.body(tiddler))),
+            FeedContent::Text => format!("<content type=\"text\">{}</content>\n", escape(tiddler.field("text").unwrap_or(""))),
+        };
+        xml.push_str(&content);
+        xml.push_str("</entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+async fn serve_feed(
+    ds: DataStore,
+    config: Arc<FeedsConfig>,
+    author: String,
+    authenticated: bool,
+    token: Option<String>,
+    headers: &HeaderMap,
+    feed: Feed,
+) -> AppResult<Response> {
+    if !authenticated && !token.as_deref().is_some_and(|t| config.allows(t, &feed.name)) {
+        return Ok((
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"TiddlyWiki Server\"")],
+        )
+            .into_response());
+    }
+    let base = base_url(&config, headers);
+    let xml = ds
+        .read(move |tiddlers| {
+            let all = tiddlers.all()?;
+            let mut selected: Vec<(Option<String>, String)> = all
+                .iter()
+                .filter(|t| !(config.exclude_system && t.is_system()))
+                // 草稿不输出
+                .filter(|t| t.field("draft.of").is_none())
+                .filter(|t| feed.tag.as_ref().is_none_or(|tag| wikitext::tags(t).contains(tag)))
+                .map(|t| (updated(t), t.title.clone()))
+                .collect();
+            selected.sort_by(|a, b| b.cmp(a));
+            selected.truncate(config.limit);
+            let titles: Vec<String> = selected.into_iter().map(|(_, title)| title).collect();
+            let wiki = Wiki::new(all);
+            Ok(build_atom(&wiki, &titles, &feed, &config, &base, &author))
+        })
+        .await?;
+    Ok(([(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")], xml).into_response())
+}
+
+// --- Handler: GET /feeds/recent.atom ---
+pub(crate) async fn recent_feed(
+    Extension(ds): Extension<DataStore>,
+    Extension(config): Extension<Arc<FeedsConfig>>,
+    Extension(status): Extension<Arc<Status>>,
+    Extension(Authenticated(authenticated)): Extension<Authenticated>,
+    extract::Query(query): extract::Query<FeedQuery>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let feed = Feed { name: "recent".to_string(), title: "Recent changes".to_string(), tag: None };
+    serve_feed(ds, config, status.username.clone(), authenticated, query.token, &headers, feed).await
+}
+
+// --- Handler: GET /feeds/tag/{tag}.atom ---
+pub(crate) async fn tag_feed(
+    Extension(ds): Extension<DataStore>,
+    Extension(config): Extension<Arc<FeedsConfig>>,
+    Extension(status): Extension<Arc<Status>>,
+    Extension(Authenticated(authenticated)): Extension<Authenticated>,
+    extract::Path(file): extract::Path<String>,
+    extract::Query(query): extract::Query<FeedQuery>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    // 路由无法匹配半个路径段，扩展名在这里去掉
+    let Some(tag) = file.strip_suffix(".atom") else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let feed = Feed { name: format!("tag/{}", tag), title: format!("Tiddlers tagged {}", tag), tag: Some(tag.to_string()) };
+    serve_feed(ds, config, status.username.clone(), authenticated, query.token, &headers, feed).await
+}
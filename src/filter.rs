@@ -0,0 +1,496 @@
+//! 服务端的 TiddlyWiki 过滤器求值 (`GET /api/filter?f=...`)
+//!
+//! 支持常用的子集：运行前缀 `+` `-` `~` (以及 `:and` `:except` `:else` `:or`)，
+//! 取反 `!`，以及以下操作符：
+//! `all` `title` `field` `has` `tag` `tags` `tagging` `prefix` `suffix` `search` `is`
+//! `sort` `nsort` `reverse` `first` `last` `limit` `days` `each` `get` `count`
+//! `links` `backlinks`。未知的操作符与 TiddlyWiki 一样被当作字段名，例如 `[type[image/png]]`。
+//! 变量 (`<var>`) 和文本引用 (`{Title}`) 作为参数时不受支持。
+
+use crate::{DataStore, Tiddler, wikitext};
+use axum::{Extension, extract, http::StatusCode, response::IntoResponse};
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::{
+    cell::OnceCell,
+    collections::{BTreeMap, HashMap, HashSet},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RunPrefix {
+    Or,
+    And,
+    Except,
+    Else,
+}
+
+#[derive(Debug)]
+struct Step {
+    negate: bool,
+    operator: String,
+    suffix: Option<String>,
+    operand: String,
+}
+
+#[derive(Debug)]
+struct Run {
+    prefix: RunPrefix,
+    steps: Vec<Step>,
+}
+
+// --- 解析 ---
+
+fn parse(filter: &str) -> Result<Vec<Run>, String> {
+    let mut runs = Vec::new();
+    let mut rest = filter.trim_start();
+    while !rest.is_empty() {
+        let (prefix, after) = parse_prefix(rest)?;
+        rest = after;
+        // [[Title]] 即省略了操作符名的 [title[Title]]，由 parse_steps 处理
+        let steps = if let Some(inner) = rest.strip_prefix('[') {
+            let (steps, after) = parse_steps(inner)?;
+            rest = after;
+            steps
+        } else if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            let inner = &rest[1..];
+            let end = inner.find(quote).ok_or("missing closing quote")?;
+            rest = &inner[end + 1..];
+            vec![title_step(&inner[..end])]
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let word = &rest[..end];
+            rest = &rest[end..];
+            vec![title_step(word)]
+        };
+        runs.push(Run { prefix, steps });
+        rest = rest.trim_start();
+    }
+    Ok(runs)
+}
+
+fn title_step(title: &str) -> Step {
+    Step { negate: false, operator: "title".to_string(), suffix: None, operand: title.to_string() }
+}
+
+fn parse_prefix(s: &str) -> Result<(RunPrefix, &str), String> {
+    for (name, prefix) in [(":and", RunPrefix::And), (":except", RunPrefix::Except), (":else", RunPrefix::Else), (":or", RunPrefix::Or)] {
+        if let Some(rest) = s.strip_prefix(name) {
+            return Ok((prefix, rest));
+        }
+    }
+    if s.starts_with(':') {
+        let name = s.split(['[', ' ']).next().unwrap_or(s);
+        return Err(format!("unsupported filter run prefix '{}'", name));
+    }
+    Ok(match s.chars().next() {
+        Some('+') => (RunPrefix::And, &s[1..]),
+        Some('-') => (RunPrefix::Except, &s[1..]),
+        Some('~') => (RunPrefix::Else, &s[1..]),
+        _ => (RunPrefix::Or, s),
+    })
+}
+
+/// 解析 `[` 之后的若干步骤，直到匹配的 `]`
+fn parse_steps(mut s: &str) -> Result<(Vec<Step>, &str), String> {
+    let mut steps = Vec::new();
+    loop {
+        if let Some(rest) = s.strip_prefix(']') {
+            if steps.is_empty() {
+                return Err("empty filter step".to_string());
+            }
+            return Ok((steps, rest));
+        }
+        let negate = s.starts_with('!');
+        if negate {
+            s = &s[1..];
+        }
+        let name_end = s.find(['[', '{', '<', '/']).ok_or("missing operand")?;
+        let name = &s[..name_end];
+        let (operator, suffix) = match name.split_once(':') {
+            Some((op, suffix)) => (op, Some(suffix.to_string())),
+            None => (name, None),
+        };
+        let operator = if operator.is_empty() { "title" } else { operator };
+        s = &s[name_end..];
+        let close = match s.chars().next() {
+            Some('[') => ']',
+            Some('{') => return Err(format!("text references are not supported (operator '{}')", operator)),
+            Some('<') => return Err(format!("variables are not supported (operator '{}')", operator)),
+            Some('/') => return Err(format!("regular expressions are not supported (operator '{}')", operator)),
+            _ => return Err("missing operand".to_string()),
+        };
+        let end = s[1..].find(close).ok_or("missing ']' after operand")?;
+        let operand = s[1..1 + end].to_string();
+        s = &s[1 + end + 1..];
+        steps.push(Step { negate, operator: operator.to_string(), suffix, operand });
+    }
+}
+
+// --- 求值 ---
+
+/// 求值时使用的只读快照，按标题排序
+pub(crate) struct Wiki {
+    tiddlers: BTreeMap<String, Tiddler>,
+    // 展开后的字段 (tags 为字符串形式)，与 GET 返回的一致
+    fields: HashMap<String, Value>,
+    backlinks: OnceCell<HashMap<String, Vec<String>>>,
+}
+
+impl Wiki {
+    pub(crate) fn new(tiddlers: Vec<Tiddler>) -> Self {
+        let fields = tiddlers.iter().map(|t| (t.title.clone(), t.as_value())).collect();
+        Self {
+            tiddlers: tiddlers.into_iter().map(|t| (t.title.clone(), t)).collect(),
+            fields,
+            backlinks: OnceCell::new(),
+        }
+    }
+
+    pub(crate) fn get(&self, title: &str) -> Option<&Tiddler> {
+        self.tiddlers.get(title)
+    }
+
+    fn field(&self, title: &str, name: &str) -> Option<String> {
+        if name == "title" {
+            return Some(title.to_string());
+        }
+        match self.fields.get(title)?.get(name)? {
+            Value::String(s) => Some(s.clone()),
+            Value::Null => None,
+            other => Some(other.to_string()),
+        }
+    }
+
+    fn tags(&self, title: &str) -> Vec<String> {
+        self.get(title).map(wikitext::tags).unwrap_or_default()
+    }
+
+    fn backlinks(&self, title: &str) -> &[String] {
+        let index = self.backlinks.get_or_init(|| {
+            let mut index: HashMap<String, Vec<String>> = HashMap::new();
+            for t in self.tiddlers.values() {
+                for target in wikitext::links(t) {
+                    index.entry(target).or_default().push(t.title.clone());
+                }
+            }
+            index
+        });
+        index.get(title).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// 对过滤器求值，返回标题列表
+    pub(crate) fn filter(&self, filter: &str) -> Result<Vec<String>, String> {
+        let mut results: Vec<String> = Vec::new();
+        for run in parse(filter)? {
+            match run.prefix {
+                RunPrefix::Or => {
+                    let output = self.run(&run.steps, self.all())?;
+                    let seen: HashSet<&String> = output.iter().collect();
+                    results.retain(|t| !seen.contains(t));
+                    results.extend(output);
+                }
+                RunPrefix::And => results = self.run(&run.steps, results)?,
+                RunPrefix::Except => {
+                    let output: HashSet<String> = self.run(&run.steps, self.all())?.into_iter().collect();
+                    results.retain(|t| !output.contains(t));
+                }
+                RunPrefix::Else => {
+                    if results.is_empty() {
+                        results = self.run(&run.steps, self.all())?;
+                    }
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    fn all(&self) -> Vec<String> {
+        self.tiddlers.keys().cloned().collect()
+    }
+
+    fn run(&self, steps: &[Step], mut input: Vec<String>) -> Result<Vec<String>, String> {
+        for step in steps {
+            input = self.step(step, input)?;
+        }
+        Ok(input)
+    }
+
+    fn step(&self, step: &Step, input: Vec<String>) -> Result<Vec<String>, String> {
+        let operand = step.operand.as_str();
+        let keep = |pred: &dyn Fn(&str) -> bool| -> Vec<String> {
+            input.iter().filter(|t| pred(t) != step.negate).cloned().collect()
+        };
+        let number = |default: i64| -> Result<i64, String> {
+            if operand.trim().is_empty() {
+                Ok(default)
+            } else {
+                operand.trim().parse().map_err(|_| format!("'{}' expects a number, got '{}'", step.operator, operand))
+            }
+        };
+
+        Ok(match step.operator.as_str() {
+            "all" => {
+                let mut out = Vec::new();
+                for source in operand.split('+') {
+                    match source {
+                        "tiddlers" => out.extend(self.all()),
+                        "current" | "" => out.extend(input.iter().cloned()),
+                        other => return Err(format!("all[{}] is not supported", other)),
+                    }
+                }
+                dedupe(out)
+            }
+            "title" => {
+                if step.negate {
+                    keep(&|t| t == operand)
+                } else {
+                    vec![operand.to_string()]
+                }
+            }
+            "field" => {
+                let name = step.suffix.as_deref().ok_or("field operator needs a field name, e.g. field:type[...]")?;
+                keep(&|t| self.field(t, name).unwrap_or_default() == operand)
+            }
+            "has" => keep(&|t| self.field(t, operand).is_some_and(|v| !v.is_empty())),
+            "tag" => keep(&|t| self.tags(t).iter().any(|tag| tag == operand)),
+            "tags" => dedupe(input.iter().flat_map(|t| self.tags(t)).collect()),
+            "tagging" => {
+                let wanted: HashSet<&String> = input.iter().collect();
+                self.tiddlers
+                    .values()
+                    .filter(|t| wikitext::tags(t).iter().any(|tag| wanted.contains(tag)))
+                    .map(|t| t.title.clone())
+                    .collect()
+            }
+            "prefix" => keep(&|t| t.starts_with(operand)),
+            "suffix" => keep(&|t| t.ends_with(operand)),
+            "search" => {
+                let field_list = step.suffix.as_deref().and_then(|s| s.split(':').next()).filter(|s| !s.is_empty());
+                let fields: Vec<&str> = match field_list {
+                    Some(list) => list.split(',').collect(),
+                    None => vec!["title", "text", "tags"],
+                };
+                let words: Vec<String> = operand.split_whitespace().map(str::to_lowercase).collect();
+                keep(&|t| {
+                    let haystack = fields
+                        .iter()
+                        .filter_map(|f| self.field(t, f))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                        .to_lowercase();
+                    words.iter().all(|w| haystack.contains(w.as_str()))
+                })
+            }
+            "is" => {
+                let exists = |t: &str| self.tiddlers.contains_key(t);
+                match operand {
+                    "system" => keep(&|t| t.starts_with("$:/")),
+                    "tiddler" => keep(&exists),
+                    "missing" => keep(&|t| !exists(t)),
+                    "image" => keep(&|t| self.field(t, "type").is_some_and(|m| m.starts_with("image/"))),
+                    "tag" => {
+                        let all_tags: HashSet<String> = self.tiddlers.values().flat_map(wikitext::tags).collect();
+                        keep(&|t| all_tags.contains(t))
+                    }
+                    // 服务器上没有影子条目
+                    "shadow" => keep(&|_| false),
+                    other => return Err(format!("is[{}] is not supported", other)),
+                }
+            }
+            "sort" | "nsort" => {
+                let name = if operand.is_empty() { "title" } else { operand };
+                let mut sorted = input;
+                if step.operator == "nsort" {
+                    let key = |t: &String| self.field(t, name).and_then(|v| v.trim().parse::<f64>().ok());
+                    sorted.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap_or(std::cmp::Ordering::Equal));
+                } else {
+                    sorted.sort_by_cached_key(|t| self.field(t, name).unwrap_or_default().to_lowercase());
+                }
+                if step.negate {
+                    sorted.reverse();
+                }
+                sorted
+            }
+            "reverse" => input.into_iter().rev().collect(),
+            "first" => input.into_iter().take(number(1)?.max(0) as usize).collect(),
+            "last" => {
+                let n = number(1)?.max(0) as usize;
+                let skip = input.len().saturating_sub(n);
+                input.into_iter().skip(skip).collect()
+            }
+            "limit" => {
+                let n = number(0)?;
+                if (n >= 0) != step.negate {
+                    input.into_iter().take(n.unsigned_abs() as usize).collect()
+                } else {
+                    let skip = input.len().saturating_sub(n.unsigned_abs() as usize);
+                    input.into_iter().skip(skip).collect()
+                }
+            }
+            "days" => {
+                let name = step.suffix.as_deref().unwrap_or("modified");
+                let interval = number(0)?;
+                let today = Utc::now().date_naive();
+                let target = today + chrono::Duration::days(interval);
+                keep(&|t| {
+                    self.field(t, name).and_then(|v| parse_date(&v)).is_some_and(|date| {
+                        let sign = (target - date).num_days().signum();
+                        sign == 0 || sign == interval.signum()
+                    })
+                })
+            }
+            "each" => {
+                if step.negate {
+                    return Err("!each is not supported".to_string());
+                }
+                let name = if operand.is_empty() { "title" } else { operand };
+                let mut seen = HashSet::new();
+                input.into_iter().filter(|t| seen.insert(self.field(t, name).unwrap_or_default())).collect()
+            }
+            "get" => dedupe(input.iter().filter_map(|t| self.field(t, operand)).filter(|v| !v.is_empty()).collect()),
+            "count" => vec![input.len().to_string()],
+            "links" => dedupe(input.iter().filter_map(|t| self.get(t)).flat_map(wikitext::links).collect()),
+            "backlinks" => dedupe(input.iter().flat_map(|t| self.backlinks(t).iter().cloned()).collect()),
+            // 与 TiddlyWiki 一样，未知操作符视为字段名
+            name => keep(&|t| self.field(t, name).unwrap_or_default() == operand),
+        })
+    }
+}
+
+fn dedupe(titles: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    titles.into_iter().filter(|t| seen.insert(t.clone())).collect()
+}
+
+/// 解析 TiddlyWiki 的日期字段 (`YYYYMMDDHHMMSSmmm`，UTC)
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FilterQuery {
+    f: String,
+    // 返回完整条目 (含正文)；默认只返回 skinny 条目
+    #[serde(default)]
+    full: bool,
+}
+
+// --- Handler: GET /api/filter?f=... ---
+pub(crate) async fn run_filter(
+    Extension(ds): Extension<DataStore>,
+    extract::Query(query): extract::Query<FilterQuery>,
+) -> crate::AppResult<axum::response::Response> {
+    let result = ds
+        .read(move |tiddlers| {
+            let wiki = Wiki::new(tiddlers.all()?);
+            Ok(wiki.filter(&query.f).map(|titles| {
+                titles
+                    .iter()
+                    .map(|title| match wiki.get(title) {
+                        Some(t) if query.full => t.as_value(),
+                        Some(t) => t.as_skinny_value(),
+                        // 结果中可能包含不存在的标题 (例如 links 指向的缺失条目)
+                        None => json!({ "title": title }),
+                    })
+                    .collect::<Vec<_>>()
+            }))
+        })
+        .await?;
+    Ok(match result {
+        Ok(values) => axum::Json(values).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, axum::Json(json!({ "error": e }))).into_response(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wiki() -> Wiki {
+        let tiddlers = [
+            json!({"title": "Alpha", "text": "links to [[Beta]] and {{Gamma}}", "tags": "Project", "priority": "2"}),
+            json!({"title": "Beta", "text": "Hello World", "tags": "Project [[Two Words]]", "priority": "10"}),
+            json!({"title": "Gamma", "text": "see [[Beta]]", "type": "text/vnd.tiddlywiki"}),
+            json!({"title": "Project", "text": "", "modified": "20200101000000000"}),
+            json!({"title": "Pic", "type": "image/png", "text": ""}),
+            json!({"title": "$:/config/Thing", "text": "yes"}),
+        ];
+        Wiki::new(tiddlers.into_iter().map(|v| Tiddler::from_value(v).unwrap()).collect())
+    }
+
+    fn run(filter: &str) -> Vec<String> {
+        wiki().filter(filter).unwrap()
+    }
+
+    #[test]
+    fn titles_and_runs() {
+        assert_eq!(run("Alpha [[Beta]] 'Gamma' \"Missing\""), ["Alpha", "Beta", "Gamma", "Missing"]);
+        // 重复的标题移到后面
+        assert_eq!(run("Alpha Beta Alpha"), ["Beta", "Alpha"]);
+        assert_eq!(run("[tag[Project]] -Alpha"), ["Beta"]);
+        assert_eq!(run("[tag[Project]] :except[[Beta]]"), ["Alpha"]);
+        assert_eq!(run("[tag[Project]] +[prefix[B]]"), ["Beta"]);
+        assert_eq!(run("[tag[Nothing]] ~[[Fallback]]"), ["Fallback"]);
+        assert_eq!(run("Alpha ~[[Fallback]]"), ["Alpha"]);
+    }
+
+    #[test]
+    fn field_operators() {
+        assert_eq!(run("[has[type]]"), ["Gamma", "Pic"]);
+        assert_eq!(run("[type[image/png]]"), ["Pic"]);
+        assert_eq!(run("[field:priority[10]]"), ["Beta"]);
+        assert_eq!(run("[all[tiddlers]!has[type]!is[system]]"), ["Alpha", "Beta", "Project"]);
+        assert_eq!(run("[[Beta]get[priority]]"), ["10"]);
+        assert_eq!(run("[[Beta]tags[]]"), ["Project", "Two Words"]);
+        assert_eq!(run("[[Project]tagging[]]"), ["Alpha", "Beta"]);
+        assert_eq!(run("[search[hello world]]"), ["Beta"]);
+        assert_eq!(run("[search:title[amm]]"), ["Gamma"]);
+    }
+
+    #[test]
+    fn is_operator() {
+        assert_eq!(run("[is[system]]"), ["$:/config/Thing"]);
+        assert_eq!(run("[is[image]]"), ["Pic"]);
+        assert_eq!(run("[is[tag]]"), ["Project"]);
+        assert_eq!(run("Alpha Nope +[is[missing]]"), ["Nope"]);
+        assert!(wiki().filter("[is[draft]]").is_err());
+    }
+
+    #[test]
+    fn sorting_and_slicing() {
+        assert_eq!(run("[tag[Project]nsort[priority]]"), ["Alpha", "Beta"]);
+        // 字符串排序时 "10" 在 "2" 前面
+        assert_eq!(run("[tag[Project]sort[priority]]"), ["Beta", "Alpha"]);
+        assert_eq!(run("[tag[Project]!sort[]]"), ["Beta", "Alpha"]);
+        assert_eq!(run("[!is[system]first[2]]"), ["Alpha", "Beta"]);
+        assert_eq!(run("[!is[system]last[2]]"), ["Pic", "Project"]);
+        assert_eq!(run("[!is[system]limit[1]]"), ["Alpha"]);
+        assert_eq!(run("[!is[system]!limit[1]]"), ["Project"]);
+        assert_eq!(run("[!is[system]reverse[]first[]]"), ["Project"]);
+        assert_eq!(run("[tag[Project]count[]]"), ["2"]);
+        assert_eq!(run("[!is[system]each[type]]"), ["Alpha", "Gamma", "Pic"]);
+    }
+
+    #[test]
+    fn links_and_backlinks() {
+        assert_eq!(run("[[Alpha]links[]]"), ["Beta", "Gamma"]);
+        assert_eq!(run("[[Beta]backlinks[]]"), ["Alpha", "Gamma"]);
+        assert!(run("[[Pic]backlinks[]]").is_empty());
+    }
+
+    #[test]
+    fn days_operator() {
+        // 没有日期字段的条目不算在最近 30 天内
+        assert!(run("[days[-30]]").is_empty());
+        assert_eq!(run("[has[modified]!days[-30]]"), ["Project"]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        for filter in ["[tag[x]", "[]", "[tag{Ref}]", "[tag<var>]", "[regexp/x/]", ":map[x]", "[first[two]]", "[all[shadows]]", "[field[x]]"] {
+            assert!(wiki().filter(filter).is_err(), "{} should fail", filter);
+        }
+        assert_eq!(run(""), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,413 @@
+//! 与 TiddlyWiki Node.js 版的 wiki 文件夹互相转换 (`export-folder` / `import-folder` 子命令)
+//!
+//! 导出时按官方的文件系统约定生成 `tiddlywiki.info` 和 `tiddlers/` 目录：
+//! 普通条目保存为 `.tid`，二进制条目保存为原始文件加 `.meta`，含多行字段的条目和插件保存为 `.json`。
+//! 导入时读取同样的格式，二进制内容与 `PUT` 一样经过转存逻辑。
+
+use crate::{
+    AppConfig, AppError, AppResult, AppState, DataStore, ServerConfig, StagedFiles, Tiddler, export, mime_to_ext,
+};
+use base64::{Engine as _, engine::general_purpose};
+use serde_json::{Map, Value, json};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+const MAX_FILENAME_LEN: usize = 200;
+
+// 导出时不写入的字段：由服务器维护
+const SERVER_FIELDS: &[&str] = &["revision", "bag", "_file_storage", "_s3_key", "_s3_bucket"];
+
+fn io_error(path: &Path, e: std::io::Error) -> AppError {
+    AppError::Response(format!("{:?}: {}", path, e))
+}
+
+pub(crate) fn is_binary_type(mime: &str) -> bool {
+    mime.starts_with("image/") && mime != "image/svg+xml"
+        || mime == "application/pdf"
+        || mime.starts_with("video/")
+        || mime.starts_with("audio/")
+        || mime == "application/octet-stream"
+}
+
+pub(crate) fn extension_for(mime: &str) -> String {
+    match mime_to_ext(mime) {
+        "bin" => mime_guess::get_mime_extensions_str(mime)
+            .and_then(|exts| exts.first())
+            .unwrap_or(&"bin")
+            .to_string(),
+        ext => ext.to_string(),
+    }
+}
+
+/// 按 TiddlyWiki 的规则把标题转换为合法的文件名 (不含扩展名)，并避免重名
+fn file_stem(title: &str, used: &mut HashSet<String>) -> String {
+    let mut stem: String = title
+        .chars()
+        .map(|c| if "<>:\"/\\|?*^~".contains(c) || c.is_control() { '_' } else { c })
+        .collect();
+    if stem.chars().count() > MAX_FILENAME_LEN {
+        stem = stem.chars().take(MAX_FILENAME_LEN).collect();
+    }
+    let stem = stem.trim_end_matches(['.', ' ']).to_string();
+    let stem = if stem.is_empty() { "_".to_string() } else { stem };
+
+    // 大小写不敏感的文件系统上也不能冲突
+    let mut candidate = stem.clone();
+    let mut n = 1;
+    while !used.insert(candidate.to_lowercase()) {
+        candidate = format!("{} {}", stem, n);
+        n += 1;
+    }
+    candidate
+}
+
+/// 展开后的字段 (字符串形式，tags 为 TiddlyWiki 列表格式)，去掉服务器维护的字段
+pub(crate) fn export_fields(tiddler: &Tiddler) -> Map<String, Value> {
+    let mut fields = match tiddler.as_value() {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    };
+    for key in SERVER_FIELDS {
+        fields.remove(*key);
+    }
+    fields
+}
+
+pub(crate) fn header_lines(fields: &Map<String, Value>) -> String {
+    let mut keys: Vec<&String> = fields.keys().filter(|k| *k != "text").collect();
+    // title 放在最前面，其余按字母顺序
+    keys.sort_by_key(|k| (*k != "title", k.to_string()));
+    let mut out = String::new();
+    for key in keys {
+        let value = match &fields[key] {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        out.push_str(&format!("{}: {}\n", key, value));
+    }
+    out
+}
+
+/// 字段能否写进 `.tid` 的头部 (值不能跨行)
+pub(crate) fn fits_tid(fields: &Map<String, Value>) -> bool {
+    fields.iter().all(|(key, value)| {
+        key == "text" || !key.contains(':') && value.as_str().is_none_or(|s| !s.contains('\n'))
+    })
+}
+
+/// 标题转为文件名：文件系统不允许的字符和 `%` 本身按百分号编码，保证可以还原
+pub(crate) fn encode_name(title: &str) -> String {
+    let mut out = String::new();
+    for c in title.chars() {
+        if "%/\\:*?\"<>|".contains(c) || c.is_control() {
+            let mut buf = [0u8; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                out.push_str(&format!("%{:02X}", b));
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// 条目需要保存为 `.json` (插件，或字段无法写进 `.tid` 头部)
+pub(crate) fn is_json(tiddler: &Tiddler) -> bool {
+    tiddler.field("plugin-type").is_some() || !fits_tid(&export_fields(tiddler))
+}
+
+/// 与标题一一对应的文件名 (`export-folder` 为了兼容 Node.js 版使用的文件名不可还原)
+pub(crate) fn file_name(tiddler: &Tiddler) -> String {
+    let ext = if is_json(tiddler) { "json" } else { "tid" };
+    format!("{}.{}", encode_name(&tiddler.title), ext)
+}
+
+/// 文件内容：`.tid` 为头部加正文，`.json` 为只含一个条目的数组
+pub(crate) fn serialize(tiddler: &Tiddler) -> AppResult<Vec<u8>> {
+    let fields = export_fields(tiddler);
+    if is_json(tiddler) {
+        return serde_json::to_vec_pretty(&[Value::Object(fields)]).map_err(|e| AppError::Serialization(e.to_string()));
+    }
+    let text = fields.get("text").and_then(Value::as_str).unwrap_or("");
+    Ok(format!("{}\n{}", header_lines(&fields), text).into_bytes())
+}
+
+pub(crate) struct FolderReport {
+    pub(crate) tiddlers: usize,
+    pub(crate) binaries: usize,
+    // 导入时无法读取而跳过的文件
+    pub(crate) skipped: Vec<PathBuf>,
+}
+
+/// 把所有条目导出到 `dir`
+pub(crate) async fn export_folder(ds: &DataStore, state: &AppState, config: &ServerConfig, dir: &Path) -> AppResult<FolderReport> {
+    let tiddlers_dir = dir.join("tiddlers");
+    std::fs::create_dir_all(&tiddlers_dir).map_err(|e| io_error(&tiddlers_dir, e))?;
+
+    let info = json!({
+        "description": "Exported from tiddly-wiki-server",
+        "plugins": ["tiddlywiki/tiddlyweb", "tiddlywiki/filesystem"],
+        "themes": ["tiddlywiki/vanilla", "tiddlywiki/snowwhite"],
+    });
+    let info_path = dir.join("tiddlywiki.info");
+    let info_json = serde_json::to_string_pretty(&info).map_err(|e| AppError::Serialization(e.to_string()))?;
+    std::fs::write(&info_path, info_json).map_err(|e| io_error(&info_path, e))?;
+
+    let tiddlers = ds.read(|tiddlers| tiddlers.all()).await?;
+    let mut used = HashSet::new();
+    let mut report = FolderReport { tiddlers: 0, binaries: 0, skipped: Vec::new() };
+    for tiddler in &tiddlers {
+        let mut fields = export_fields(tiddler);
+        let mime = tiddler.field("type").unwrap_or("").to_string();
+        let stem = file_stem(&tiddler.title, &mut used);
+
+        // 二进制条目和转存过的条目 (如 SVG)：文件或 base64 正文写成原始文件，其余字段写进 .meta
+        let mut binary = None;
+        if is_binary_type(&mime) || tiddler.field("_canonical_uri").is_some() {
+            binary = match export::load_asset(tiddler, state, config).await {
+                Ok(Some(data)) => {
+                    fields.remove("_canonical_uri");
+                    Some(data)
+                }
+                Ok(None) if is_binary_type(&mime) => tiddler.field("text").and_then(|t| general_purpose::STANDARD.decode(t).ok()),
+                // 外部链接，保留 _canonical_uri
+                Ok(None) => None,
+                Err(e) => {
+                    tracing::warn!("Could not read file of '{}', keeping _canonical_uri: {:?}", tiddler.title, e);
+                    None
+                }
+            };
+        }
+
+        if let Some(data) = binary {
+            fields.remove("text");
+            let path = tiddlers_dir.join(format!("{}.{}", stem, extension_for(&mime)));
+            std::fs::write(&path, data).map_err(|e| io_error(&path, e))?;
+            let meta_path = PathBuf::from(format!("{}.meta", path.display()));
+            std::fs::write(&meta_path, header_lines(&fields)).map_err(|e| io_error(&meta_path, e))?;
+            report.binaries += 1;
+        } else if tiddler.field("plugin-type").is_some() || !fits_tid(&fields) {
+            let path = tiddlers_dir.join(format!("{}.json", stem));
+            let body = serde_json::to_string_pretty(&[Value::Object(fields)])
+                .map_err(|e| AppError::Serialization(e.to_string()))?;
+            std::fs::write(&path, body).map_err(|e| io_error(&path, e))?;
+        } else {
+            let path = tiddlers_dir.join(format!("{}.tid", stem));
+            let text = fields.get("text").and_then(Value::as_str).unwrap_or("");
+            let body = format!("{}\n{}", header_lines(&fields), text);
+            std::fs::write(&path, body).map_err(|e| io_error(&path, e))?;
+        }
+        report.tiddlers += 1;
+    }
+    Ok(report)
+}
+
+/// 解析 `.tid` / `.meta` 的头部；返回字段和正文 (`.meta` 没有正文)
+pub(crate) fn parse_tid(content: &str) -> (Map<String, Value>, Option<String>) {
+    let content = content.replace("\r\n", "\n");
+    let (header, text) = match content.split_once("\n\n") {
+        Some((header, text)) => (header.to_string(), Some(text.to_string())),
+        None => (content.clone(), None),
+    };
+    let mut fields = Map::new();
+    for line in header.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            let key = key.trim();
+            if !key.is_empty() {
+                fields.insert(key.to_string(), Value::String(value.trim().to_string()));
+            }
+        }
+    }
+    (fields, text)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> AppResult<()> {
+    let entries = std::fs::read_dir(dir).map_err(|e| io_error(dir, e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| io_error(dir, e))?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// 读取一个文件中的条目 (`.json` 中可能有多个)
+fn read_tiddlers(path: &Path) -> AppResult<Vec<Value>> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("").to_string();
+    let file_name = path.file_name().and_then(|s| s.to_str()).unwrap_or("").to_string();
+    match ext.as_str() {
+        "tid" => {
+            let content = std::fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+            let (mut fields, text) = parse_tid(&content);
+            fields.entry("title").or_insert(Value::String(stem));
+            fields.insert("text".to_string(), Value::String(text.unwrap_or_default()));
+            Ok(vec![Value::Object(fields)])
+        }
+        "json" => {
+            let content = std::fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+            let value: Value = serde_json::from_str(&content)
+                .map_err(|e| AppError::Serialization(format!("{:?}: {}", path, e)))?;
+            match value {
+                Value::Array(items) => Ok(items.into_iter().filter(|v| v.get("title").is_some()).collect()),
+                // 不是 tiddler 的 JSON 文件作为 application/json 条目导入
+                Value::Object(ref map) if map.contains_key("title") => Ok(vec![value]),
+                _ => Ok(vec![json!({ "title": file_name, "type": "application/json", "text": content })]),
+            }
+        }
+        _ => {
+            let data = std::fs::read(path).map_err(|e| io_error(path, e))?;
+            let meta_path = PathBuf::from(format!("{}.meta", path.display()));
+            let mut fields = match std::fs::read_to_string(&meta_path) {
+                Ok(meta) => parse_tid(&meta).0,
+                Err(_) => Map::new(),
+            };
+            fields.entry("title").or_insert(Value::String(file_name));
+            let mime = match fields.get("type").and_then(Value::as_str) {
+                Some(t) => t.to_string(),
+                None => mime_guess::from_path(path).first_or_octet_stream().to_string(),
+            };
+            let text = if is_binary_type(&mime) {
+                general_purpose::STANDARD.encode(&data)
+            } else {
+                String::from_utf8_lossy(&data).into_owned()
+            };
+            fields.insert("type".to_string(), Value::String(mime));
+            fields.insert("text".to_string(), Value::String(text));
+            Ok(vec![Value::Object(fields)])
+        }
+    }
+}
+
+/// 从 wiki 文件夹 (或其中的 `tiddlers/` 目录) 导入条目，已存在的条目会被覆盖
+pub(crate) async fn import_folder(ds: &DataStore, state: &AppState, config: &ServerConfig, dir: &Path) -> AppResult<FolderReport> {
+    let root = if dir.join("tiddlers").is_dir() { dir.join("tiddlers") } else { dir.to_path_buf() };
+    let mut files = Vec::new();
+    collect_files(&root, &mut files)?;
+    files.sort();
+
+    let mut tiddlers = Vec::new();
+    let mut binaries = 0;
+    let mut skipped = Vec::new();
+    // 事务失败时据此删除新文件、恢复被覆盖的文件
+    let mut staged = StagedFiles::default();
+    for path in files {
+        let name = path.file_name().and_then(|s| s.to_str()).unwrap_or("");
+        if name.ends_with(".meta") || name == "tiddlywiki.info" || name.starts_with('.') {
+            continue;
+        }
+        // 单个文件损坏时跳过它，不影响其余文件的导入
+        let values = match read_tiddlers(&path) {
+            Ok(values) => values,
+            Err(e) => {
+                tracing::warn!("Skipped {:?}: {:?}", path, e);
+                skipped.push(path);
+                continue;
+            }
+        };
+        for value in values {
+            let title = match value.get("title").and_then(Value::as_str) {
+                Some(t) => t.to_string(),
+                None => continue,
+            };
+            match Tiddler::from_value(value) {
+                Ok(mut tiddler) => {
+                    if staged.offload(config, state, &title, &mut tiddler.meta).await {
+                        binaries += 1;
+                    }
+                    tiddlers.push(tiddler);
+                }
+                Err(e) => {
+                    tracing::warn!("Skipped '{}' in {:?}: {:?}", title, path, e);
+                    skipped.push(path.clone());
+                }
+            }
+        }
+    }
+
+    let count = tiddlers.len();
+    let result = ds
+        .write(move |store| {
+            store.transaction(|store| {
+                for mut tiddler in tiddlers {
+                    if let Some(existing) = store.get(&tiddler.title)? {
+                        tiddler.revision = existing.revision + 1;
+                    }
+                    store.put(tiddler)?;
+                }
+                Ok(())
+            })
+        })
+        .await;
+    staged.finish(result.is_ok()).await;
+    result?;
+    Ok(FolderReport { tiddlers: count, binaries, skipped })
+}
+
+/// `export-folder` / `import-folder` 子命令
+pub(crate) async fn run_cli(config: &AppConfig, dir: &Path, import: bool) -> Result<(), String> {
+    let ds = crate::initialize_datastore(&config.server).map_err(|e| format!("Error initializing datastore: {:?}", e))?;
+    let state = crate::build_app_state(config).await?;
+    if import {
+        let report = import_folder(&ds, &state, &config.server, dir)
+            .await
+            .map_err(|e| format!("Import failed: {:?}", e))?;
+        tracing::info!("Imported {} tiddler(s) from {:?} ({} file(s) offloaded)", report.tiddlers, dir, report.binaries);
+        if !report.skipped.is_empty() {
+            tracing::warn!("Skipped {} unreadable file(s): {:?}", report.skipped.len(), report.skipped);
+        }
+    } else {
+        let report = export_folder(&ds, &state, &config.server, dir)
+            .await
+            .map_err(|e| format!("Export failed: {:?}", e))?;
+        tracing::info!("Exported {} tiddler(s) to {:?} ({} binary file(s))", report.tiddlers, dir, report.binaries);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    #[tokio::test]
+    async fn failed_import_restores_files() {
+        let tmp = testutil::TempDir::new("folder-import");
+        let config = testutil::server_config(tmp.path());
+        let ds = testutil::datastore(&config);
+        let state = testutil::app_state();
+        let wiki = tmp.path().join("wiki");
+        std::fs::create_dir_all(wiki.join("tiddlers")).unwrap();
+        std::fs::write(wiki.join("tiddlers/Logo.png"), b"old").unwrap();
+
+        let report = import_folder(&ds, &state, &config, &wiki).await.unwrap();
+        assert_eq!((report.tiddlers, report.binaries), (1, 1));
+        let file = config.files_dir.join(crate::offload_file_name("Logo.png", "image/png"));
+        assert_eq!(std::fs::read(&file).unwrap(), b"old");
+
+        ds.write(|t| {
+            t.cxn
+                .execute_batch(
+                    "CREATE TRIGGER boom BEFORE INSERT ON tiddlers WHEN NEW.title = 'Boom'
+                     BEGIN SELECT RAISE(ABORT, 'boom'); END",
+                )
+                .map_err(|e| AppError::Database(e.to_string()))
+        })
+        .await
+        .unwrap();
+        std::fs::write(wiki.join("tiddlers/Logo.png"), b"new").unwrap();
+        std::fs::write(wiki.join("tiddlers/Photo.png"), b"photo").unwrap();
+        std::fs::write(wiki.join("tiddlers/Boom.tid"), "title: Boom\n\nboom").unwrap();
+
+        assert!(import_folder(&ds, &state, &config, &wiki).await.is_err());
+        assert_eq!(std::fs::read(&file).unwrap(), b"old");
+        assert_eq!(std::fs::read_dir(&config.files_dir).unwrap().count(), 1);
+        let logo = ds.read(|t| t.get("Logo.png")).await.unwrap().unwrap();
+        assert_eq!(logo.revision, 0);
+    }
+}
@@ -0,0 +1,407 @@
+//! 从单文件 TiddlyWiki (HTML) 导入条目 (`POST /api/import` 或 `import-html` 子命令)
+//!
+//! 支持 5.2 以后的 `tiddlywiki-tiddler-store` JSON 脚本块，以及更早的 `storeArea` div 格式。
+//! 服务器模板中已经存在的条目 (核心、模板自带的插件 / 主题) 和运行时状态条目会被跳过；
+//! 嵌入的 base64 图片与上传一样被转存。与已有标题冲突时按所选策略处理。
+
+use crate::{
+    AppConfig, AppError, AppResult, AppState, DataStore, ServerConfig, SharedTemplate, StagedFiles, Tiddler,
+    Tiddlers,
+    template::{self, STORE_MARKER},
+};
+use axum::{Extension, body::Bytes, extract, http::StatusCode, response::IntoResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::{collections::HashSet, path::Path, sync::Arc};
+
+// 只在浏览器运行时有意义的条目；核心和插件是否已由服务器提供，以模板中的标题为准
+const SKIPPED_PREFIXES: &[&str] = &["$:/state/", "$:/status/", "$:/temp/", "$:/info/"];
+const SKIPPED_TITLES: &[&str] = &["$:/StoryList", "$:/HistoryList", "$:/isEncrypted", "$:/Import"];
+
+/// 标题冲突时的处理方式
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ConflictPolicy {
+    /// 保留已有条目
+    #[default]
+    Skip,
+    /// 用导入的条目覆盖
+    Overwrite,
+    /// 以 `标题 (imported)` 的形式另存
+    Rename,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub(crate) struct ImportReport {
+    imported: Vec<String>,
+    overwritten: Vec<String>,
+    renamed: Vec<Renamed>,
+    // 与已有标题冲突而跳过的条目
+    conflicts: Vec<String>,
+    // 核心 / 影子条目等被忽略的数量
+    ignored: usize,
+    offloaded: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct Renamed {
+    from: String,
+    to: String,
+}
+
+fn html_unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(idx) = rest.find('&') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+        let entity_end = rest.find(';').filter(|&end| end <= 10);
+        let decoded = entity_end.and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, end + 1))
+        });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 解析开始标签中的属性，如 `<div title="x" tags="a b">`
+fn parse_attributes(tag: &str) -> Map<String, Value> {
+    let mut attrs = Map::new();
+    let mut rest = tag;
+    while let Some(eq) = rest.find("=\"") {
+        let name = rest[..eq].rsplit(char::is_whitespace).next().unwrap_or("").trim();
+        let value_start = eq + 2;
+        let Some(value_len) = rest[value_start..].find('"') else { break };
+        if !name.is_empty() {
+            attrs.insert(name.to_string(), Value::String(html_unescape(&rest[value_start..value_start + value_len])));
+        }
+        rest = &rest[value_start + value_len + 1..];
+    }
+    attrs
+}
+
+/// 旧版 `<div id="storeArea">` 格式
+fn parse_store_area(html: &str) -> Vec<Value> {
+    let Some(start) = html.find(r#"<div id="storeArea""#) else {
+        return Vec::new();
+    };
+    let mut tiddlers = Vec::new();
+    let mut rest = &html[start..];
+    // 跳过 storeArea 本身的开始标签
+    rest = rest.find('>').map_or("", |i| &rest[i + 1..]);
+    loop {
+        let trimmed = rest.trim_start();
+        let Some(after_open) = trimmed.strip_prefix("<div") else { break };
+        let Some(tag_end) = after_open.find('>') else { break };
+        let mut fields = parse_attributes(&after_open[..tag_end]);
+        let body = &after_open[tag_end + 1..];
+        let Some(close) = body.find("</div>") else { break };
+        let inner = &body[..close];
+        let text = match (inner.find("<pre>"), inner.rfind("</pre>")) {
+            (Some(open), Some(end)) if open < end => html_unescape(&inner[open + 5..end]),
+            _ => html_unescape(inner.trim()),
+        };
+        fields.insert("text".to_string(), Value::String(text));
+        if fields.contains_key("title") {
+            tiddlers.push(Value::Object(fields));
+        }
+        rest = &body[close + "</div>".len()..];
+    }
+    tiddlers
+}
+
+/// 从 HTML 中取出所有条目；同名条目以后出现的为准
+pub(crate) fn parse_html(html: &str) -> Result<Vec<Value>, String> {
+    if html.contains(r#"id="encryptedStoreArea""#) {
+        return Err("encrypted wikis are not supported, remove the password before importing".to_string());
+    }
+    let mut found = Vec::new();
+    let mut rest = html;
+    while let Some(idx) = rest.find(STORE_MARKER) {
+        let content = &rest[idx + STORE_MARKER.len()..];
+        let end = content.find("</script>").ok_or("unterminated tiddler store script")?;
+        let store: Vec<Value> = serde_json::from_str(&content[..end])
+            .map_err(|e| format!("tiddler store is not a valid JSON array: {}", e))?;
+        found.extend(store);
+        rest = &content[end..];
+    }
+    found.extend(parse_store_area(html));
+    if found.is_empty() {
+        return Err("no tiddler store found, is this a TiddlyWiki file?".to_string());
+    }
+
+    let mut seen = HashSet::new();
+    let mut tiddlers: Vec<Value> = found
+        .into_iter()
+        .rev()
+        .filter(|t| t.get("title").and_then(Value::as_str).is_some_and(|title| seen.insert(title.to_string())))
+        .collect();
+    tiddlers.reverse();
+    Ok(tiddlers)
+}
+
+fn is_ignored(title: &str, template_titles: &HashSet<String>) -> bool {
+    SKIPPED_TITLES.contains(&title)
+        || SKIPPED_PREFIXES.iter().any(|p| title.starts_with(p))
+        || template_titles.contains(title)
+}
+
+/// 条目最终写入的标题及其在报告中的归类
+enum Outcome {
+    Imported,
+    Overwritten,
+    Renamed(String),
+}
+
+/// 按冲突策略确定每个条目最终的标题；被跳过的标题记入 `conflicts`
+fn plan(
+    store: &Tiddlers,
+    values: Vec<Value>,
+    policy: ConflictPolicy,
+    conflicts: &mut Vec<String>,
+) -> AppResult<Vec<(Value, String, Outcome)>> {
+    // 本次导入用到的标题，改名时不能与它们重复
+    let mut taken: HashSet<String> =
+        values.iter().filter_map(|v| v.get("title")?.as_str().map(str::to_string)).collect();
+    let mut planned = Vec::new();
+    for value in values {
+        let title = value.get("title").and_then(Value::as_str).unwrap_or_default().to_string();
+        if store.get(&title)?.is_none() {
+            planned.push((value, title, Outcome::Imported));
+            continue;
+        }
+        match policy {
+            ConflictPolicy::Skip => conflicts.push(title),
+            ConflictPolicy::Overwrite => planned.push((value, title, Outcome::Overwritten)),
+            ConflictPolicy::Rename => {
+                let mut to = format!("{} (imported)", title);
+                let mut n = 2;
+                while taken.contains(&to) || store.get(&to)?.is_some() {
+                    to = format!("{} (imported {})", title, n);
+                    n += 1;
+                }
+                taken.insert(to.clone());
+                planned.push((value, to, Outcome::Renamed(title)));
+            }
+        }
+    }
+    Ok(planned)
+}
+
+/// 导入 HTML 中的条目
+pub(crate) async fn import_html(
+    ds: &DataStore,
+    state: &AppState,
+    config: &ServerConfig,
+    template_titles: HashSet<String>,
+    html: &str,
+    policy: ConflictPolicy,
+) -> AppResult<ImportReport> {
+    let mut values = parse_html(html).map_err(AppError::Serialization)?;
+    let mut report = ImportReport::default();
+    values.retain(|v| {
+        let ignored = is_ignored(v.get("title").and_then(Value::as_str).unwrap_or_default(), &template_titles);
+        report.ignored += usize::from(ignored);
+        !ignored
+    });
+    // 先确定最终标题，再以最终标题转存文件，避免改名的条目覆盖已有条目的文件
+    let (planned, conflicts) = ds
+        .read(move |store| {
+            let mut conflicts = Vec::new();
+            let planned = plan(store, values, policy, &mut conflicts)?;
+            Ok((planned, conflicts))
+        })
+        .await?;
+    report.conflicts = conflicts;
+
+    // 事务失败时据此删除新文件、恢复被覆盖的文件
+    let mut staged = StagedFiles::default();
+    let mut tiddlers = Vec::new();
+    for (mut value, title, outcome) in planned {
+        if let Some(map) = value.as_object_mut() {
+            // 导入的条目从新的 revision 开始
+            map.remove("revision");
+            map.remove("bag");
+            map.insert("title".to_string(), Value::String(title.clone()));
+        }
+        if staged.offload(config, state, &title, &mut value).await {
+            report.offloaded += 1;
+        }
+        tiddlers.push((Tiddler::from_value(value)?, outcome));
+    }
+
+    let result = ds
+        .write(move |store| {
+            store.transaction(|store| {
+                for (mut tiddler, outcome) in tiddlers {
+                    let existing = store.get(&tiddler.title)?;
+                    match outcome {
+                        Outcome::Overwritten => {
+                            tiddler.revision = existing.map_or(0, |t| t.revision + 1);
+                            report.overwritten.push(tiddler.title.clone());
+                        }
+                        // 确定标题之后又被别人创建了
+                        _ if existing.is_some() => {
+                            return Err(AppError::Response(format!(
+                                "'{}' was created during the import, please retry",
+                                tiddler.title
+                            )));
+                        }
+                        Outcome::Imported => report.imported.push(tiddler.title.clone()),
+                        Outcome::Renamed(from) => report.renamed.push(Renamed { from, to: tiddler.title.clone() }),
+                    }
+                    store.put(tiddler)?;
+                }
+                Ok(report)
+            })
+        })
+        .await;
+
+    staged.finish(result.is_ok()).await;
+    result
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ImportQuery {
+    #[serde(default)]
+    policy: ConflictPolicy,
+}
+
+// --- Handler: POST /api/import?policy=skip|overwrite|rename ，请求体为 HTML 文件 ---
+pub(crate) async fn import_handler(
+    Extension(ds): Extension<DataStore>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(config): Extension<ServerConfig>,
+    Extension(shared): Extension<SharedTemplate>,
+    extract::Query(query): extract::Query<ImportQuery>,
+    body: Bytes,
+) -> AppResult<axum::response::Response> {
+    let html = String::from_utf8_lossy(&body);
+    let template_titles = template::current(&shared).store_titles();
+    match import_html(&ds, &state, &config, template_titles, &html, query.policy).await {
+        Ok(report) => {
+            tracing::info!("Imported {} tiddler(s) from uploaded HTML", report.imported.len() + report.overwritten.len() + report.renamed.len());
+            Ok(axum::Json(report).into_response())
+        }
+        // 无法解析的文件是客户端错误
+        Err(AppError::Serialization(e)) => Ok((StatusCode::BAD_REQUEST, axum::Json(json!({ "error": e }))).into_response()),
+        Err(e) => Err(e),
+    }
+}
+
+/// `import-html` 子命令
+pub(crate) async fn run_cli(config: &AppConfig, file: &Path, policy: ConflictPolicy) -> Result<(), String> {
+    let html = std::fs::read(file).map_err(|e| format!("Failed to read {:?}: {}", file, e))?;
+    let html = String::from_utf8_lossy(&html);
+    let ds = crate::initialize_datastore(&config.server).map_err(|e| format!("Error initializing datastore: {:?}", e))?;
+    let template = template::load(&config.server).map_err(|e| format!("Error loading wiki template: {:?}", e))?;
+    let state = crate::build_app_state(config).await?;
+    let report = import_html(&ds, &state, &config.server, template.store_titles(), &html, policy)
+        .await
+        .map_err(|e| format!("Import failed: {:?}", e))?;
+    let summary = serde_json::to_string_pretty(&report).unwrap_or_default();
+    tracing::info!("Imported {:?}:\n{}", file, summary);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn titles(values: &[Value]) -> Vec<&str> {
+        values.iter().filter_map(|v| v.get("title")?.as_str()).collect()
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!(html_unescape("a &amp; b &lt;c&gt; &quot;d&quot; &apos;e&#39;"), "a & b <c> \"d\" 'e'");
+        assert_eq!(html_unescape("&#x4e2d;&#X6587;&#20013;&nbsp;"), "中文中\u{a0}");
+        // 无法识别的实体原样保留
+        assert_eq!(html_unescape("AT&T &bogus; &#xZZ; & &amp"), "AT&T &bogus; &#xZZ; & &amp");
+        assert_eq!(html_unescape("&#1114112;"), "&#1114112;");
+    }
+
+    #[test]
+    fn json_store() {
+        let html = format!(
+            "<html>{}[{{\"title\":\"A\",\"text\":\"1\"}},{{\"title\":\"B\"}},{{\"text\":\"no title\"}}]</script>\
+             {}[{{\"title\":\"A\",\"text\":\"2\"}}]</script></html>",
+            STORE_MARKER, STORE_MARKER
+        );
+        let values = parse_html(&html).unwrap();
+        // 同名条目以后出现的为准，顺序按最后一次出现
+        assert_eq!(titles(&values), ["B", "A"]);
+        assert_eq!(values[1]["text"], "2");
+    }
+
+    #[test]
+    fn store_area() {
+        let html = r#"<div id="storeArea" style="display:none;">
+<div title="First &amp; Foremost" tags="a [[b c]]" modified="20200101000000000">
+<pre>line &lt;1&gt;
+line 2</pre>
+</div>
+<div title="Legacy" created="20100101000000000">plain &quot;text&quot;</div>
+<div created="20100101000000000">untitled</div>
+</div>"#;
+        let values = parse_html(html).unwrap();
+        assert_eq!(titles(&values), ["First & Foremost", "Legacy"]);
+        assert_eq!(values[0]["tags"], "a [[b c]]");
+        assert_eq!(values[0]["text"], "line <1>\nline 2");
+        assert_eq!(values[1]["text"], "plain \"text\"");
+    }
+
+    #[test]
+    fn both_formats() {
+        let html = format!(
+            "{}[{{\"title\":\"Json\"}}]</script><div id=\"storeArea\"><div title=\"Div\"><pre>x</pre></div></div>",
+            STORE_MARKER
+        );
+        assert_eq!(titles(&parse_html(&html).unwrap()), ["Json", "Div"]);
+    }
+
+    #[test]
+    fn rejects_unusable_files() {
+        assert!(parse_html("<html><body>hello</body></html>").is_err());
+        assert!(parse_html(r#"<pre id="encryptedStoreArea">...</pre>"#).is_err());
+        assert!(parse_html(&format!("{}[not json]</script>", STORE_MARKER)).is_err());
+        assert!(parse_html(&format!("{}[]", STORE_MARKER)).is_err());
+    }
+
+    #[test]
+    fn ignored_titles() {
+        let template = HashSet::from(["$:/core".to_string(), "$:/plugins/tiddlywiki/markdown".to_string()]);
+        for title in ["$:/core", "$:/StoryList", "$:/state/popup", "$:/temp/search", "$:/plugins/tiddlywiki/markdown"] {
+            assert!(is_ignored(title, &template), "{}", title);
+        }
+        // 模板中没有的官方插件和对核心条目的修改都要导入
+        for title in ["Note", "$:/config/Thing", "$:/plugins/tiddlywiki/katex", "$:/core/ui/PageTemplate", "$:/languages/zh-Hans"] {
+            assert!(!is_ignored(title, &template), "{}", title);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! 链接索引：记录每个条目链接 / 嵌入了哪些条目、使用了哪些标签
+//!
+//! 索引在写入和删除条目时同步更新 (与写入本身使用同一个写连接)，
+//! 因此反向链接、缺失条目、孤立条目等查询不需要加载整个 Wiki。
+
+use crate::{AppError, AppResult, DataStore, Tiddler, wikitext};
+use axum::{Extension, extract};
+use rusqlite::Connection;
+use serde::Serialize;
+
+/// 创建 links 表的迁移版本 (`0003_links.sql`)；从更早的版本升级时需要重建索引
+pub(crate) const SCHEMA_VERSION: i64 = 3;
+
+const KIND_LINK: &str = "link";
+const KIND_TAG: &str = "tag";
+
+/// 用条目当前的内容替换它的出链
+pub(crate) fn index(cxn: &Connection, tiddler: &Tiddler) -> AppResult<()> {
+    unindex(cxn, &tiddler.title)?;
+    const INSERT: &str = "INSERT OR IGNORE INTO links (source, target, kind) VALUES (?1, ?2, ?3)";
+    let mut stmt = cxn.prepare_cached(INSERT).map_err(AppError::from)?;
+    let links = wikitext::links(tiddler).into_iter().map(|t| (t, KIND_LINK));
+    let tags = wikitext::tags(tiddler).into_iter().map(|t| (t, KIND_TAG));
+    for (target, kind) in links.chain(tags) {
+        stmt.execute(rusqlite::params![tiddler.title, target, kind])
+            .map_err(|e| AppError::Database(format!("Error indexing links of '{}': {}", tiddler.title, e)))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn unindex(cxn: &Connection, title: &str) -> AppResult<()> {
+    cxn.prepare_cached("DELETE FROM links WHERE source = ?1")
+        .and_then(|mut stmt| stmt.execute([title]))
+        .map_err(|e| AppError::Database(format!("Error removing links of '{}': {}", title, e)))?;
+    Ok(())
+}
+
+/// 为所有条目重建索引
+pub(crate) fn rebuild(cxn: &Connection) -> AppResult<()> {
+    let tx = cxn.unchecked_transaction().map_err(AppError::from)?;
+    tx.execute("DELETE FROM links", []).map_err(AppError::from)?;
+    let mut count = 0;
+    {
+        let mut stmt = tx.prepare("SELECT meta FROM tiddlers").map_err(AppError::from)?;
+        let mut rows = stmt.query([]).map_err(AppError::from)?;
+        while let Some(row) = rows.next().map_err(AppError::from)? {
+            let tiddler = Tiddler::from_value(row.get(0).map_err(AppError::from)?)?;
+            index(&tx, &tiddler)?;
+            count += 1;
+        }
+    }
+    tx.commit().map_err(AppError::from)?;
+    tracing::info!("Rebuilt link index for {} tiddler(s)", count);
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub(crate) struct LinkEntry {
+    title: String,
+    kind: String,
+    // 仅用于出链：目标条目不存在
+    #[serde(skip_serializing_if = "Option::is_none")]
+    missing: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct MissingEntry {
+    title: String,
+    referenced_by: Vec<String>,
+}
+
+fn query_entries(cxn: &Connection, sql: &str, title: &str, with_missing: bool) -> AppResult<Vec<LinkEntry>> {
+    let mut stmt = cxn.prepare_cached(sql).map_err(AppError::from)?;
+    let rows = stmt
+        .query_map([title], |r| {
+            Ok(LinkEntry {
+                title: r.get(0)?,
+                kind: r.get(1)?,
+                missing: if with_missing { Some(r.get(2)?) } else { None },
+            })
+        })
+        .map_err(AppError::from)?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(AppError::from)
+}
+
+// --- Handler: GET /api/links/backlinks/{title} ---
+pub(crate) async fn get_backlinks(
+    Extension(ds): Extension<DataStore>,
+    extract::Path(title): extract::Path<String>,
+) -> AppResult<axum::Json<Vec<LinkEntry>>> {
+    const SQL: &str = "SELECT source, kind FROM links WHERE target = ?1 ORDER BY source, kind";
+    let entries = ds.read(move |tiddlers| query_entries(&tiddlers.cxn, SQL, &title, false)).await?;
+    Ok(axum::Json(entries))
+}
+
+// --- Handler: GET /api/links/outgoing/{title} ---
+pub(crate) async fn get_outgoing(
+    Extension(ds): Extension<DataStore>,
+    extract::Path(title): extract::Path<String>,
+) -> AppResult<axum::Json<Vec<LinkEntry>>> {
+    const SQL: &str = r#"
+        SELECT l.target, l.kind, t.title IS NULL FROM links l
+        LEFT JOIN tiddlers t ON t.title = l.target
+        WHERE l.source = ?1 ORDER BY l.kind, l.target
+    "#;
+    let entries = ds.read(move |tiddlers| query_entries(&tiddlers.cxn, SQL, &title, true)).await?;
+    Ok(axum::Json(entries))
+}
+
+// --- Handler: GET /api/links/missing ，被链接但不存在的条目 (不含只作为标签使用的标题) ---
+pub(crate) async fn get_missing(Extension(ds): Extension<DataStore>) -> AppResult<axum::Json<Vec<MissingEntry>>> {
+    const SQL: &str = r#"
+        SELECT l.target, l.source FROM links l
+        LEFT JOIN tiddlers t ON t.title = l.target
+        WHERE l.kind = 'link' AND t.title IS NULL
+        ORDER BY l.target, l.source
+    "#;
+    let missing = ds
+        .read(|tiddlers| {
+            let mut stmt = tiddlers.cxn.prepare_cached(SQL).map_err(AppError::from)?;
+            let mut rows = stmt.query([]).map_err(AppError::from)?;
+            let mut missing: Vec<MissingEntry> = Vec::new();
+            while let Some(row) = rows.next().map_err(AppError::from)? {
+                let (target, source): (String, String) =
+                    (row.get(0).map_err(AppError::from)?, row.get(1).map_err(AppError::from)?);
+                match missing.last_mut() {
+                    Some(last) if last.title == target => last.referenced_by.push(source),
+                    _ => missing.push(MissingEntry { title: target, referenced_by: vec![source] }),
+                }
+            }
+            Ok(missing)
+        })
+        .await?;
+    Ok(axum::Json(missing))
+}
+
+// --- Handler: GET /api/links/orphans ，没有被任何条目链接或用作标签的非系统条目 ---
+pub(crate) async fn get_orphans(Extension(ds): Extension<DataStore>) -> AppResult<axum::Json<Vec<String>>> {
+    const SQL: &str = r#"
+        SELECT t.title FROM tiddlers t
+        WHERE substr(t.title, 1, 3) != '$:/'
+          AND NOT EXISTS (SELECT 1 FROM links l WHERE l.target = t.title AND l.source != t.title)
+        ORDER BY t.title
+    "#;
+    let orphans = ds
+        .read(|tiddlers| {
+            let mut stmt = tiddlers.cxn.prepare_cached(SQL).map_err(AppError::from)?;
+            let rows = stmt.query_map([], |r| r.get(0)).map_err(AppError::from)?;
+            rows.collect::<Result<Vec<String>, _>>().map_err(AppError::from)
+        })
+        .await?;
+    Ok(axum::Json(orphans))
+}
@@ -10,10 +10,10 @@
 //! [web server API]: https://tiddlywiki.com/#WebServer
 //! [SQLite]: https://sqlite.org/index.html
 
-use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
+use aws_config::BehaviorVersion;
 use aws_sdk_s3::{config::Credentials, config::Region, presigning::PresigningConfig, Client as S3Client};
 use axum::{
-    Extension, Router, extract::{self, DefaultBodyLimit, Request}, http::{StatusCode, header}, middleware::{self, Next}, response::Response, routing::{delete, get, post, put}
+    Extension, Router, extract::{self, DefaultBodyLimit, Request}, http::{StatusCode, header}, middleware::{self, Next}, response::Response, routing::{any, delete, get, post, put}
 };
 
 use axum::{
@@ -22,11 +22,8 @@ use axum::{
     response::{IntoResponse},
 };
 
-use axum::http::{HeaderValue, header::CONTENT_SECURITY_POLICY};
 use chrono::Local;
-use tower_http::set_header::SetResponseHeaderLayer; // 引入修改响应头的层
 use clap::Parser;
-use rusqlite::params;
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -38,28 +35,120 @@ use std::{
     time::Duration,
 };
 use tokio::fs;
-use tokio::sync::Mutex;
 use tower_http::{services::ServeDir, trace::TraceLayer};
-use tracing_subscriber::{EnvFilter, layer::{self, SubscriberExt}, util::SubscriberInitExt};
+use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
 use base64::{engine::general_purpose, Engine as _};
 use tower_http::compression::CompressionLayer;
 
 use rust_embed::RustEmbed;
 
+mod backup;
+mod batch;
+mod cache;
+mod changes;
+mod crypto;
+mod dav;
+mod db;
+mod events;
+mod export;
+mod feeds;
+mod filter;
+mod folder;
+mod importer;
+mod links;
+mod markdown;
+mod mirror;
+mod rename;
+mod render;
+mod share;
+mod store;
+mod template;
+#[cfg(test)]
+mod testutil;
+mod webhooks;
+mod wikitext;
+use backup::BackupConfig;
+use cache::Encoding;
+use crypto::{FileCipher, SseCustomerKey};
+use store::{Change, Store};
+use template::{SharedTemplate, WikiTemplate};
+
 #[derive(RustEmbed)]
 #[folder = "web/foliate-js/ebook_reader/"] // 编译时，Cargo 会去这个路径把文件打包进来
 struct FoliateAssets;
 
 
-type DataStore = Arc<Mutex<Tiddlers>>;
+type DataStore = Arc<Store>;
 
 // --- 配置结构定义 ---
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Path to the configuration file
-    #[arg(short, long, default_value = "config.toml")]
+    #[arg(short, long, default_value = "config.toml", global = true)]
     config: PathBuf,
+
+    /// Passphrase of the encrypted database (requires the `sqlcipher` feature)
+    #[arg(long, env = "TW_DB_PASSPHRASE", hide_env_values = true, global = true)]
+    db_passphrase: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Run the web server (default)
+    Serve,
+    /// Encrypt an existing plaintext database in place
+    EncryptDb,
+    /// Decrypt an encrypted database in place
+    DecryptDb,
+    /// Restore the database from a snapshot (stop the server first)
+    Restore {
+        /// Path to the snapshot file
+        snapshot: PathBuf,
+    },
+    /// Export the wiki as a standalone single-file HTML
+    ExportHtml {
+        /// Output file
+        output: PathBuf,
+        /// Embed offloaded files (local or S3) as base64
+        #[arg(long)]
+        inline_assets: bool,
+    },
+    /// Export all tiddlers as a TiddlyWiki Node.js wiki folder (.tid files)
+    ExportFolder {
+        /// Target wiki folder
+        dir: PathBuf,
+    },
+    /// Import tiddlers from a TiddlyWiki Node.js wiki folder
+    ImportFolder {
+        /// Wiki folder (or its tiddlers/ directory)
+        dir: PathBuf,
+    },
+    /// Export tiddlers selected by tag or filter as Markdown files with front matter
+    ExportMarkdown {
+        /// Output directory
+        dir: PathBuf,
+        /// Export tiddlers with this tag
+        #[arg(long, conflicts_with = "filter")]
+        tag: Option<String>,
+        /// Export tiddlers selected by a TiddlyWiki filter
+        #[arg(long)]
+        filter: Option<String>,
+        /// Front matter format
+        #[arg(long, value_enum, default_value = "yaml")]
+        front_matter: markdown::FrontMatter,
+    },
+    /// Import tiddlers from a single-file TiddlyWiki (HTML)
+    ImportHtml {
+        /// The wiki HTML file
+        file: PathBuf,
+        /// What to do when a title already exists
+        #[arg(long, value_enum, default_value = "skip")]
+        policy: importer::ConflictPolicy,
+    },
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -69,6 +158,18 @@ struct AppConfig {
     #[serde(default = "default_status_config")] 
     status: Status, 
     auth: Option<AuthConfig>, 
+    #[serde(default)]
+    encryption: Option<EncryptionConfig>,
+    #[serde(default)]
+    backup: BackupConfig,
+    #[serde(default)]
+    render: render::RenderConfig,
+    #[serde(default)]
+    feeds: feeds::FeedsConfig,
+    #[serde(default)]
+    git_mirror: mirror::GitMirrorConfig,
+    #[serde(default)]
+    webhooks: Vec<webhooks::WebhookConfig>,
 }
 
 fn default_status_config() -> Status {
@@ -114,9 +215,9 @@ impl Default for Space {
     }
 }
 
-// 定义版本号的默认值生成函数
+// 定义版本号的默认值生成函数：留空表示使用模板中 <meta name="tiddlywiki-version"> 的版本
 fn default_tw_version() -> String {
-    "5.3.8".to_string()
+    String::new()
 }
 
 
@@ -126,6 +227,59 @@ struct ServerConfig {
     port: u16,
     db_path: PathBuf,
     files_dir: PathBuf,
+    // 只读连接的最大数量 (写连接始终只有一个)
+    #[serde(default = "default_read_connections")]
+    read_connections: usize,
+    // 在内存中缓存预渲染的 Wiki 页面 (含压缩版本)
+    #[serde(default = "default_cache_wiki")]
+    cache_wiki: bool,
+    // 懒加载模式：页面中只嵌入不含正文的 skinny tiddler，正文按需获取
+    #[serde(default)]
+    lazy_load: LazyLoad,
+    // 从磁盘加载的 empty.html，用于不重新编译即可升级 TiddlyWiki；未设置时使用内置模板
+    #[serde(default)]
+    template_path: Option<PathBuf>,
+    // 存放数据库密码的文件 (也可以用 --db-passphrase / TW_DB_PASSPHRASE 提供)
+    #[serde(default)]
+    db_passphrase_file: Option<PathBuf>,
+    // 启动时解析出的数据库密码，不从配置文件读取
+    #[serde(skip)]
+    db_passphrase: Option<String>,
+}
+
+fn default_read_connections() -> usize {
+    4
+}
+
+fn default_cache_wiki() -> bool {
+    true
+}
+
+/// 页面中嵌入哪些 tiddler 的正文
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum LazyLoad {
+    /// 嵌入所有正文 (默认)
+    #[default]
+    Off,
+    /// 除插件外，所有 tiddler 都只嵌入 skinny 版本
+    All,
+    /// 系统 tiddler (`$:/`) 和插件保留正文，其余只嵌入 skinny 版本
+    Content,
+}
+
+impl LazyLoad {
+    fn is_skinny(&self, tiddler: &Tiddler) -> bool {
+        // 插件在启动时就要解包，必须完整嵌入
+        if tiddler.field("plugin-type").is_some() {
+            return false;
+        }
+        match self {
+            LazyLoad::Off => false,
+            LazyLoad::All => true,
+            LazyLoad::Content => !tiddler.is_system(),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -138,6 +292,21 @@ struct S3Config {
     region: String,
     bucket_name: String,
     public_url_base: String,
+    // SSE-C 客户密钥 (base64/hex)，设置后由服务器代理上传与下载
+    #[serde(default)]
+    sse_customer_key: Option<String>,
+    #[serde(default)]
+    sse_customer_key_file: Option<PathBuf>,
+}
+
+// 本地落盘文件的加密配置
+#[derive(Deserialize, Debug, Clone)]
+struct EncryptionConfig {
+    enable: bool,
+    #[serde(default)]
+    key: Option<String>,
+    #[serde(default)]
+    key_file: Option<PathBuf>,
 }
 
 // [新增] 账号密码结构
@@ -155,6 +324,8 @@ struct AppState {
     s3_client: Option<S3Client>, // 设为 Option，允许不启用 S3
     bucket_name: String,
     public_url_base: String,
+    sse_c: Option<SseCustomerKey>,       // 启用 SSE-C 时，S3 读写都经过服务器
+    file_cipher: Option<Arc<FileCipher>>, // 启用加密时，本地落盘文件会被加密
 }
 
 fn mime_to_ext(mime: &str) -> &str {
@@ -195,35 +366,6 @@ struct InboxRequest {
     tags: Option<String>,
 }
 
-// --- 预处理模板 ---
-
-#[derive(Clone)]
-struct WikiTemplate {
-    prefix: String,
-    suffix: String,
-}
-
-impl WikiTemplate {
-    fn new(html_content: &str) -> Self {
-        let store_marker = r#"<script class="tiddlywiki-tiddler-store" type="application/json">"#;
-        let start_tag_idx = html_content
-            .find(store_marker)
-            .expect("Invalid empty.html: missing store script tag");
-        let end_tag_idx = html_content[start_tag_idx..]
-            .find("</script>")
-            .map(|i| start_tag_idx + i)
-            .expect("Invalid empty.html: missing closing script tag");
-        let split_idx = html_content[..end_tag_idx]
-            .rfind(']')
-            .expect("Invalid empty.html: store content is not a valid JSON array");
-
-        Self {
-            prefix: html_content[..split_idx].to_string(),
-            suffix: html_content[split_idx..].to_string(),
-        }
-    }
-}
-
 // --- Handler: 获取 S3 预签名 URL ---
 async fn get_presigned_url(
     Extension(state): Extension<Arc<AppState>>,
@@ -235,8 +377,21 @@ async fn get_presigned_url(
 
     let mut hasher = sha2::Sha256::new();
     hasher.update(params.filename.as_bytes());
-    let ext = params.filename.split('.').last().unwrap_or("bin");
+    let ext = params.filename.split('.').next_back().unwrap_or("bin");
     let safe_key = format!("tiddlers/{}.{}", hex::encode(hasher.finalize()), ext);
+    let region = client.config().region().map(|r| r.as_ref()).unwrap_or("default").to_string();
+
+    // 启用 SSE-C 时不能把客户密钥交给浏览器，改为经由服务器代理上传
+    if state.sse_c.is_some() {
+        return Ok(axum::Json(PresignResponse {
+            upload_url: format!("/api/s3-upload/{}", safe_key),
+            public_url: format!("/files/s3/{}", safe_key),
+            name: state.s3_name.clone(),
+            key: safe_key,
+            bucket: state.bucket_name.clone(),
+            region,
+        }));
+    }
 
     let presigned_req = client
         .put_object()
@@ -250,8 +405,6 @@ async fn get_presigned_url(
     let upload_url = presigned_req.uri().to_string();
     let public_url = format!("{}/{}", state.public_url_base, safe_key);
 
-    let region = client.config().region().map(|r| r.as_ref()).unwrap_or("default").to_string();
-
     Ok(axum::Json(PresignResponse {
         upload_url,
         public_url,
@@ -262,6 +415,127 @@ async fn get_presigned_url(
     }))
 }
 
+// --- Handler: 经由服务器以 SSE-C 上传到 S3 ---
+async fn upload_s3_object(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(key): Path<String>,
+    headers: axum::http::HeaderMap,
+    body: axum::body::Bytes,
+) -> AppResult<StatusCode> {
+    let (client, sse) = match (&state.s3_client, &state.sse_c) {
+        (Some(c), Some(k)) => (c, k),
+        _ => return Err(AppError::Response("SSE-C upload is not enabled in configuration".to_string())),
+    };
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream");
+
+    client
+        .put_object()
+        .bucket(&state.bucket_name)
+        .key(&key)
+        .content_type(content_type)
+        .sse_customer_algorithm(SseCustomerKey::ALGORITHM)
+        .sse_customer_key(&sse.key_b64)
+        .sse_customer_key_md5(&sse.key_md5_b64)
+        .body(body.into())
+        .send()
+        .await
+        .map_err(|e| AppError::Response(format!("S3 upload failed: {}", e)))?;
+
+    tracing::info!("Uploaded S3 object with SSE-C: {}", key);
+    Ok(StatusCode::OK)
+}
+
+// --- Handler: /files/* ，透明解密本地文件，代理 SSE-C 对象 ---
+async fn serve_file(
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(config): Extension<ServerConfig>,
+    Path(path): Path<String>,
+    req: Request,
+) -> AppResult<Response> {
+    use tower::ServiceExt;
+
+    if let Some(key) = path.strip_prefix("s3/") {
+        return proxy_s3_object(&state, key).await;
+    }
+
+    if path.contains("..") || path.contains('\\') {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    if let Some(cipher) = &state.file_cipher {
+        let file_path = config.files_dir.join(&path);
+        let mut head = [0u8; crypto::MAGIC.len()];
+        let sealed = match fs::File::open(&file_path).await {
+            Ok(mut f) => {
+                use tokio::io::AsyncReadExt;
+                f.read_exact(&mut head).await.is_ok() && &head == crypto::MAGIC
+            }
+            Err(_) => false,
+        };
+
+        if sealed {
+            let data = fs::read(&file_path)
+                .await
+                .map_err(|e| AppError::Response(format!("Failed to read file: {}", e)))?;
+            let plain = cipher.open(&data).map_err(AppError::Response)?;
+            let mime = mime_guess::from_path(&path).first_or_octet_stream();
+            return Response::builder()
+                .header(header::CONTENT_TYPE, mime.as_ref())
+                .header(header::CACHE_CONTROL, "private, max-age=3600")
+                .body(Body::from(plain))
+                .map_err(|e| AppError::Response(format!("error building response: {}", e)));
+        }
+    }
+
+    // 明文文件交给 ServeDir 处理，保留 Range 等支持
+    let (mut parts, body) = req.into_parts();
+    parts.uri = format!("/{}", path)
+        .parse()
+        .map_err(|e| AppError::Response(format!("invalid file path: {}", e)))?;
+    let resp = ServeDir::new(&config.files_dir)
+        .oneshot(Request::from_parts(parts, body))
+        .await
+        .map_err(|e| AppError::Response(format!("error serving file: {}", e)))?;
+    Ok(resp.map(Body::new))
+}
+
+async fn proxy_s3_object(state: &AppState, key: &str) -> AppResult<Response> {
+    let (client, sse) = match (&state.s3_client, &state.sse_c) {
+        (Some(c), Some(k)) => (c, k),
+        _ => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+
+    let obj = client
+        .get_object()
+        .bucket(&state.bucket_name)
+        .key(key)
+        .sse_customer_algorithm(SseCustomerKey::ALGORITHM)
+        .sse_customer_key(&sse.key_b64)
+        .sse_customer_key_md5(&sse.key_md5_b64)
+        .send()
+        .await
+        .map_err(|e| AppError::Response(format!("S3 download failed: {}", e)))?;
+
+    let content_type = obj
+        .content_type()
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| mime_guess::from_path(key).first_or_octet_stream().to_string());
+    let data = obj
+        .body
+        .collect()
+        .await
+        .map_err(|e| AppError::Response(format!("S3 download failed: {}", e)))?
+        .into_bytes();
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CACHE_CONTROL, "private, max-age=3600")
+        .body(Body::from(data))
+        .map_err(|e| AppError::Response(format!("error building response: {}", e)))
+}
 
 // 处理 /foliate/* 的请求
 async fn static_handler(Path(path): Path<String>) -> impl IntoResponse {
@@ -314,7 +588,7 @@ async fn main() {
         }
     };
     
-    let config: AppConfig = match toml::from_str(&config_content) {
+    let mut config: AppConfig = match toml::from_str(&config_content) {
         Ok(c) => c,
         Err(e) => {
             tracing::error!("Failed to parse config file: {}", e);
@@ -324,45 +598,116 @@ async fn main() {
     
     tracing::info!("Configuration loaded from {:?}", args.config);
 
+    // 2.1 解析数据库密码：命令行 / 环境变量优先，其次是密码文件
+    config.server.db_passphrase = match (args.db_passphrase, &config.server.db_passphrase_file) {
+        (Some(p), _) => Some(p),
+        (None, Some(path)) => match std::fs::read_to_string(path) {
+            Ok(p) => Some(p.trim_end_matches(['\r', '\n']).to_string()),
+            Err(e) => {
+                tracing::error!("Failed to read database passphrase file {:?}: {}", path, e);
+                return;
+            }
+        },
+        (None, None) => None,
+    };
+
+    // 2.2 离线子命令
+    match args.command.unwrap_or(Command::Serve) {
+        Command::Serve => {}
+        Command::EncryptDb | Command::DecryptDb if config.server.db_passphrase.is_none() => {
+            tracing::error!("A database passphrase is required (--db-passphrase, TW_DB_PASSPHRASE or server.db_passphrase_file)");
+            return;
+        }
+        Command::EncryptDb => {
+            let pass = config.server.db_passphrase.as_deref().unwrap_or_default();
+            if let Err(e) = db::encrypt_database(&config.server.db_path, pass) {
+                tracing::error!("Failed to encrypt database: {:?}", e);
+            }
+            return;
+        }
+        Command::DecryptDb => {
+            let pass = config.server.db_passphrase.as_deref().unwrap_or_default();
+            if let Err(e) = db::decrypt_database(&config.server.db_path, pass) {
+                tracing::error!("Failed to decrypt database: {:?}", e);
+            }
+            return;
+        }
+        Command::Restore { snapshot } => {
+            if let Err(e) = backup::restore(&config.server, &snapshot) {
+                tracing::error!("Failed to restore database: {:?}", e);
+            }
+            return;
+        }
+        Command::ExportHtml { output, inline_assets } => {
+            if let Err(e) = export::run_cli(&config, &output, inline_assets).await {
+                tracing::error!("{}", e);
+            }
+            return;
+        }
+        Command::ExportFolder { dir } => {
+            if let Err(e) = folder::run_cli(&config, &dir, false).await {
+                tracing::error!("{}", e);
+            }
+            return;
+        }
+        Command::ImportFolder { dir } => {
+            if let Err(e) = folder::run_cli(&config, &dir, true).await {
+                tracing::error!("{}", e);
+            }
+            return;
+        }
+        Command::ExportMarkdown { dir, tag, filter, front_matter } => {
+            if let Err(e) = markdown::run_cli(&config, &dir, tag, filter, front_matter).await {
+                tracing::error!("{}", e);
+            }
+            return;
+        }
+        Command::ImportHtml { file, policy } => {
+            if let Err(e) = importer::run_cli(&config, &file, policy).await {
+                tracing::error!("{}", e);
+            }
+            return;
+        }
+    }
+
     // 3. 初始化数据库
-    let datastore = initialize_datastore(&config.server).expect("Error initializing datastore");
+    let datastore = match initialize_datastore(&config.server) {
+        Ok(ds) => ds,
+        Err(e) => {
+            tracing::error!("Error initializing datastore: {:?}", e);
+            return;
+        }
+    };
 
     // 4. 加载 HTML 模板
-    let empty_html_str = include_str!("../empty.html");
-    let template = Arc::new(WikiTemplate::new(empty_html_str));
+    let template: SharedTemplate = match template::load(&config.server) {
+        Ok(t) => Arc::new(std::sync::RwLock::new(Arc::new(t))),
+        Err(e) => {
+            tracing::error!("Error loading wiki template: {:?}", e);
+            return;
+        }
+    };
 
-    // 5. 初始化 S3 客户端 (如果启用)
-    let s3_client = if config.s3.enable {
-        let credentials = Credentials::new(
-            &config.s3.access_key,
-            &config.s3.secret_key,
-            None,
-            None,
-            "static_conf",
-        );
-        let region = Region::new(config.s3.region.clone());
-        let s3_config = aws_config::defaults(BehaviorVersion::latest())
-            .region(region)
-            .credentials_provider(credentials)
-            .endpoint_url(&config.s3.endpoint)
-            .load()
-            .await;
-        
-        tracing::info!("S3 client initialized for bucket: {}", config.s3.bucket_name);
-        Some(S3Client::new(&s3_config))
-    } else {
-        tracing::warn!("S3 integration is disabled in config");
-        None
+    // 5. 初始化 S3 客户端与加密密钥
+    let app_state = match build_app_state(&config).await {
+        Ok(state) => Arc::new(state),
+        Err(e) => {
+            tracing::error!("{}", e);
+            return;
+        }
     };
 
-    let app_state = Arc::new(AppState {
-        s3_name:config.s3.name.clone(),
-        s3_client,
-        bucket_name: config.s3.bucket_name.clone(),
-        public_url_base: config.s3.public_url_base.clone(),
-    });
+    if config.backup.enable {
+        backup::spawn_scheduler(config.server.clone(), config.backup.clone(), app_state.clone());
+    }
+    if config.git_mirror.enable {
+        mirror::spawn(datastore.clone(), config.git_mirror.clone(), config.status.username.clone());
+    }
+    if !config.webhooks.is_empty() {
+        webhooks::spawn(datastore.clone(), config.webhooks.clone());
+    }
+    let share_views = share::ViewCounter::spawn(datastore.clone());
 
-    let files_service = ServeDir::new(&config.server.files_dir);
     let addr = SocketAddr::from((config.server.bind, config.server.port));
 
     // 6. 构建路由
@@ -378,7 +723,33 @@ async fn main() {
         .route("/bags/efault/tiddlers/{title}", delete(delete_tiddler)) // 兼容旧客户端拼写错误
         .route("/api/sign-upload", get(get_presigned_url))
         .route("/api/inbox", post(add_inbox_item))
-        .nest_service("/files", files_service)
+        .route("/api/changes", get(changes::changes_since))
+        .route("/api/events", get(events::subscribe))
+        .route("/api/tiddlers/batch", post(batch::apply_batch))
+        .route("/api/tiddlers/{title}/rename", post(rename::rename_tiddler))
+        .route("/api/filter", get(filter::run_filter))
+        .route("/api/export/html", get(export::export_html))
+        // 旧的单文件 Wiki 往往比普通请求大得多
+        .route("/api/import", post(importer::import_handler).layer(DefaultBodyLimit::max(256 * 1024 * 1024)))
+        .route("/api/links/backlinks/{title}", get(links::get_backlinks))
+        .route("/api/links/outgoing/{title}", get(links::get_outgoing))
+        .route("/api/links/missing", get(links::get_missing))
+        .route("/api/links/orphans", get(links::get_orphans))
+        .route("/api/s3-upload/{*key}", put(upload_s3_object))
+        .route("/api/admin/backup", post(backup::backup_now))
+        .route("/api/admin/template", put(template::replace_template))
+        .route("/files/{*path}", get(serve_file))
+        .route("/render/{title}", get(render::render_tiddler))
+        .route("/api/shares", post(share::create_share).get(share::list_shares))
+        .route("/api/shares/{id}", delete(share::revoke_share))
+        .route("/api/webhooks/deliveries", get(webhooks::list_deliveries))
+        .route("/share/{token}", get(share::view_share))
+        .route("/share/{token}/{title}", get(share::view_shared_tiddler))
+        .route("/feeds/recent.atom", get(feeds::recent_feed))
+        .route("/feeds/tag/{file}", get(feeds::tag_feed))
+        .route("/dav", any(dav::dav_handler))
+        .route("/dav/", any(dav::dav_handler))
+        .route("/dav/{*path}", any(dav::dav_handler))
         // .nest_service("/foliate", epub_service)
         .route("/foliate/{*path}", get(static_handler)) 
         
@@ -386,12 +757,18 @@ async fn main() {
         .layer(Extension(config.server)) 
         .layer(Extension(template))
         .layer(Extension(app_state))
+        .layer(Extension(Arc::new(config.backup)))
+        .layer(Extension(Arc::new(config.feeds)))
+        .layer(Extension(Arc::new(dav::Scratch::default())))
+        .layer(Extension(Arc::new(render::PublicTitles::default())))
+        .layer(Extension(share_views))
         .layer(Extension(Arc::new(config.status)))
         .layer(DefaultBodyLimit::max(20 * 1024 * 1024))
         .layer(TraceLayer::new_for_http())
         .layer(CompressionLayer::new().gzip(true).br(true).zstd(true))
         .layer(middleware::from_fn(auth_middleware))
-        .layer(Extension(config.auth));
+        .layer(Extension(config.auth))
+        .layer(Extension(Arc::new(config.render)));
     tracing::info!("TiddlyWiki server listening on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(&addr)
@@ -400,6 +777,69 @@ async fn main() {
     axum::serve(listener, app).await.expect("Error serving app");
 }
 
+/// 初始化 S3 客户端 (如果启用) 并加载加密密钥
+async fn build_app_state(config: &AppConfig) -> Result<AppState, String> {
+    let s3_client = if config.s3.enable {
+        let credentials = Credentials::new(
+            &config.s3.access_key,
+            &config.s3.secret_key,
+            None,
+            None,
+            "static_conf",
+        );
+        let region = Region::new(config.s3.region.clone());
+        let s3_config = aws_config::defaults(BehaviorVersion::latest())
+            .region(region)
+            .credentials_provider(credentials)
+            .endpoint_url(&config.s3.endpoint)
+            .load()
+            .await;
+        
+        tracing::info!("S3 client initialized for bucket: {}", config.s3.bucket_name);
+        Some(S3Client::new(&s3_config))
+    } else {
+        tracing::warn!("S3 integration is disabled in config");
+        None
+    };
+
+    // 加载加密密钥 (如果启用)
+    let file_cipher = match &config.encryption {
+        Some(enc) if enc.enable => {
+            match crypto::load_key(enc.key.as_deref(), enc.key_file.as_deref()) {
+                Ok(key) => {
+                    tracing::info!("Encryption at rest enabled for offloaded files");
+                    Some(Arc::new(FileCipher::new(&key)))
+                }
+                Err(e) => return Err(format!("Invalid encryption key: {}", e)),
+            }
+        }
+        _ => None,
+    };
+
+    let sse_c = if config.s3.enable
+        && (config.s3.sse_customer_key.is_some() || config.s3.sse_customer_key_file.is_some())
+    {
+        match crypto::load_key(config.s3.sse_customer_key.as_deref(), config.s3.sse_customer_key_file.as_deref()) {
+            Ok(key) => {
+                tracing::info!("S3 SSE-C enabled, uploads will be proxied through the server");
+                Some(SseCustomerKey::new(&key))
+            }
+            Err(e) => return Err(format!("Invalid S3 SSE-C key: {}", e)),
+        }
+    } else {
+        None
+    };
+
+    Ok(AppState {
+        s3_name:config.s3.name.clone(),
+        s3_client,
+        bucket_name: config.s3.bucket_name.clone(),
+        public_url_base: config.s3.public_url_base.clone(),
+        sse_c,
+        file_cipher,
+    })
+}
+
 fn insert_default_data(str:&str,conn: &Connection) -> Result<(), AppError> {
     tracing::info!("Installing plugin...");
     let v: serde_json::Value = serde_json::from_str(str)
@@ -421,6 +861,11 @@ fn insert_default_data(str:&str,conn: &Connection) -> Result<(), AppError> {
         ":revision": tiddler.revision,
         ":meta": tiddler.meta,
     }).map_err(AppError::from)?;
+    // 与 Tiddlers::put 一样记录修改序号，增量同步才能拿到默认插件
+    conn.execute(
+        "INSERT OR REPLACE INTO changes (title, revision, deleted) VALUES (?1, ?2, 0)",
+        rusqlite::params![tiddler.title, tiddler.revision],
+    ).map_err(AppError::from)?;
     Ok(())
 }
 
@@ -437,12 +882,12 @@ fn initialize_datastore(config: &ServerConfig) -> AppResult<DataStore> {
     let db_exists = config.db_path.exists();
 
     // 打开数据库连接
-    let cxn = Connection::open(&config.db_path).map_err(AppError::from)?;
+    let mut cxn = db::open_connection(&config.db_path, config.db_passphrase.as_deref())?;
+    if config.db_passphrase.is_some() {
+        tracing::info!("Opened encrypted database");
+    }
 
-    // 只有在数据库不存在时才执行初始化
     if !db_exists {
-        const S3_PLUGIN_JSON: &str = include_str!("../s3_uploader_plugin.json");
-        const CPL_PLUGIN_JSON: &str = include_str!("../CPL-Repo.json");
         // 开启 WAL 模式
         cxn.execute_batch(r#"
                             PRAGMA journal_mode = WAL;
@@ -455,12 +900,19 @@ fn initialize_datastore(config: &ServerConfig) -> AppResult<DataStore> {
                             PRAGMA journal_size_limit = 33554432;
                             PRAGMA wal_checkpoint(TRUNCATE);"#)
             .map_err(AppError::from)?;
-        
-        // 执行初始化 SQL 脚本
-        let init_script = include_str!("./init.sql");
-        
-        cxn.execute_batch(init_script)
-            .map_err(|e| AppError::Database(format!("初始化数据库失败: {}", e)))?;
+    } else {
+        // journal_mode 是持久的；旧版本 encrypt-db / decrypt-db 生成的文件没有开启 WAL
+        cxn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))
+            .map_err(AppError::from)?;
+    }
+
+    // 每次启动都执行尚未应用的迁移
+    let previous_version = db::migrate(&mut cxn)?;
+
+    // 只有在数据库不存在时才安装默认插件
+    if !db_exists {
+        const S3_PLUGIN_JSON: &str = include_str!("../s3_uploader_plugin.json");
+        const CPL_PLUGIN_JSON: &str = include_str!("../CPL-Repo.json");
         insert_default_data(S3_PLUGIN_JSON,&cxn)?;
         insert_default_data(CPL_PLUGIN_JSON,&cxn)?;
         
@@ -468,8 +920,12 @@ fn initialize_datastore(config: &ServerConfig) -> AppResult<DataStore> {
     } else {
         tracing::info!("Use the existing database!")
     }
-    let tiddlers = Tiddlers { cxn };
-    Ok(Arc::new(Mutex::new(tiddlers)))
+    // 链接索引刚刚建立，为已有条目补建索引
+    if previous_version < links::SCHEMA_VERSION {
+        links::rebuild(&cxn)?;
+    }
+    let store = Store::new(cxn, config)?;
+    Ok(Arc::new(store))
 }
 
 // -----------------------------------------------------------------------------------
@@ -477,38 +933,63 @@ fn initialize_datastore(config: &ServerConfig) -> AppResult<DataStore> {
 
 async fn render_wiki(
     Extension(ds): Extension<DataStore>,
-    Extension(template): Extension<Arc<WikiTemplate>>,
+    Extension(template): Extension<SharedTemplate>,
+    headers: axum::http::HeaderMap,
 ) -> AppResult<axum::response::Response> {
     use axum::response::Response;
+    let template = template::current(&template);
 
-    let mut ds_lock = ds.lock().await;
-    let datastore = &mut *ds_lock;
-
-    let tiddlers: Vec<Tiddler> = datastore.all()?;
-    let db_json_values: Vec<serde_json::Value> = tiddlers.iter().map(|t| t.as_value()).collect();
-    let db_json_str = serde_json::to_string(&db_json_values)
-        .map_err(|e| AppError::Serialization(format!("error serializing db: {}", e)))?;
+    // 未启用缓存时，边读数据库边输出，避免在内存中保留整页
+    if !ds.caches_page() {
+        return Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/html")
+            .body(Body::from_stream(ds.stream_page(template)))
+            .map_err(|e| AppError::Response(format!("error building wiki: {}", e)));
+    }
 
-    let inner_json = &db_json_str[1..db_json_str.len() - 1];
-    let safe_json = inner_json.replace("</script>", "<\\/script>");
+    let page = ds.render_page(template).await?;
+
+    // 页面未变化时直接返回 304
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|tag| tag.trim() == page.etag));
+    if not_modified {
+        return Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(header::ETAG, &page.etag)
+            .body(Body::empty())
+            .map_err(|e| AppError::Response(format!("error building wiki: {}", e)));
+    }
 
-    let mut buffer = Vec::with_capacity(template.prefix.len() + safe_json.len() + template.suffix.len() + 1);
-    buffer.extend(template.prefix.as_bytes());
-    buffer.push(b',');
-    buffer.extend(safe_json.as_bytes());
-    buffer.extend(template.suffix.as_bytes());
+    // 使用缓存的压缩版本，CompressionLayer 会跳过已设置 Content-Encoding 的响应
+    let accept = headers.get(header::ACCEPT_ENCODING).and_then(|v| v.to_str().ok()).unwrap_or("");
+    let encoding = Encoding::negotiate(accept);
+    let etag = page.etag.clone();
+    let body = tokio::task::spawn_blocking(move || page.encoded(encoding))
+        .await
+        .map_err(|e| AppError::Response(format!("error compressing wiki: {}", e)))?;
 
-    Response::builder()
+    let mut builder = Response::builder()
         .status(StatusCode::OK)
         .header("Content-Type", "text/html")
-        .body(axum::body::Body::from(buffer))
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, "no-cache");
+    if let Some(value) = encoding.header_value() {
+        builder = builder
+            .header(header::CONTENT_ENCODING, value)
+            .header(header::VARY, "Accept-Encoding");
+    }
+    builder
+        .body(Body::from(body))
         .map_err(|e| AppError::Response(format!("error building wiki: {}", e)))
 }
 
 async fn all_tiddlers(Extension(ds): Extension<DataStore>) -> AppResult<axum::Json<Vec<serde_json::Value>>> {
-    let mut lock = ds.lock().await;
-    let tiddlers = &mut *lock;
-    let all: Vec<serde_json::Value> = tiddlers.all()?.iter().map(|t| t.as_skinny_value()).collect();
+    let all = ds.read(|tiddlers| {
+        Ok(tiddlers.all()?.iter().map(|t| t.as_skinny_value()).collect::<Vec<_>>())
+    }).await?;
     Ok(axum::Json(all))
 }
 
@@ -516,10 +997,8 @@ async fn get_tiddler(
     Extension(ds): Extension<DataStore>,
     extract::Path(title): extract::Path<String>,
 ) -> AppResult<axum::http::Response<String>> {
-    let mut lock = ds.lock().await;
-    let tiddlers = &mut *lock;
-
-    if let Some(t) = tiddlers.get(&title)? {
+    let lookup = title.clone();
+    if let Some(t) = ds.read(move |tiddlers| tiddlers.get(&lookup)).await? {
         let body = serde_json::to_string_pretty(&t.as_value())
             .map_err(|e| AppError::Serialization(format!("error serializing tiddler: {}", e)))?;
         axum::response::Response::builder()
@@ -541,10 +1020,8 @@ async fn delete_tiddler(
     Extension(config): Extension<ServerConfig>,
     extract::Path(title): extract::Path<String>,
 ) -> AppResult<axum::response::Response<String>> {
-    let mut lock = ds.lock().await;
-    let tiddlers = &mut *lock;
-    let deleted_tiddler = tiddlers.pop(&title)?;
-    drop(lock);
+    let target = title.clone();
+    let deleted_tiddler = ds.write(move |tiddlers| tiddlers.pop(&target)).await?;
     // tiddlers.pop(&title)?;
     // 如果成功删除了条目，检查是否有关联文件需要删除
     if let Some(tiddler) = deleted_tiddler {
@@ -635,17 +1112,15 @@ async fn try_delete_associated_file(tiddler: Tiddler, state: Arc<AppState>, conf
     // === 分支 C: 兼容旧数据 (Legacy) ===
     // 如果没有 _file_storage 字段，回退到基于 _canonical_uri 解析的逻辑
     
-    if uri.starts_with("/files/") {
+    if let Some(filename) = uri.strip_prefix("/files/") {
         // ... (原有的本地文件删除逻辑) ...
-        let filename = &uri["/files/".len()..];
         if filename.contains("..") || filename.contains('/') || filename.contains('\\') { return; }
         let file_path = config.files_dir.join(filename);
         let _ = fs::remove_file(&file_path).await;
         tracing::info!("Deleted local file (Legacy detection): {:?}", file_path);
     } 
-    else if state.s3_client.is_some() && uri.starts_with(&state.public_url_base) {
+    else if let Some(client) = &state.s3_client && uri.starts_with(&state.public_url_base) {
         // ... (原有的 S3 删除逻辑，依赖 config.toml 中的 public_url_base) ...
-        let client = state.s3_client.as_ref().unwrap();
         let mut key = &uri[state.public_url_base.len()..];
         if key.starts_with('/') { key = &key[1..]; }
         
@@ -660,66 +1135,135 @@ async fn try_delete_associated_file(tiddler: Tiddler, state: Arc<AppState>, conf
     }
 }
 
+/// 把二进制 tiddler 的 base64 内容写到 `files_dir`，tiddler 中只保留 `_canonical_uri`。
+/// 启用加密时，文件以信封加密的形式落盘。
+/// 转存文件名：标题的 SHA-256 加上由 MIME 类型决定的扩展名
+fn offload_file_name(title: &str, mime: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(title.as_bytes());
+    format!("{}.{}", hex::encode(hasher.finalize()), mime_to_ext(mime))
+}
+
+async fn offload_binary(config: &ServerConfig, state: &AppState, title: &str, v: &mut Value) {
+    let mime = v.get("type").and_then(|t| t.as_str()).unwrap_or("");
+    let is_binary = mime.starts_with("image/") || mime == "application/pdf" || mime.starts_with("video/") || mime.starts_with("audio/");
+    if !is_binary {
+        return;
+    }
+
+    let base64_str = match v.get("text").and_then(|t| t.as_str()) {
+        Some(s) if !s.is_empty() => s,
+        _ => return,
+    };
+    let clean_b64 = match base64_str.find(',') {
+        Some(idx) => &base64_str[idx + 1..],
+        None => base64_str,
+    };
+    let data = match general_purpose::STANDARD.decode(clean_b64) {
+        Ok(d) => d,
+        Err(_) => return,
+    };
+
+    let filename = offload_file_name(title, mime);
+    let file_path = config.files_dir.join(&filename);
+
+    let data = match &state.file_cipher {
+        Some(cipher) => match cipher.seal(&data) {
+            Ok(sealed) => sealed,
+            Err(e) => {
+                tracing::error!("Failed to encrypt file for '{}': {}", title, e);
+                return;
+            }
+        },
+        None => data,
+    };
+
+    if let Err(e) = fs::write(&file_path, &data).await {
+        tracing::error!("Failed to write file to disk: {}", e);
+        return;
+    }
+    if let Some(obj) = v.as_object_mut() {
+        obj.insert("text".to_string(), Value::String(String::new()));
+        obj.insert("_canonical_uri".to_string(), Value::String(format!("/files/{}", filename)));
+        obj.insert("_file_storage".to_string(), Value::String("local".to_string()));
+        tracing::info!("Offloaded binary file for '{}' to {}", title, file_path.display());
+    }
+}
+
+/// 在事务之前转存的文件：事务失败时删除新写入的文件，并恢复被覆盖的旧文件
+#[derive(Default)]
+pub(crate) struct StagedFiles {
+    written: Vec<PathBuf>,
+    // (备份, 原路径)
+    backups: Vec<(PathBuf, PathBuf)>,
+}
+
+impl StagedFiles {
+    /// 与 [`offload_binary`] 相同，但先把同名的旧文件移到一旁；返回是否写入了文件
+    pub(crate) async fn offload(&mut self, config: &ServerConfig, state: &AppState, title: &str, v: &mut Value) -> bool {
+        let had_text = v.get("text").and_then(Value::as_str).is_some_and(|t| !t.is_empty());
+        let mime = v.get("type").and_then(Value::as_str).unwrap_or_default();
+        let path = config.files_dir.join(offload_file_name(title, mime));
+        let backup = db::sibling_path(&path, "bak");
+        // 本批次写过的文件不是旧文件，不能覆盖已有的备份
+        let first = !self.written.contains(&path);
+        let backed_up = first && path.exists() && fs::rename(&path, &backup).await.is_ok();
+
+        offload_binary(config, state, title, v).await;
+        let offloaded = had_text && v.get("text").and_then(Value::as_str).is_some_and(str::is_empty);
+        if offloaded && first {
+            self.written.push(path.clone());
+        }
+        if backed_up {
+            if offloaded {
+                self.backups.push((backup, path));
+            } else {
+                let _ = fs::rename(&backup, &path).await;
+            }
+        }
+        offloaded
+    }
+
+    /// 事务结束后调用：成功时删除备份，失败时还原
+    pub(crate) async fn finish(self, committed: bool) {
+        if committed {
+            for (backup, _) in self.backups {
+                let _ = fs::remove_file(backup).await;
+            }
+            return;
+        }
+        for path in self.written {
+            let _ = fs::remove_file(path).await;
+        }
+        for (backup, path) in self.backups {
+            if let Err(e) = fs::rename(&backup, &path).await {
+                tracing::error!("Failed to restore {:?} from {:?}: {}", path, backup, e);
+            }
+        }
+    }
+}
+
 async fn put_tiddler(
     Extension(ds): Extension<DataStore>,
     Extension(config): Extension<ServerConfig>, // 注意这里改成了 ServerConfig
+    Extension(state): Extension<Arc<AppState>>,
     extract::Path(title): extract::Path<String>,
     extract::Json(mut v): extract::Json<serde_json::Value>,
 ) -> AppResult<axum::http::Response<String>> {
     use axum::http::response::Response;
 
-    let is_binary = if let Some(type_val) = v.get("type") {
-        let t = type_val.as_str().unwrap_or("");
-        t.starts_with("image/") || t == "application/pdf" || t.starts_with("video/") || t.starts_with("audio/")
-    } else {
-        false
-    };
-
-    if is_binary {
-        if let Some(text_val) = v.get("text") {
-            if let Some(base64_str) = text_val.as_str() {
-                if !base64_str.is_empty() {
-                    let clean_b64 = if let Some(idx) = base64_str.find(",") {
-                        &base64_str[idx + 1..]
-                    } else {
-                        base64_str
-                    };
-
-                    if let Ok(data) = general_purpose::STANDARD.decode(clean_b64) {
-                        let mut hasher = Sha256::new();
-                        hasher.update(title.as_bytes());
-                        let safe_filename = hex::encode(hasher.finalize());
-                        let mime = v.get("type").and_then(|t| t.as_str()).unwrap_or("");
-                        let ext = mime_to_ext(mime);
-                        let filename = format!("{}.{}", safe_filename, ext);
-                        let file_path = config.files_dir.join(&filename);
-
-                        if let Err(e) = fs::write(&file_path, &data).await {
-                            tracing::error!("Failed to write file to disk: {}", e);
-                        } else {
-                            if let Some(obj) = v.as_object_mut() {
-                                obj.insert("text".to_string(), serde_json::Value::String("".to_string()));
-                                let uri = format!("/files/{}", filename);
-                                obj.insert("_canonical_uri".to_string(), serde_json::Value::String(uri));
-                                obj.insert("_file_storage".to_string(), serde_json::Value::String("local".to_string()));
-                                tracing::info!("Offloaded binary file for '{}' to {}", title, file_path.display());
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
+    offload_binary(&config, &state, &title, &mut v).await;
 
     let mut new_tiddler = Tiddler::from_value(v)?;
-    let mut lock = ds.lock().await;
-    let tiddlers = &mut *lock;
-
-    if let Some(_old_tiddler) = tiddlers.pop(&title)? {
-        new_tiddler.revision += 1;
-    }
-    let new_revision = new_tiddler.revision;
-    tiddlers.put(new_tiddler)?;
+    let target = title.clone();
+    let new_revision = ds.write(move |tiddlers| {
+        if tiddlers.get(&target)?.is_some() {
+            new_tiddler.revision += 1;
+        }
+        let new_revision = new_tiddler.revision;
+        tiddlers.put(new_tiddler)?;
+        Ok(new_revision)
+    }).await?;
     
     Response::builder()
         .status(StatusCode::NO_CONTENT)
@@ -732,9 +1276,15 @@ async fn put_tiddler(
 // Models
 pub(crate) struct Tiddlers {
     cxn: rusqlite::Connection,
+    // 本次写操作产生的修改，由 Store 在写操作结束后取走 (用于更新缓存等)
+    pending: Vec<Change>,
 }
 
 impl Tiddlers {
+    pub(crate) fn new(cxn: rusqlite::Connection) -> Self {
+        Self { cxn, pending: Vec::new() }
+    }
+
     pub(crate) fn all(&self) -> AppResult<Vec<Tiddler>> {
         // 将 debug 改为 trace 减少刷屏
         tracing::trace!("Retrieving all tiddlers"); 
@@ -751,6 +1301,18 @@ impl Tiddlers {
         Ok(tiddlers)
     }
 
+    /// 逐行遍历所有 tiddler，不会一次性把整个数据库读入内存
+    pub(crate) fn for_each(&self, mut f: impl FnMut(Tiddler) -> AppResult<()>) -> AppResult<()> {
+        const GET: &str = r#"SELECT title, revision, meta FROM tiddlers"#;
+        let mut stmt = self.cxn.prepare_cached(GET).map_err(AppError::from)?;
+        let mut rows = stmt.query([]).map_err(AppError::from)?;
+        while let Some(row) = rows.next().map_err(AppError::from)? {
+            let raw = row.get::<usize, serde_json::Value>(2).map_err(AppError::from)?;
+            f(Tiddler::from_value(raw)?)?;
+        }
+        Ok(())
+    }
+
     pub(crate) fn get(&self, title: &str) -> AppResult<Option<Tiddler>> {
         use rusqlite::OptionalExtension;
         tracing::debug!("getting tiddler: {}", title);
@@ -776,6 +1338,18 @@ impl Tiddlers {
             ":revision": tiddler.revision,
             ":meta": tiddler.meta,
         })?;
+        self.record_change(&tiddler.title, Some(tiddler.revision), false)?;
+        links::index(&self.cxn, &tiddler)?;
+        self.pending.push(Change::Put(tiddler));
+        Ok(())
+    }
+
+    /// 与 `put` 相同，但只发布一个 Inbox 事件
+    pub(crate) fn put_inbox(&mut self, tiddler: Tiddler) -> AppResult<()> {
+        self.put(tiddler)?;
+        if let Some(Change::Put(t)) = self.pending.pop() {
+            self.pending.push(Change::Inbox(t));
+        }
         Ok(())
     }
 
@@ -786,8 +1360,30 @@ impl Tiddlers {
         let mut stmt = self.cxn.prepare(DELETE).map_err(|e| AppError::Database(format!("Error preparing {}: {}", DELETE, e)))?;
         stmt.execute(rusqlite::named_params! { ":title": title })
             .map_err(|e| AppError::Database(format!("Error removing tiddler: {}", e)))?;
+        if let Some(t) = &result {
+            self.record_change(title, Some(t.revision), true)?;
+            links::unindex(&self.cxn, title)?;
+            self.pending.push(Change::Delete(t.clone()));
+        }
         Ok(result)
     }
+
+    /// 在一个事务中执行 `f`：`f` 返回错误时回滚全部修改
+    pub(crate) fn transaction<T>(&mut self, f: impl FnOnce(&mut Self) -> AppResult<T>) -> AppResult<T> {
+        self.cxn.execute_batch("BEGIN IMMEDIATE").map_err(AppError::from)?;
+        let result = f(self).and_then(|v| {
+            self.cxn.execute_batch("COMMIT").map_err(AppError::from)?;
+            Ok(v)
+        });
+        if result.is_err() {
+            if let Err(e) = self.cxn.execute_batch("ROLLBACK") {
+                tracing::error!("Failed to roll back transaction: {}", e);
+            }
+            // 回滚后这些修改并未生效
+            self.pending.clear();
+        }
+        result
+    }
 }
 
 #[derive(Clone, Serialize, Debug)]
@@ -827,6 +1423,38 @@ impl Tiddler {
         meta
     }
 
+    /// 嵌入页面用的 skinny tiddler，`_is_skinny` 标记让 TiddlyWiki 在需要时通过 syncer 懒加载正文
+    pub(crate) fn as_embedded_skinny_value(&self) -> Value {
+        let mut value = self.as_skinny_value();
+        if let Value::Object(map) = &mut value {
+            map.insert("_is_skinny".to_string(), Value::String(String::new()));
+        }
+        value
+    }
+
+    /// 读取字段，字段可能在顶层，也可能在 'fields' 对象里
+    pub(crate) fn field(&self, key: &str) -> Option<&str> {
+        self.meta
+            .get(key)
+            .or_else(|| self.meta.get("fields").and_then(|f| f.get(key)))
+            .and_then(|v| v.as_str())
+    }
+
+    /// 设置字段；字段原本位于 `fields` 中时仍写回 `fields`
+    pub(crate) fn set_field(&mut self, key: &str, value: Value) {
+        if let Some(Value::Object(fields)) = self.meta.get_mut("fields")
+            && fields.contains_key(key)
+        {
+            fields.insert(key.to_string(), value);
+        } else if let Value::Object(map) = &mut self.meta {
+            map.insert(key.to_string(), value);
+        }
+    }
+
+    pub(crate) fn is_system(&self) -> bool {
+        self.title.starts_with("$:/")
+    }
+
     pub(crate) fn as_skinny_value(&self) -> Value {
         let meta = self.as_value();
         if let Value::Object(mut map) = meta {
@@ -858,9 +1486,17 @@ impl Tiddler {
 
 // -----------------------------------------------------------------------------------
 
-async fn status(Extension(status_config): Extension<Arc<Status>>) -> axum::Json<Status> {
+async fn status(
+    Extension(status_config): Extension<Arc<Status>>,
+    Extension(template): Extension<SharedTemplate>,
+) -> axum::Json<Status> {
     // axum::Json(STATUS)
-    axum::Json(status_config.as_ref().clone())
+    let mut status = status_config.as_ref().clone();
+    // 未配置版本号时使用模板中的版本
+    if status.tiddlywiki_version.is_empty() {
+        status.tiddlywiki_version = template::current(&template).version.clone().unwrap_or_default();
+    }
+    axum::Json(status)
 }
 
 // -----------------------------------------------------------------------------------
@@ -894,15 +1530,30 @@ impl From<rusqlite::Error> for AppError {
     }
 }
 
+/// 请求是否通过了认证；由 `auth_middleware` 写入请求扩展，供允许匿名访问的接口判断权限
+#[derive(Clone, Copy)]
+pub(crate) struct Authenticated(pub(crate) bool);
+
+/// 未认证时也放行的路径，由对应的 handler 自行检查权限
+fn allows_anonymous(path: &str, render: &render::RenderConfig) -> bool {
+    path.starts_with("/render/") && render.public_filter.is_some()
+        || path.starts_with("/share/")
+        || path.starts_with("/feeds/")
+}
+
 async fn auth_middleware(
     Extension(auth_config): Extension<Option<AuthConfig>>,
-    req: Request,
+    Extension(render_config): Extension<Arc<render::RenderConfig>>,
+    mut req: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
     // 1. 如果配置中没有 auth 部分，直接放行 (允许无密码运行)
     let auth = match auth_config {
         Some(config) => config,
-        None => return Ok(next.run(req).await),
+        None => {
+            req.extensions_mut().insert(Authenticated(true));
+            return Ok(next.run(req).await);
+        }
     };
 
     // 2. 获取请求头中的 Authorization
@@ -912,19 +1563,21 @@ async fn auth_middleware(
         .and_then(|h| h.strip_prefix("Basic "));
 
     // 3. 验证账号密码
-    if let Some(encoded) = auth_header {
-        // 解码 Base64
-        if let Ok(decoded) = general_purpose::STANDARD.decode(encoded) {
-            if let Ok(creds) = String::from_utf8(decoded) {
-                // 格式通常是 "username:password"
-                if let Some((u, p)) = creds.split_once(':') {
-                    if u == auth.username && p == auth.password {
-                        // 验证通过，继续处理请求
-                        return Ok(next.run(req).await);
-                    }
-                }
-            }
-        }
+    if let Some(encoded) = auth_header
+        && let Ok(decoded) = general_purpose::STANDARD.decode(encoded)
+        && let Ok(creds) = String::from_utf8(decoded)
+        // 格式通常是 "username:password"
+        && let Some((u, p)) = creds.split_once(':')
+        && u == auth.username && p == auth.password
+    {
+        // 验证通过，继续处理请求
+        req.extensions_mut().insert(Authenticated(true));
+        return Ok(next.run(req).await);
+    }
+
+    if allows_anonymous(req.uri().path(), &render_config) {
+        req.extensions_mut().insert(Authenticated(false));
+        return Ok(next.run(req).await);
     }
 
     // 4. 验证失败或未提供 Header，返回 401 并触发浏览器弹窗
@@ -943,9 +1596,6 @@ async fn add_inbox_item(
     Extension(ds): Extension<DataStore>,
     extract::Json(payload): extract::Json<InboxRequest>,
 ) -> AppResult<axum::Json<serde_json::Value>> {
-    let mut lock = ds.lock().await;
-    let tiddlers = &mut *lock;
-
     // 1. 获取当前时间
     let now = Local::now();
     
@@ -976,7 +1626,7 @@ async fn add_inbox_item(
     // 6. 存入数据库
     // 我们复用已有的 Tiddler::from_value 方法进行转换和校验
     let tiddler = Tiddler::from_value(tiddler_json)?;
-    tiddlers.put(tiddler)?;
+    ds.write(move |tiddlers| tiddlers.put_inbox(tiddler)).await?;
 
     tracing::info!("📥 Inbox captured: {}", title);
 
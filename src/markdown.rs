@@ -0,0 +1,533 @@
+//! 导出为 Markdown，供 Hugo / Zola 等静态网站生成器使用 (`export-markdown` 子命令)
+//!
+//! 按标签或过滤器选出条目，每个条目写成一个带 front matter 的 `.md` 文件。
+//! 常用的 wikitext 语法 (标题、列表、粗体 / 斜体、链接、代码块、图片、表格) 会被转换为 Markdown，
+//! 引用到的图片和选中的二进制条目被复制到 `assets/` 目录。
+
+use crate::{
+    AppConfig, AppError, AppResult, AppState, DataStore, ServerConfig, Tiddler, export,
+    filter::Wiki,
+    folder::{extension_for, is_binary_type},
+    wikitext,
+};
+use base64::{Engine as _, engine::general_purpose};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+/// front matter 格式
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub(crate) enum FrontMatter {
+    /// `---` 包裹的 YAML (Hugo 的布局：tags 和自定义字段位于顶层)
+    Yaml,
+    /// `+++` 包裹的 TOML (Zola 的布局：`[taxonomies]` 和 `[extra]`)
+    Toml,
+}
+
+// 不写入 front matter 的字段：正文、单独处理的字段以及服务器维护的字段
+const SKIPPED_FIELDS: &[&str] = &[
+    "text", "title", "tags", "created", "modified", "type", "revision", "bag",
+    "_canonical_uri", "_file_storage", "_s3_key", "_s3_bucket", "_is_skinny",
+];
+
+/// 把标题转换为 URL 友好的文件名，并避免重名
+fn slug(title: &str, used: &mut HashSet<String>) -> String {
+    let mut slug = String::new();
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    let slug = if slug.is_empty() { "untitled" } else { slug };
+    let mut candidate = slug.to_string();
+    let mut n = 2;
+    while !used.insert(candidate.clone()) {
+        candidate = format!("{}-{}", slug, n);
+        n += 1;
+    }
+    candidate
+}
+
+/// TiddlyWiki 的日期 (`YYYYMMDDHHMMSSmmm`，UTC) 转为 RFC 3339
+fn rfc3339(value: &str) -> Option<String> {
+    let date = chrono::NaiveDateTime::parse_from_str(value.get(..14)?, "%Y%m%d%H%M%S").ok()?;
+    Some(date.and_utc().format("%Y-%m-%dT%H:%M:%SZ").to_string())
+}
+
+fn key(name: &str) -> String {
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        name.to_string()
+    } else {
+        quote(name)
+    }
+}
+
+// JSON 字符串同时也是合法的 YAML 双引号字符串和 TOML 基本字符串
+fn quote(value: &str) -> String {
+    Value::String(value.to_string()).to_string()
+}
+
+fn quote_list(items: &[String]) -> String {
+    format!("[{}]", items.iter().map(|t| quote(t)).collect::<Vec<_>>().join(", "))
+}
+
+fn front_matter(tiddler: &Tiddler, format: FrontMatter) -> String {
+    let fields = match tiddler.as_value() {
+        Value::Object(map) => map,
+        _ => Default::default(),
+    };
+    let date = tiddler.field("created").and_then(rfc3339);
+    let updated = tiddler.field("modified").and_then(rfc3339);
+    let tags = wikitext::tags(tiddler);
+    let mut extra: Vec<(&String, String)> = fields
+        .iter()
+        .filter(|(k, _)| !SKIPPED_FIELDS.contains(&k.as_str()))
+        .map(|(k, v)| (k, v.as_str().map_or_else(|| v.to_string(), str::to_string)))
+        .collect();
+    extra.sort();
+
+    let mut out = String::new();
+    match format {
+        FrontMatter::Yaml => {
+            out.push_str("---\n");
+            out.push_str(&format!("title: {}\n", quote(&tiddler.title)));
+            if let Some(date) = date {
+                out.push_str(&format!("date: {}\n", date));
+            }
+            if let Some(updated) = updated {
+                out.push_str(&format!("lastmod: {}\n", updated));
+            }
+            if !tags.is_empty() {
+                out.push_str(&format!("tags: {}\n", quote_list(&tags)));
+            }
+            for (k, v) in extra {
+                out.push_str(&format!("{}: {}\n", key(k), quote(&v)));
+            }
+            out.push_str("---\n\n");
+        }
+        FrontMatter::Toml => {
+            out.push_str("+++\n");
+            out.push_str(&format!("title = {}\n", quote(&tiddler.title)));
+            // TOML 的日期时间不加引号
+            if let Some(date) = date {
+                out.push_str(&format!("date = {}\n", date));
+            }
+            if let Some(updated) = updated {
+                out.push_str(&format!("updated = {}\n", updated));
+            }
+            if !tags.is_empty() {
+                out.push_str(&format!("\n[taxonomies]\ntags = {}\n", quote_list(&tags)));
+            }
+            if !extra.is_empty() {
+                out.push_str("\n[extra]\n");
+                for (k, v) in extra {
+                    out.push_str(&format!("{} = {}\n", key(k), quote(&v)));
+                }
+            }
+            out.push_str("+++\n\n");
+        }
+    }
+    out
+}
+
+// --- wikitext 转 Markdown ---
+
+/// 链接和图片的目标：已导出的条目和复制的资源
+struct Targets {
+    pages: HashMap<String, String>,
+    assets: HashMap<String, String>,
+}
+
+fn is_external(target: &str) -> bool {
+    target.contains("://") || target.starts_with("mailto:") || target.starts_with("data:")
+}
+
+impl Targets {
+    fn link(&self, label: &str, target: &str) -> String {
+        if is_external(target) {
+            return format!("[{}]({})", label, target);
+        }
+        match self.pages.get(target) {
+            Some(slug) => format!("[{}]({}.md)", label, slug),
+            // 没有导出的条目只保留文字
+            None => label.to_string(),
+        }
+    }
+
+    fn image(&self, alt: &str, source: &str) -> String {
+        let url = self.assets.get(source).map_or(source, String::as_str);
+        format!("![{}]({})", alt, url)
+    }
+}
+
+/// 转换一行中的行内语法
+fn inline(line: &str, targets: &Targets) -> String {
+    let mut out = String::with_capacity(line.len());
+    let (mut underline, mut sup, mut sub) = (false, false, false);
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        // 裸 URL 原样保留，其中的 `//` 不是斜体
+        if ["https://", "http://", "ftp://"].iter().any(|scheme| rest.starts_with(scheme)) {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if let Some(inner) = rest.strip_prefix("[[")
+            && let Some(end) = inner.find("]]")
+        {
+            let body = &inner[..end];
+            let (label, target) = body.split_once('|').unwrap_or((body, body));
+            out.push_str(&targets.link(label.trim(), target.trim()));
+            rest = &inner[end + 2..];
+        } else if let Some(inner) = rest.strip_prefix("[ext[")
+            && let Some(end) = inner.find("]]")
+        {
+            let body = &inner[..end];
+            let (label, target) = body.split_once('|').unwrap_or((body, body));
+            out.push_str(&format!("[{}]({})", label.trim(), target.trim()));
+            rest = &inner[end + 2..];
+        } else if let Some(attrs) = rest.strip_prefix("[img")
+            && let Some(open) = attrs.find('[')
+            && let Some(end) = attrs[open..].find("]]")
+        {
+            // [img[Title]]、[img[提示|Title]]、[img width=32 [Title]]
+            let body = &attrs[open + 1..open + end];
+            let (alt, source) = body.split_once('|').unwrap_or(("", body));
+            out.push_str(&targets.image(alt.trim(), source.trim()));
+            rest = &attrs[open + end + 2..];
+        } else if let Some(inner) = rest.strip_prefix("{{{")
+            && let Some(end) = inner.find("}}}")
+        {
+            // 过滤器嵌入无法转换，与宏调用一样原样保留
+            out.push_str(&rest[..end + 6]);
+            rest = &inner[end + 3..];
+        } else if let Some(inner) = rest.strip_prefix("{{")
+            && let Some(end) = inner.find("}}")
+        {
+            // 嵌入：图片显示为图片，其余条目显示为链接
+            let title = inner[..end].split("||").next().unwrap_or("");
+            let title = title.split(['!', '#']).next().unwrap_or("").trim();
+            if targets.assets.contains_key(title) {
+                out.push_str(&targets.image(title, title));
+            } else {
+                out.push_str(&targets.link(title, title));
+            }
+            rest = &inner[end + 2..];
+        } else if let Some(after) = rest.strip_prefix("''") {
+            out.push_str("**");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("//") {
+            out.push('*');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("__") {
+            out.push_str(if underline { "</u>" } else { "<u>" });
+            underline = !underline;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("^^") {
+            out.push_str(if sup { "</sup>" } else { "<sup>" });
+            sup = !sup;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix(",,") {
+            out.push_str(if sub { "</sub>" } else { "<sub>" });
+            sub = !sub;
+            rest = after;
+        } else {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+/// 转换行内语法，跳过行内代码
+fn inline_segments(line: &str, targets: &Targets) -> String {
+    wikitext::split_code(line)
+        .into_iter()
+        .map(|(code, segment)| if code { segment.to_string() } else { inline(segment, targets) })
+        .collect()
+}
+
+/// 表格行 `|a|b|`，末尾的 `h` 表示表头行；`c` (标题) 和 `k` (样式) 行被忽略
+fn table(rows: &[&str], targets: &Targets, out: &mut Vec<String>) {
+    let mut cells: Vec<(bool, Vec<String>)> = Vec::new();
+    for row in rows {
+        // 只有一个 `|` 的行没有单元格
+        let Some(idx) = row.rfind('|').filter(|&idx| idx > 0) else { continue };
+        let (body, kind) = (&row[1..idx], row[idx + 1..].trim());
+        if kind == "c" || kind == "k" {
+            continue;
+        }
+        let cols = wikitext::split_cells(body)
+            .into_iter()
+            .map(|cell| inline_segments(cell.trim().trim_start_matches('!').trim(), targets).replace('|', "\\|"))
+            .collect();
+        cells.push((kind == "h", cols));
+    }
+    let Some(width) = cells.iter().map(|(_, c)| c.len()).max() else { return };
+    // Markdown 表格必须有表头：没有标记为表头的行时使用第一行
+    let header = cells.iter().position(|(h, _)| *h).unwrap_or(0);
+    let (_, head) = cells.remove(header);
+    let line = |cols: &[String]| {
+        let mut cols = cols.to_vec();
+        cols.resize(width, String::new());
+        format!("| {} |", cols.join(" | "))
+    };
+    out.push(line(&head));
+    out.push(format!("|{}", "---|".repeat(width)));
+    for (_, cols) in &cells {
+        out.push(line(cols));
+    }
+}
+
+/// 把 wikitext 正文转换为 Markdown
+fn to_markdown(text: &str, targets: &Targets) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut out: Vec<String> = Vec::new();
+    let (mut in_code, mut in_quote) = (false, false);
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+            out.push(line.to_string());
+            continue;
+        }
+        if in_code {
+            out.push(line.to_string());
+            continue;
+        }
+        if line.starts_with("<<<") {
+            in_quote = !in_quote;
+            // 引用块结束后空一行，避免下一行被并入引用
+            if !in_quote {
+                out.push(String::new());
+            }
+            continue;
+        }
+        if line.starts_with('|') {
+            let start = i - 1;
+            while i < lines.len() && lines[i].starts_with('|') {
+                i += 1;
+            }
+            table(&lines[start..i], targets, &mut out);
+            continue;
+        }
+
+        let converted = if let Some(level) = line.find(|c| c != '!').filter(|&n| n > 0) {
+            format!("{} {}", "#".repeat(level.min(6)), inline_segments(line[level..].trim(), targets))
+        } else if let Some(depth) = line.find(|c| c != '*' && c != '#').filter(|&n| n > 0 && line[n..].starts_with(' ')) {
+            let marker = if line[..depth].ends_with('#') { "1." } else { "-" };
+            format!("{}{} {}", "    ".repeat(depth - 1), marker, inline_segments(line[depth..].trim(), targets))
+        } else {
+            inline_segments(line, targets)
+        };
+        out.push(if in_quote { format!("> {}", converted) } else { converted });
+    }
+    let mut markdown = out.join("\n");
+    markdown.push('\n');
+    markdown
+}
+
+/// 条目正文转为 Markdown；Markdown 条目原样保留，其他文本类型放进代码块
+fn body(tiddler: &Tiddler, targets: &Targets) -> String {
+    let text = tiddler.field("text").unwrap_or("");
+    match tiddler.field("type").unwrap_or("") {
+        "text/markdown" | "text/x-markdown" => format!("{}\n", text.trim_end()),
+        _ if wikitext::is_wikitext(tiddler) => to_markdown(text, targets),
+        _ => format!("```\n{}\n```\n", text.trim_end()),
+    }
+}
+
+// --- 导出 ---
+
+pub(crate) struct MarkdownReport {
+    pub(crate) pages: usize,
+    pub(crate) assets: usize,
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> AppError {
+    AppError::Response(format!("{:?}: {}", path, e))
+}
+
+/// 文件内容：转存的文件或 base64 正文；指向外部地址时返回 `None`
+async fn asset_bytes(tiddler: &Tiddler, state: &AppState, config: &ServerConfig) -> Option<Vec<u8>> {
+    match export::load_asset(tiddler, state, config).await {
+        Ok(Some(data)) => Some(data),
+        Ok(None) if tiddler.field("_canonical_uri").is_none() => {
+            tiddler.field("text").and_then(|t| general_purpose::STANDARD.decode(t.trim()).ok())
+        }
+        Ok(None) => None,
+        Err(e) => {
+            tracing::warn!("Could not read file of '{}': {:?}", tiddler.title, e);
+            None
+        }
+    }
+}
+
+/// 导出 `filter` 选出的条目到 `dir`
+pub(crate) async fn export_markdown(
+    ds: &DataStore,
+    state: &AppState,
+    config: &ServerConfig,
+    dir: &Path,
+    filter: &str,
+    format: FrontMatter,
+) -> AppResult<MarkdownReport> {
+    let filter = filter.to_string();
+    let (selected, wiki) = ds
+        .read(move |tiddlers| {
+            let wiki = Wiki::new(tiddlers.all()?);
+            let titles = wiki.filter(&filter).map_err(|e| AppError::Response(format!("Invalid filter: {}", e)))?;
+            Ok((titles, wiki))
+        })
+        .await?;
+
+    let is_binary = |t: &Tiddler| is_binary_type(t.field("type").unwrap_or(""));
+    let pages: Vec<&Tiddler> = selected.iter().filter_map(|title| wiki.get(title)).filter(|t| !is_binary(t)).collect();
+
+    // 需要复制的资源：选中的二进制条目，以及页面中引用到的图片
+    let mut asset_titles: Vec<&str> = selected.iter().map(String::as_str).filter(|t| wiki.get(t).is_some_and(is_binary)).collect();
+    for page in &pages {
+        for target in wikitext::links(page) {
+            if let Some(t) = wiki.get(&target).filter(|t| is_binary(t))
+                && !asset_titles.contains(&t.title.as_str())
+            {
+                asset_titles.push(&t.title);
+            }
+        }
+        for source in wikitext::image_sources(page.field("text").unwrap_or("")) {
+            if let Some(t) = wiki.get(&source).filter(|t| is_binary(t))
+                && !asset_titles.contains(&t.title.as_str())
+            {
+                asset_titles.push(&t.title);
+            }
+        }
+    }
+
+    std::fs::create_dir_all(dir).map_err(|e| io_error(dir, e))?;
+    let assets_dir = dir.join("assets");
+    let mut used = HashSet::new();
+    let mut targets = Targets { pages: HashMap::new(), assets: HashMap::new() };
+    let mut report = MarkdownReport { pages: 0, assets: 0 };
+    for title in asset_titles {
+        let Some(tiddler) = wiki.get(title) else { continue };
+        match asset_bytes(tiddler, state, config).await {
+            Some(data) => {
+                let ext = extension_for(tiddler.field("type").unwrap_or(""));
+                let stem = title.strip_suffix(&format!(".{}", ext)).unwrap_or(title);
+                let name = format!("{}.{}", slug(stem, &mut used), ext);
+                std::fs::create_dir_all(&assets_dir).map_err(|e| io_error(&assets_dir, e))?;
+                let path = assets_dir.join(&name);
+                std::fs::write(&path, data).map_err(|e| io_error(&path, e))?;
+                targets.assets.insert(title.to_string(), format!("assets/{}", name));
+                report.assets += 1;
+            }
+            // 外部图片直接引用原地址
+            None => {
+                if let Some(uri) = tiddler.field("_canonical_uri") {
+                    targets.assets.insert(title.to_string(), uri.to_string());
+                }
+            }
+        }
+    }
+
+    let mut used = HashSet::from(["assets".to_string()]);
+    for page in &pages {
+        targets.pages.insert(page.title.clone(), slug(&page.title, &mut used));
+    }
+    for page in &pages {
+        let path = dir.join(format!("{}.md", targets.pages[&page.title]));
+        let content = format!("{}{}", front_matter(page, format), body(page, &targets));
+        std::fs::write(&path, content).map_err(|e| io_error(&path, e))?;
+        report.pages += 1;
+    }
+    Ok(report)
+}
+
+/// `export-markdown` 子命令
+pub(crate) async fn run_cli(
+    config: &AppConfig,
+    dir: &Path,
+    tag: Option<String>,
+    filter: Option<String>,
+    format: FrontMatter,
+) -> Result<(), String> {
+    let filter = match (tag, filter) {
+        (Some(tag), None) => format!("[tag[{}]]", tag),
+        (None, Some(filter)) => filter,
+        _ => return Err("Specify exactly one of --tag or --filter".to_string()),
+    };
+    let ds = crate::initialize_datastore(&config.server).map_err(|e| format!("Error initializing datastore: {:?}", e))?;
+    let state = crate::build_app_state(config).await?;
+    let report = export_markdown(&ds, &state, &config.server, dir, &filter, format)
+        .await
+        .map_err(|e| format!("Export failed: {:?}", e))?;
+    tracing::info!("Exported {} page(s) and {} asset(s) to {:?}", report.pages, report.assets, dir);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets() -> Targets {
+        Targets {
+            pages: HashMap::from([("Other Page".to_string(), "other-page".to_string())]),
+            assets: HashMap::from([("Pic.png".to_string(), "assets/pic.png".to_string())]),
+        }
+    }
+
+    fn convert(text: &str) -> String {
+        to_markdown(text, &targets())
+    }
+
+    #[test]
+    fn headings_and_lists() {
+        assert_eq!(convert("! Title\n!!!Third\n!!!!!!!! Deep"), "# Title\n### Third\n###### Deep\n");
+        assert_eq!(convert("* one\n** nested\n# first\n##  second"), "- one\n    - nested\n1. first\n    1. second\n");
+        // 没有空格时不是列表，只有 `!` 的行不是标题
+        assert_eq!(convert("*bold*\n!"), "*bold*\n!\n");
+    }
+
+    #[test]
+    fn tables() {
+        let text = "|Name|Value|h\n|a|''b''|\n|Caption|c\n|only one|";
+        assert_eq!(convert(text), "| Name | Value |\n|---|---|\n| a | **b** |\n| only one |  |\n");
+        // 没有表头行时使用第一行
+        assert_eq!(convert("|!x|y|\n|1|2|"), "| x | y |\n|---|---|\n| 1 | 2 |\n");
+        // 链接中的 `|` 不是单元格分隔符
+        assert_eq!(convert("|[[see|Other Page]]|"), "| [see](other-page.md) |\n|---|\n");
+    }
+
+    #[test]
+    fn rows_without_cells() {
+        assert_eq!(convert("|"), "\n");
+        assert_eq!(convert("|\n|a|b|\n|"), "| a | b |\n|---|---|\n");
+    }
+
+    #[test]
+    fn inline_formatting() {
+        assert_eq!(convert("''b'' //i// __u__ ^^sup^^ ,,sub,,"), "**b** *i* <u>u</u> <sup>sup</sup> <sub>sub</sub>\n");
+        assert_eq!(convert("see https://example.com//path"), "see https://example.com//path\n");
+        assert_eq!(convert("`''code''` ''x''"), "`''code''` **x**\n");
+    }
+
+    #[test]
+    fn links_images_and_transclusions() {
+        assert_eq!(convert("[[Other Page]] [[label|Other Page]] [[Missing]]"), "[Other Page](other-page.md) [label](other-page.md) Missing\n");
+        assert_eq!(convert("[ext[site|https://x.org]]"), "[site](https://x.org)\n");
+        assert_eq!(convert("[img[Pic.png]] [img width=3 [alt|Pic.png]]"), "![](assets/pic.png) ![alt](assets/pic.png)\n");
+        assert_eq!(convert("{{Pic.png}} {{Other Page!!caption}} {{{ [tag[x]] }}}"), "![Pic.png](assets/pic.png) [Other Page](other-page.md) {{{ [tag[x]] }}}\n");
+    }
+
+    #[test]
+    fn code_and_quotes() {
+        assert_eq!(convert("```\n! not a heading\n```"), "```\n! not a heading\n```\n");
+        assert_eq!(convert("<<<\nquoted ''text''\n<<<\nafter"), "> quoted **text**\n\nafter\n");
+    }
+}
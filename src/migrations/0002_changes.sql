@@ -0,0 +1,13 @@
+-- 每个 tiddler 最近一次修改 (含删除) 的序号，用于增量同步。
+-- INSERT OR REPLACE 会删除旧行并分配新的 seq，因此每个标题只保留一行且 seq 单调递增。
+CREATE TABLE IF NOT EXISTS changes
+(
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    title TEXT NOT NULL UNIQUE,
+    revision INTEGER,
+    deleted INTEGER NOT NULL DEFAULT 0,
+    timestamp TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+-- 已有的 tiddler 视为一次修改，客户端从 since=0 开始即可拿到全部标题
+INSERT OR IGNORE INTO changes (title, revision) SELECT title, revision FROM tiddlers;
@@ -0,0 +1,10 @@
+-- 条目之间的链接索引：正文中的链接 / 嵌入 (kind = 'link') 以及标签 (kind = 'tag')。
+-- 已有条目的索引由程序在迁移后重建 (见 links::rebuild)。
+CREATE TABLE IF NOT EXISTS links
+(
+    source TEXT NOT NULL,
+    target TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    PRIMARY KEY (source, target, kind)
+);
+CREATE INDEX IF NOT EXISTS links_target_index ON links (target);
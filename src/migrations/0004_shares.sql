@@ -0,0 +1,23 @@
+-- 分享链接：令牌 = id + HMAC 签名，过滤器决定可以访问哪些条目。
+-- 撤销后保留记录 (revoked = 1)，便于查看历史访问次数。
+CREATE TABLE IF NOT EXISTS shares
+(
+    id TEXT PRIMARY KEY,
+    filter TEXT NOT NULL,
+    label TEXT,
+    created TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    expires TEXT,
+    revoked INTEGER NOT NULL DEFAULT 0,
+    views INTEGER NOT NULL DEFAULT 0,
+    last_viewed TEXT
+);
+
+-- 服务器内部的键值设置
+CREATE TABLE IF NOT EXISTS settings
+(
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+
+-- 签名分享令牌的密钥，由 SQLite 的 CSPRNG 生成
+INSERT OR IGNORE INTO settings (key, value) VALUES ('share_secret', lower(hex(randomblob(32))));
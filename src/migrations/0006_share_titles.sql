@@ -0,0 +1,8 @@
+-- 分享单个条目时直接保存标题，不再拼成 `[[标题]]` 过滤器 (标题中含 `]]` 时无法表示)，
+-- 访问时也只需读取这一个条目。此时 filter 为空字符串。
+ALTER TABLE shares ADD COLUMN title TEXT;
+
+-- 已有的单条目分享
+UPDATE shares
+SET title = substr(filter, 3, length(filter) - 4), filter = ''
+WHERE filter LIKE '[[%]]' AND instr(substr(filter, 3, length(filter) - 4), ']]') = 0;
@@ -0,0 +1,356 @@
+//! Git 镜像：把每次写入 / 删除以 `.tid` 文件提交到本地 git 仓库，并可推送到远程
+//!
+//! 后台任务订阅 [`Store`](crate::store::Store) 发布的修改事件，每个修改一次提交：
+//! 作者为条目的 `modifier` 或 `creator` (都为空或删除时为 `[status]` 中的用户名)，提交信息包含条目标题。
+//! 文件名与 WebDAV 相同 (标题百分号编码，含多行字段的条目为 `.json`)。
+//! 启动时和事件丢失时会与数据库做一次全量同步。需要系统中安装了 `git` 命令。
+
+use crate::{DataStore, Tiddler, events::EventKind, folder};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+// 提交者固定为服务器本身，作者为修改条目的用户
+const COMMITTER: &str = "tiddly-wiki-server";
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct GitMirrorConfig {
+    #[serde(default)]
+    pub(crate) enable: bool,
+    // 本地仓库目录，不存在时自动创建
+    #[serde(default = "default_repo")]
+    pub(crate) repo: PathBuf,
+    // 每批提交后推送到的远程：本地路径 (如裸仓库) 或 git 能识别的 URL
+    #[serde(default)]
+    pub(crate) remote: Option<String>,
+    #[serde(default = "default_branch")]
+    pub(crate) branch: String,
+    // 不写入镜像的标题前缀
+    #[serde(default = "default_exclude")]
+    pub(crate) exclude: Vec<String>,
+}
+
+fn default_repo() -> PathBuf {
+    PathBuf::from("./mirror")
+}
+
+fn default_branch() -> String {
+    "main".to_string()
+}
+
+fn default_exclude() -> Vec<String> {
+    ["$:/StoryList", "$:/HistoryList", "$:/state/", "$:/temp/", "$:/status/"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+impl Default for GitMirrorConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            repo: default_repo(),
+            remote: None,
+            branch: default_branch(),
+            exclude: default_exclude(),
+        }
+    }
+}
+
+impl GitMirrorConfig {
+    fn includes(&self, title: &str) -> bool {
+        !self.exclude.iter().any(|prefix| title.starts_with(prefix.as_str()))
+    }
+}
+
+/// git 会去掉作者名中的 `<`、`>` 和换行，并拒绝空的作者名；这样的名字返回 `None`
+fn author_name(name: &str) -> Option<String> {
+    let name: String = name.chars().filter(|c| !matches!(c, '<' | '>' | '\n' | '\r')).collect();
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// 需要写入镜像的修改
+enum Op {
+    Put(Tiddler),
+    Delete(String),
+    // 与数据库全量同步
+    Sync(Vec<Tiddler>),
+}
+
+struct Repo {
+    cfg: GitMirrorConfig,
+}
+
+impl Repo {
+    fn git(&self, args: &[&str], author: Option<&str>) -> Result<String, String> {
+        let mut cmd = Command::new("git");
+        cmd.args(args)
+            .current_dir(&self.cfg.repo)
+            .env("GIT_COMMITTER_NAME", COMMITTER)
+            .env("GIT_COMMITTER_EMAIL", "");
+        if let Some(name) = author {
+            cmd.env("GIT_AUTHOR_NAME", name).env("GIT_AUTHOR_EMAIL", "");
+        }
+        let output = cmd.output().map_err(|e| format!("failed to run git: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn init(&self) -> Result<(), String> {
+        std::fs::create_dir_all(&self.cfg.repo).map_err(|e| format!("{:?}: {}", self.cfg.repo, e))?;
+        if !self.cfg.repo.join(".git").exists() {
+            self.git(&["init", "-q"], None)?;
+            self.git(&["symbolic-ref", "HEAD", &format!("refs/heads/{}", self.cfg.branch)], None)?;
+            tracing::info!("Initialized git mirror in {:?}", self.cfg.repo);
+        }
+        Ok(())
+    }
+
+    /// 条目的两种可能文件名 (`.tid` / `.json`)；格式变化时旧文件也要删掉
+    fn paths(&self, title: &str) -> [PathBuf; 2] {
+        let name = folder::encode_name(title);
+        [self.cfg.repo.join(format!("{}.tid", name)), self.cfg.repo.join(format!("{}.json", name))]
+    }
+
+    fn write(&self, tiddler: &Tiddler) -> Result<(), String> {
+        for path in self.paths(&tiddler.title) {
+            let _ = std::fs::remove_file(path);
+        }
+        let data = folder::serialize(tiddler).map_err(|e| format!("{:?}", e))?;
+        let path = self.cfg.repo.join(folder::file_name(tiddler));
+        std::fs::write(&path, data).map_err(|e| format!("{:?}: {}", path, e))
+    }
+
+    fn remove(&self, title: &str) {
+        for path in self.paths(title) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    /// 暂存全部修改并提交；没有变化时返回 false
+    fn commit(&self, message: &str, author: &str) -> Result<bool, String> {
+        self.git(&["add", "-A"], None)?;
+        if self.git(&["status", "--porcelain"], None)?.trim().is_empty() {
+            return Ok(false);
+        }
+        let author = author_name(author).unwrap_or_else(|| COMMITTER.to_string());
+        if let Err(e) = self.git(&["commit", "-q", "-m", message], Some(&author)) {
+            // 取消暂存，免得这次的修改混进下一个提交；文件留在工作区，下次提交时再暂存
+            let _ = self.git(&["reset", "-q"], None);
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    fn apply(&self, op: Op, username: &str) -> Result<bool, String> {
+        match op {
+            Op::Put(tiddler) => {
+                let created = self.paths(&tiddler.title).iter().all(|p| !p.exists());
+                self.write(&tiddler)?;
+                let verb = if created { "Create" } else { "Update" };
+                let author = [tiddler.field("modifier"), tiddler.field("creator")]
+                    .into_iter()
+                    .flatten()
+                    .find(|name| author_name(name).is_some())
+                    .unwrap_or(username);
+                self.commit(&format!("{} {}", verb, tiddler.title), author)
+            }
+            Op::Delete(title) => {
+                self.remove(&title);
+                self.commit(&format!("Delete {}", title), username)
+            }
+            Op::Sync(tiddlers) => {
+                let mut expected = HashSet::new();
+                for tiddler in tiddlers.iter().filter(|t| self.cfg.includes(&t.title)) {
+                    if let Err(e) = self.write(tiddler) {
+                        tracing::warn!("Git mirror: skipped '{}': {}", tiddler.title, e);
+                        continue;
+                    }
+                    expected.insert(folder::file_name(tiddler));
+                }
+                // 删除数据库中已不存在的条目
+                let entries = std::fs::read_dir(&self.cfg.repo).map_err(|e| format!("{:?}: {}", self.cfg.repo, e))?;
+                for entry in entries.flatten() {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if (name.ends_with(".tid") || name.ends_with(".json")) && !expected.contains(&name) {
+                        let _ = std::fs::remove_file(entry.path());
+                    }
+                }
+                self.commit("Sync with database", username)
+            }
+        }
+    }
+
+    fn push(&self, remote: &str) -> Result<(), String> {
+        // 本地路径相对于启动目录，而 git 在仓库目录中运行
+        let remote = match Path::new(remote).canonicalize() {
+            Ok(path) => path.display().to_string(),
+            Err(_) => remote.to_string(),
+        };
+        self.git(&["push", "-q", &remote, &format!("HEAD:refs/heads/{}", self.cfg.branch)], None)?;
+        Ok(())
+    }
+}
+
+/// 读取事件对应的修改；条目已被再次修改或删除时按数据库的当前状态处理
+async fn op_for(ds: &DataStore, kind: EventKind, title: String) -> Option<Op> {
+    match kind {
+        EventKind::Delete => Some(Op::Delete(title)),
+        // 收件箱条目只发布 Inbox 事件，与写入相同处理
+        EventKind::Put | EventKind::Inbox => {
+            let lookup = title.clone();
+            match ds.read(move |tiddlers| tiddlers.get(&lookup)).await {
+                Ok(Some(tiddler)) => Some(Op::Put(tiddler)),
+                Ok(None) => Some(Op::Delete(title)),
+                Err(e) => {
+                    tracing::error!("Git mirror: failed to read '{}': {:?}", title, e);
+                    None
+                }
+            }
+        }
+    }
+}
+
+async fn sync_op(ds: &DataStore) -> Option<Op> {
+    match ds.read(|tiddlers| tiddlers.all()).await {
+        Ok(all) => Some(Op::Sync(all)),
+        Err(e) => {
+            tracing::error!("Git mirror: failed to read tiddlers: {:?}", e);
+            None
+        }
+    }
+}
+
+/// 在阻塞线程中提交一批修改，之后推送一次
+async fn commit_batch(repo: &Arc<Repo>, ops: Vec<Op>, username: &str) {
+    let repo = repo.clone();
+    let username = username.to_string();
+    let result = tokio::task::spawn_blocking(move || {
+        let mut committed = false;
+        for op in ops {
+            match repo.apply(op, &username) {
+                Ok(c) => committed |= c,
+                Err(e) => tracing::error!("Git mirror: {}", e),
+            }
+        }
+        if committed && let Some(remote) = &repo.cfg.remote {
+            repo.push(remote)?;
+        }
+        Ok::<(), String>(())
+    })
+    .await;
+    match result {
+        Ok(Err(e)) => tracing::error!("Git mirror: {}", e),
+        Err(e) => tracing::error!("Git mirror task panicked: {}", e),
+        Ok(Ok(())) => {}
+    }
+}
+
+/// 启动镜像任务
+pub(crate) fn spawn(ds: DataStore, cfg: GitMirrorConfig, username: String) {
+    // 先订阅，避免初次同步期间的修改丢失
+    let mut events = ds.subscribe();
+    tokio::spawn(async move {
+        let repo = Arc::new(Repo { cfg: cfg.clone() });
+        let init = repo.clone();
+        let ready = tokio::task::spawn_blocking(move || init.init()).await.map_err(|e| e.to_string()).and_then(|r| r);
+        if let Err(e) = ready {
+            tracing::error!("Git mirror disabled: {}", e);
+            return;
+        }
+        tracing::info!("Mirroring tiddlers into git repository {:?}", cfg.repo);
+        if let Some(op) = sync_op(&ds).await {
+            commit_batch(&repo, vec![op], &username).await;
+        }
+
+        loop {
+            let mut resync = false;
+            let mut pending = Vec::new();
+            match events.recv().await {
+                Ok(event) => pending.push(event),
+                Err(RecvError::Lagged(_)) => resync = true,
+                Err(RecvError::Closed) => break,
+            }
+            // 已经排队的事件一起处理，推送一次
+            loop {
+                match events.try_recv() {
+                    Ok(event) => pending.push(event),
+                    Err(TryRecvError::Lagged(_)) => resync = true,
+                    Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+                }
+            }
+
+            let mut ops = Vec::new();
+            if resync {
+                tracing::warn!("Git mirror fell behind, resyncing with the database");
+                ops.extend(sync_op(&ds).await);
+            } else {
+                for event in pending.into_iter().filter(|e| cfg.includes(&e.title)) {
+                    ops.extend(op_for(&ds, event.kind, event.title).await);
+                }
+            }
+            if !ops.is_empty() {
+                commit_batch(&repo, ops, &username).await;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use serde_json::json;
+
+    #[test]
+    fn author_names() {
+        assert_eq!(author_name(" alice ").as_deref(), Some("alice"));
+        assert_eq!(author_name("bob <b@x>").as_deref(), Some("bob b@x"));
+        assert_eq!(author_name(""), None);
+        assert_eq!(author_name(" <> "), None);
+    }
+
+    #[test]
+    fn empty_modifier_falls_back_to_username() {
+        let tmp = testutil::TempDir::new("mirror");
+        let repo = Repo { cfg: GitMirrorConfig { enable: true, repo: tmp.path().join("repo"), ..Default::default() } };
+        repo.init().unwrap();
+        let tiddler = Tiddler::from_value(json!({"title": "Note", "modifier": "", "creator": "<>", "text": "x"})).unwrap();
+        assert!(repo.apply(Op::Put(tiddler), "owner").unwrap());
+        assert_eq!(repo.git(&["log", "-1", "--format=%an|%s"], None).unwrap().trim(), "owner|Create Note");
+
+        let tiddler = Tiddler::from_value(json!({"title": "Note", "modifier": "alice", "text": "y"})).unwrap();
+        assert!(repo.apply(Op::Put(tiddler), "").unwrap());
+        assert_eq!(repo.git(&["log", "-1", "--format=%an|%s"], None).unwrap().trim(), "alice|Update Note");
+        assert!(repo.apply(Op::Delete("Note".to_string()), "").unwrap());
+        assert_eq!(repo.git(&["log", "-1", "--format=%an"], None).unwrap().trim(), COMMITTER);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_commit_unstages_changes() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = testutil::TempDir::new("mirror-reset");
+        let repo = Repo { cfg: GitMirrorConfig { enable: true, repo: tmp.path().join("repo"), ..Default::default() } };
+        repo.init().unwrap();
+        let hook = repo.cfg.repo.join(".git/hooks/pre-commit");
+        std::fs::create_dir_all(hook.parent().unwrap()).unwrap();
+        std::fs::write(&hook, "#!/bin/sh\nexit 1\n").unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let tiddler = Tiddler::from_value(json!({"title": "Note", "text": "x"})).unwrap();
+        assert!(repo.apply(Op::Put(tiddler), "owner").is_err());
+        assert_eq!(repo.git(&["diff", "--cached", "--name-only"], None).unwrap(), "");
+
+        std::fs::remove_file(&hook).unwrap();
+        assert!(repo.commit("Retry", "owner").unwrap());
+    }
+}
@@ -0,0 +1,320 @@
+//! 重命名：`POST /api/tiddlers/{title}/rename`
+//!
+//! 在一个事务中把条目改名，并可选地把其它条目 (以及它自己) 中指向旧标题的 `[[链接]]`、
+//! 标签和 `list` 字段改为新标题。条目的其它字段 (创建 / 修改时间等) 原样保留；
+//! 转存到本地的文件按新标题重新命名，`_canonical_uri` 随之更新。
+
+use crate::{AppError, AppResult, DataStore, ServerConfig, Tiddler, db, offload_file_name, wikitext};
+use axum::{Extension, extract, http::StatusCode, response::IntoResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+pub(crate) struct RenameRequest {
+    to: String,
+    // 是否改写其它条目中的引用
+    #[serde(default = "default_relink")]
+    relink: bool,
+}
+
+fn default_relink() -> bool {
+    true
+}
+
+#[derive(Serialize)]
+pub(crate) struct RenameResponse {
+    from: String,
+    to: String,
+    revision: u64,
+    relinked: Vec<String>,
+}
+
+enum Outcome {
+    Renamed(RenameResponse),
+    NotFound,
+    Conflict,
+}
+
+/// 替换标题列表中的 `from`；没有出现时返回 `None`
+fn replace_title(titles: Vec<String>, from: &str, to: &str) -> Option<Vec<String>> {
+    if !titles.iter().any(|t| t == from) {
+        return None;
+    }
+    let mut replaced: Vec<String> = Vec::with_capacity(titles.len());
+    for t in titles {
+        let t = if t == from { to.to_string() } else { t };
+        if !replaced.contains(&t) {
+            replaced.push(t);
+        }
+    }
+    Some(replaced)
+}
+
+/// 改写一个条目中对 `from` 的引用；有改动时返回 true
+fn relink(tiddler: &mut Tiddler, from: &str, to: &str) -> bool {
+    let mut changed = false;
+
+    if wikitext::is_wikitext(tiddler)
+        && let Some(text) = tiddler.field("text").and_then(|text| wikitext::relink_text(text, from, to))
+    {
+        tiddler.set_field("text", Value::String(text));
+        changed = true;
+    }
+
+    if let Some(tags) = replace_title(wikitext::tags(tiddler), from, to) {
+        // 保持原来的存储形式 (数组或字符串)
+        let as_array = matches!(tiddler.meta.get("tags"), Some(Value::Array(_)));
+        let value = if as_array { json!(tags) } else { Value::String(wikitext::stringify_list(&tags)) };
+        tiddler.set_field("tags", value);
+        changed = true;
+    }
+
+    let list = tiddler.field("list").map(wikitext::parse_string_array);
+    if let Some(list) = list.and_then(|list| replace_title(list, from, to)) {
+        tiddler.set_field("list", Value::String(wikitext::stringify_list(&list)));
+        changed = true;
+    }
+
+    changed
+}
+
+/// 转存的文件以标题的哈希命名，改名后要换成新标题对应的文件名，否则以后新建的
+/// 同名条目会覆盖它；需要移动时返回 (旧路径, 新路径)
+pub(crate) fn rekey_file(tiddler: &mut Tiddler, files_dir: &Path, from: &str, to: &str) -> Option<(PathBuf, PathBuf)> {
+    let mime = tiddler.field("type").unwrap_or_default().to_string();
+    let old_name = offload_file_name(from, &mime);
+    if tiddler.field("_canonical_uri") != Some(format!("/files/{}", old_name).as_str()) {
+        return None;
+    }
+    let old_path = files_dir.join(old_name);
+    // 文件已经不在时保留原来的引用
+    if !old_path.exists() {
+        return None;
+    }
+    let new_name = offload_file_name(to, &mime);
+    tiddler.set_field("_canonical_uri", Value::String(format!("/files/{}", new_name)));
+    Some((old_path, files_dir.join(new_name)))
+}
+
+/// 在事务中移动的文件。目标位置已有的文件 (被覆盖条目的或遗留的) 先移到一旁，
+/// 提交后删除；事务失败时连同移动一起还原
+pub(crate) struct MovedFile {
+    from: PathBuf,
+    to: PathBuf,
+    displaced: Option<PathBuf>,
+}
+
+impl MovedFile {
+    pub(crate) fn perform(from: PathBuf, to: PathBuf) -> AppResult<Self> {
+        let io_error = |path: &Path, e: std::io::Error| AppError::Response(format!("Failed to move {:?}: {}", path, e));
+        let displaced = to.exists().then(|| db::sibling_path(&to, "bak"));
+        if let Some(backup) = &displaced {
+            std::fs::rename(&to, backup).map_err(|e| io_error(&to, e))?;
+        }
+        if let Err(e) = std::fs::rename(&from, &to) {
+            if let Some(backup) = &displaced {
+                let _ = std::fs::rename(backup, &to);
+            }
+            return Err(io_error(&from, e));
+        }
+        Ok(Self { from, to, displaced })
+    }
+
+    pub(crate) fn finish(self, committed: bool) {
+        if committed {
+            if let Some(backup) = self.displaced {
+                let _ = std::fs::remove_file(backup);
+            }
+            return;
+        }
+        if let Err(e) = std::fs::rename(&self.to, &self.from) {
+            tracing::error!("Failed to move {:?} back to {:?}: {}", self.to, self.from, e);
+        }
+        if let Some(backup) = self.displaced {
+            let _ = std::fs::rename(backup, &self.to);
+        }
+    }
+}
+
+// --- Handler: POST /api/tiddlers/{title}/rename ---
+pub(crate) async fn rename_tiddler(
+    Extension(ds): Extension<DataStore>,
+    Extension(config): Extension<ServerConfig>,
+    extract::Path(from): extract::Path<String>,
+    axum::Json(req): axum::Json<RenameRequest>,
+) -> AppResult<axum::response::Response> {
+    let to = req.to.trim().to_string();
+    if to.is_empty() || to == from {
+        let error = json!({ "error": "the new title must be non-empty and different from the old one" });
+        return Ok((StatusCode::BAD_REQUEST, axum::Json(error)).into_response());
+    }
+
+    let outcome = ds
+        .write(move |tiddlers| {
+            // 文件在事务内移动；提交失败时移回原处
+            let mut moved = None;
+            let result = tiddlers.transaction(|tiddlers| {
+                let Some(mut tiddler) = tiddlers.get(&from)? else {
+                    return Ok(Outcome::NotFound);
+                };
+                if tiddlers.get(&to)?.is_some() {
+                    return Ok(Outcome::Conflict);
+                }
+
+                tiddlers.pop(&from)?;
+                tiddler.title = to.clone();
+                tiddler.set_field("title", Value::String(to.clone()));
+                tiddler.revision += 1;
+                // 条目自身的链接和标签也指向旧标题
+                if req.relink {
+                    relink(&mut tiddler, &from, &to);
+                }
+                let file = rekey_file(&mut tiddler, &config.files_dir, &from, &to);
+                let revision = tiddler.revision;
+                tiddlers.put(tiddler)?;
+
+                let mut relinked = Vec::new();
+                if req.relink {
+                    for mut other in tiddlers.all()? {
+                        if other.title != to && relink(&mut other, &from, &to) {
+                            other.revision += 1;
+                            relinked.push(other.title.clone());
+                            tiddlers.put(other)?;
+                        }
+                    }
+                }
+                if let Some((old_path, new_path)) = file {
+                    moved = Some(MovedFile::perform(old_path, new_path)?);
+                }
+                Ok(Outcome::Renamed(RenameResponse { from, to, revision, relinked }))
+            });
+            if let Some(moved) = moved {
+                moved.finish(result.is_ok());
+            }
+            result
+        })
+        .await?;
+
+    Ok(match outcome {
+        Outcome::Renamed(resp) => {
+            tracing::info!("Renamed '{}' to '{}' ({} reference(s) updated)", resp.from, resp.to, resp.relinked.len());
+            axum::Json(resp).into_response()
+        }
+        Outcome::NotFound => StatusCode::NOT_FOUND.into_response(),
+        Outcome::Conflict => {
+            let error = json!({ "error": "a tiddler with the new title already exists" });
+            (StatusCode::CONFLICT, axum::Json(error)).into_response()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use axum::body::to_bytes;
+
+    fn tiddler(value: Value) -> Tiddler {
+        Tiddler::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn relinks_text_tags_and_lists() {
+        let mut t = tiddler(json!({"title": "A", "text": "see [[Old]] and [[x|Old]]", "tags": "Old [[Other tag]]", "list": "Old New"}));
+        assert!(relink(&mut t, "Old", "New"));
+        assert_eq!(t.field("text"), Some("see [[New]] and [[x|New]]"));
+        assert_eq!(t.field("tags"), Some("New [[Other tag]]"));
+        // 重复的标题只保留一个
+        assert_eq!(t.field("list"), Some("New"));
+
+        let mut t = tiddler(json!({"title": "B", "tags": ["Old"], "type": "text/plain", "text": "[[Old]]"}));
+        assert!(relink(&mut t, "Old", "New"));
+        assert_eq!(t.meta["tags"], json!(["New"]));
+        assert_eq!(t.field("text"), Some("[[Old]]"));
+        assert!(!relink(&mut t, "Old", "New"));
+    }
+
+    struct Fixture {
+        _tmp: testutil::TempDir,
+        ds: DataStore,
+        config: ServerConfig,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let tmp = testutil::TempDir::new(name);
+            let config = testutil::server_config(tmp.path());
+            let ds = testutil::datastore(&config);
+            Self { _tmp: tmp, ds, config }
+        }
+
+        async fn put(&self, value: Value) {
+            let t = tiddler(value);
+            self.ds.write(move |tiddlers| tiddlers.put(t)).await.unwrap();
+        }
+
+        async fn get(&self, title: &str) -> Option<Tiddler> {
+            let title = title.to_string();
+            self.ds.read(move |t| t.get(&title)).await.unwrap()
+        }
+
+        async fn rename(&self, from: &str, body: Value) -> (StatusCode, Value) {
+            let resp = rename_tiddler(
+                Extension(self.ds.clone()),
+                Extension(self.config.clone()),
+                extract::Path(from.to_string()),
+                axum::Json(serde_json::from_value(body).unwrap()),
+            )
+            .await
+            .unwrap();
+            let status = resp.status();
+            let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+        }
+    }
+
+    #[tokio::test]
+    async fn renames_and_relinks() {
+        let fx = Fixture::new("rename");
+        fx.put(json!({"title": "Old", "text": "self [[Old]]", "tags": "Old", "list": "Old"})).await;
+        fx.put(json!({"title": "Ref", "text": "[[Old]]"})).await;
+        fx.put(json!({"title": "Taken"})).await;
+
+        assert_eq!(fx.rename("Old", json!({"to": "Taken"})).await.0, StatusCode::CONFLICT);
+        assert_eq!(fx.rename("Missing", json!({"to": "X"})).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(fx.rename("Old", json!({"to": " Old "})).await.0, StatusCode::BAD_REQUEST);
+
+        let (status, body) = fx.rename("Old", json!({"to": "New"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["revision"], 1);
+        assert_eq!(body["relinked"], json!(["Ref"]));
+        assert!(fx.get("Old").await.is_none());
+        let renamed = fx.get("New").await.unwrap();
+        assert_eq!(renamed.field("text"), Some("self [[New]]"));
+        assert_eq!((renamed.field("tags"), renamed.field("list")), (Some("New"), Some("New")));
+        assert_eq!(fx.get("Ref").await.unwrap().field("text"), Some("[[New]]"));
+
+        let (_, body) = fx.rename("Ref", json!({"to": "Ref2", "relink": false})).await;
+        assert_eq!(body["relinked"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn moves_offloaded_files() {
+        let fx = Fixture::new("rename-file");
+        let old_name = offload_file_name("Old.png", "image/png");
+        std::fs::write(fx.config.files_dir.join(&old_name), b"png").unwrap();
+        fx.put(json!({"title": "Old.png", "type": "image/png", "text": "", "_canonical_uri": format!("/files/{}", old_name), "_file_storage": "local"})).await;
+        fx.put(json!({"title": "Far.png", "type": "image/png", "text": "", "_canonical_uri": "https://example.com/far.png"})).await;
+
+        assert_eq!(fx.rename("Old.png", json!({"to": "New.png"})).await.0, StatusCode::OK);
+        let new_name = offload_file_name("New.png", "image/png");
+        assert_eq!(fx.get("New.png").await.unwrap().field("_canonical_uri"), Some(format!("/files/{}", new_name).as_str()));
+        assert!(!fx.config.files_dir.join(&old_name).exists());
+        assert_eq!(std::fs::read(fx.config.files_dir.join(&new_name)).unwrap(), b"png");
+
+        // 外部文件不受影响
+        assert_eq!(fx.rename("Far.png", json!({"to": "Near.png"})).await.0, StatusCode::OK);
+        assert_eq!(fx.get("Near.png").await.unwrap().field("_canonical_uri"), Some("https://example.com/far.png"));
+    }
+}
@@ -0,0 +1,699 @@
+//! 在服务器端把单个条目渲染为静态 HTML (`GET /render/{title}`)
+//!
+//! 只实现 wikitext 的常用子集：标题、列表、表格、引用、代码块、行内格式、链接、嵌入和图片。
+//! 宏和微件不会被执行，原始 HTML 一律转义。
+//! 配置 `[render] public_filter` 后，匹配的条目无需登录即可访问，便于分享和被搜索引擎收录。
+
+use crate::{
+    AppError, AppResult, AppState, Authenticated, DataStore, ServerConfig, Tiddler, Tiddlers, export, filter::Wiki,
+    folder::is_binary_type, wikitext,
+};
+use axum::{
+    Extension,
+    body::Body,
+    extract,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine as _, engine::general_purpose};
+use serde::Deserialize;
+use std::{
+    cell::RefCell,
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct RenderConfig {
+    // 匹配此过滤器的条目 (如 `[tag[Public]]`) 无需登录即可通过 /render/ 访问；未设置时全部需要登录
+    #[serde(default)]
+    pub(crate) public_filter: Option<String>,
+}
+
+// 嵌入的最大深度，防止循环嵌入
+const MAX_TRANSCLUSION_DEPTH: usize = 8;
+// 渲染单个条目时最多读取的相关条目数
+const MAX_REFERENCED: usize = 1000;
+
+const STYLESHEET: &str = r#"
+body { max-width: 46rem; margin: 2rem auto; padding: 0 1rem; font: 16px/1.6 -apple-system, "Segoe UI", Helvetica, Arial, sans-serif; color: #333; }
+h1, h2, h3, h4, h5, h6 { line-height: 1.25; }
+a { color: #5778d8; text-decoration: none; }
+a:hover { text-decoration: underline; }
+a.missing { font-style: italic; color: #999; }
+pre, code { font-family: Menlo, Consolas, monospace; font-size: 0.9em; background: #f5f5f5; }
+pre { padding: 0.75em; overflow-x: auto; }
+blockquote { margin: 1em 0; padding-left: 1em; border-left: 4px solid #ddd; color: #666; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.25em 0.5em; }
+img { max-width: 100%; }
+.tags span { display: inline-block; margin-right: 0.5em; padding: 0 0.5em; border-radius: 1em; background: #eee; font-size: 0.85em; }
+"#;
+
+pub(crate) fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn is_safe_url(url: &str) -> bool {
+    ["http://", "https://", "mailto:"].iter().any(|scheme| url.starts_with(scheme))
+}
+
+fn is_image(tiddler: &Tiddler) -> bool {
+    tiddler.field("type").is_some_and(|t| t.starts_with("image/"))
+}
+
+/// 直接输出文件内容 (而不是渲染成页面) 的条目：二进制文件和 SVG 等图片
+pub(crate) fn is_asset(tiddler: &Tiddler) -> bool {
+    is_image(tiddler) || is_binary_type(tiddler.field("type").unwrap_or(""))
+}
+
+/// `selected` 加上它们链接或引用的文件，这些文件也需要能被访问，页面中的图片才能显示
+pub(crate) fn with_assets(wiki: &Wiki, selected: &[String]) -> HashSet<String> {
+    let mut visible: HashSet<String> = selected.iter().cloned().collect();
+    for title in selected {
+        let Some(tiddler) = wiki.get(title) else { continue };
+        let images = wikitext::image_sources(tiddler.field("text").unwrap_or(""));
+        for target in wikitext::links(tiddler).into_iter().chain(images) {
+            if wiki.get(&target).is_some_and(is_asset) {
+                visible.insert(target);
+            }
+        }
+    }
+    visible
+}
+
+/// wikitext 渲染器；`visible` 决定哪些条目可以被链接、嵌入和显示
+pub(crate) struct Renderer<'a> {
+    wiki: &'a Wiki,
+    visible: &'a dyn Fn(&str) -> bool,
+    // 条目链接的前缀，如 `/render/`
+    link_base: &'a str,
+    stack: RefCell<Vec<String>>,
+}
+
+impl<'a> Renderer<'a> {
+    pub(crate) fn new(wiki: &'a Wiki, visible: &'a dyn Fn(&str) -> bool, link_base: &'a str) -> Self {
+        Self { wiki, visible, link_base, stack: RefCell::new(Vec::new()) }
+    }
+
+    fn href(&self, title: &str) -> String {
+        format!("{}{}", self.link_base, urlencoding::encode(title))
+    }
+
+    /// 渲染条目正文
+    pub(crate) fn body(&self, tiddler: &Tiddler) -> String {
+        let text = tiddler.field("text").unwrap_or("");
+        if is_image(tiddler) {
+            return format!(r#"<p><img src="{}" alt="{}"></p>"#, escape(&self.href(&tiddler.title)), escape(&tiddler.title));
+        }
+        if !wikitext::is_wikitext(tiddler) {
+            return format!("<pre>{}</pre>", escape(text));
+        }
+        self.stack.borrow_mut().push(tiddler.title.clone());
+        let html = self.blocks(text);
+        self.stack.borrow_mut().pop();
+        html
+    }
+
+    fn link(&self, label: &str, target: &str) -> String {
+        if is_safe_url(target) {
+            return format!(r#"<a href="{}" rel="noopener">{}</a>"#, escape(target), escape(label));
+        }
+        match self.wiki.get(target) {
+            Some(_) if (self.visible)(target) => format!(r#"<a href="{}">{}</a>"#, escape(&self.href(target)), escape(label)),
+            None if (self.visible)(target) => format!(r#"<a class="missing">{}</a>"#, escape(label)),
+            // 不可访问的条目只显示文字
+            _ => escape(label),
+        }
+    }
+
+    fn image(&self, alt: &str, source: &str) -> String {
+        let src = if is_safe_url(source) || source.starts_with("data:image/") {
+            source.to_string()
+        } else if self.wiki.get(source).is_some() && (self.visible)(source) {
+            self.href(source)
+        } else {
+            return escape(alt);
+        };
+        format!(r#"<img src="{}" alt="{}">"#, escape(&src), escape(alt))
+    }
+
+    /// `{{Title}}`、`{{Title!!field}}`、`{{Title||Template}}` (模板被忽略)
+    fn transclude(&self, spec: &str, block: bool) -> String {
+        let target = spec.split("||").next().unwrap_or("").trim();
+        let (title, field) = match target.split_once("!!") {
+            Some((title, field)) => (title.trim(), Some(field.trim())),
+            None => (target.split("##").next().unwrap_or("").trim(), None),
+        };
+        let Some(tiddler) = self.wiki.get(title).filter(|_| (self.visible)(title)) else {
+            return String::new();
+        };
+        if let Some(field) = field {
+            let value = if field == "title" { Some(title) } else { tiddler.field(field) };
+            return escape(value.unwrap_or(""));
+        }
+        if is_image(tiddler) {
+            return self.image(title, title);
+        }
+        if self.stack.borrow().len() >= MAX_TRANSCLUSION_DEPTH || self.stack.borrow().iter().any(|t| t == title) {
+            return String::new();
+        }
+        if !wikitext::is_wikitext(tiddler) {
+            return format!("<pre>{}</pre>", escape(tiddler.field("text").unwrap_or("")));
+        }
+        self.stack.borrow_mut().push(title.to_string());
+        let text = tiddler.field("text").unwrap_or("");
+        let html = if block { format!("<div class=\"transclusion\">{}</div>", self.blocks(text)) } else { self.inline(text) };
+        self.stack.borrow_mut().pop();
+        html
+    }
+
+    // --- 块级语法 ---
+
+    fn blocks(&self, text: &str) -> String {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut out = String::new();
+        let mut paragraph: Vec<&str> = Vec::new();
+        let flush = |paragraph: &mut Vec<&str>, out: &mut String| {
+            if !paragraph.is_empty() {
+                out.push_str(&format!("<p>{}</p>\n", self.inline(&paragraph.join("\n"))));
+                paragraph.clear();
+            }
+        };
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i];
+            i += 1;
+            let trimmed = line.trim();
+
+            if trimmed.is_empty() {
+                flush(&mut paragraph, &mut out);
+            } else if let Some(lang) = trimmed.strip_prefix("```") {
+                flush(&mut paragraph, &mut out);
+                let start = i;
+                while i < lines.len() && !lines[i].trim_start().starts_with("```") {
+                    i += 1;
+                }
+                let class = if lang.trim().is_empty() { String::new() } else { format!(r#" class="language-{}""#, escape(lang.trim())) };
+                out.push_str(&format!("<pre><code{}>{}</code></pre>\n", class, escape(&lines[start..i.min(lines.len())].join("\n"))));
+                i += 1;
+            } else if line.starts_with("<<<") {
+                flush(&mut paragraph, &mut out);
+                let start = i;
+                while i < lines.len() && !lines[i].starts_with("<<<") {
+                    i += 1;
+                }
+                out.push_str(&format!("<blockquote>{}</blockquote>\n", self.blocks(&lines[start..i.min(lines.len())].join("\n"))));
+                i += 1;
+            } else if let Some(level) = line.find(|c| c != '!').filter(|&n| n > 0) {
+                flush(&mut paragraph, &mut out);
+                let level = level.min(6);
+                out.push_str(&format!("<h{0}>{1}</h{0}>\n", level, self.inline(line.trim_start_matches('!').trim())));
+            } else if trimmed.len() >= 3 && trimmed.chars().all(|c| c == '-') {
+                flush(&mut paragraph, &mut out);
+                out.push_str("<hr>\n");
+            } else if list_item(line).is_some() {
+                flush(&mut paragraph, &mut out);
+                let start = i - 1;
+                while i < lines.len() && list_item(lines[i]).is_some() {
+                    i += 1;
+                }
+                out.push_str(&self.list(&lines[start..i]));
+            } else if line.starts_with('|') {
+                flush(&mut paragraph, &mut out);
+                let start = i - 1;
+                while i < lines.len() && lines[i].starts_with('|') {
+                    i += 1;
+                }
+                out.push_str(&self.table(&lines[start..i]));
+            } else if let Some(quote) = line.strip_prefix('>') {
+                flush(&mut paragraph, &mut out);
+                out.push_str(&format!("<blockquote><p>{}</p></blockquote>\n", self.inline(quote.trim())));
+            } else if let Some(spec) = trimmed.strip_prefix("{{").and_then(|s| s.strip_suffix("}}"))
+                && !spec.contains("}}")
+            {
+                // 单独成行的嵌入按块级渲染
+                flush(&mut paragraph, &mut out);
+                out.push_str(&self.transclude(spec, true));
+                out.push('\n');
+            } else {
+                paragraph.push(line);
+            }
+        }
+        flush(&mut paragraph, &mut out);
+        out
+    }
+
+    /// 连续的列表行，`*` 为无序、`#` 为有序，可以混合嵌套
+    fn list(&self, lines: &[&str]) -> String {
+        let mut out = String::new();
+        let mut stack: Vec<char> = Vec::new();
+        let close = |kind: char| if kind == '#' { "</li></ol>" } else { "</li></ul>" };
+        for line in lines {
+            let Some((markers, text)) = list_item(line) else { continue };
+            let markers: Vec<char> = markers.chars().collect();
+            let common = stack.iter().zip(&markers).take_while(|(a, b)| a == b).count();
+            while stack.len() > common {
+                out.push_str(close(stack.pop().unwrap_or('*')));
+            }
+            if stack.len() == markers.len() {
+                out.push_str("</li><li>");
+            }
+            for &kind in &markers[stack.len()..] {
+                out.push_str(if kind == '#' { "<ol><li>" } else { "<ul><li>" });
+                stack.push(kind);
+            }
+            out.push_str(&self.inline(text));
+        }
+        while let Some(kind) = stack.pop() {
+            out.push_str(close(kind));
+        }
+        out.push('\n');
+        out
+    }
+
+    /// 表格行 `|a|b|`；末尾 `h` 为表头行，`c` 为标题，`k` (样式) 被忽略；以 `!` 开头的单元格为表头
+    fn table(&self, rows: &[&str]) -> String {
+        let mut caption = String::new();
+        let mut body = String::new();
+        for row in rows {
+            let Some(idx) = row.rfind('|').filter(|&idx| idx > 0) else { continue };
+            let kind = row[idx + 1..].trim();
+            let cells = &row[1..idx];
+            match kind {
+                "c" => caption = format!("<caption>{}</caption>", self.inline(cells.trim())),
+                "k" => {}
+                _ => {
+                    body.push_str("<tr>");
+                    for cell in wikitext::split_cells(cells) {
+                        let (header, cell) = match cell.trim().strip_prefix('!') {
+                            Some(cell) => (true, cell),
+                            None => (kind == "h", cell),
+                        };
+                        let tag = if header { "th" } else { "td" };
+                        body.push_str(&format!("<{0}>{1}</{0}>", tag, self.inline(cell.trim())));
+                    }
+                    body.push_str("</tr>");
+                }
+            }
+        }
+        format!("<table>{}{}</table>\n", caption, body)
+    }
+
+    // --- 行内语法 ---
+
+    fn inline(&self, text: &str) -> String {
+        wikitext::split_code(text)
+            .into_iter()
+            .map(|(code, segment)| {
+                if code {
+                    format!("<code>{}</code>", escape(segment.trim_matches('`')))
+                } else {
+                    self.inline_text(segment)
+                }
+            })
+            .collect()
+    }
+
+    fn inline_text(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut open: Vec<&str> = Vec::new();
+        let mut toggle = |out: &mut String, tag: &'static str| {
+            if open.last() == Some(&tag) {
+                open.pop();
+                out.push_str(&format!("</{}>", tag));
+            } else {
+                open.push(tag);
+                out.push_str(&format!("<{}>", tag));
+            }
+        };
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            if is_safe_url(rest) && !rest.starts_with("mailto:") {
+                let end = rest.find(|c: char| c.is_whitespace() || c == '<' || c == '"').unwrap_or(rest.len());
+                let url = rest[..end].trim_end_matches(['.', ',', ')', ';']);
+                out.push_str(&format!(r#"<a href="{0}" rel="noopener">{0}</a>"#, escape(url)));
+                rest = &rest[url.len()..];
+            } else if let Some(inner) = rest.strip_prefix("[[")
+                && let Some(end) = inner.find("]]")
+            {
+                let body = &inner[..end];
+                let (label, target) = body.split_once('|').unwrap_or((body, body));
+                out.push_str(&self.link(label.trim(), target.trim()));
+                rest = &inner[end + 2..];
+            } else if let Some(inner) = rest.strip_prefix("[ext[")
+                && let Some(end) = inner.find("]]")
+            {
+                let body = &inner[..end];
+                let (label, target) = body.split_once('|').unwrap_or((body, body));
+                out.push_str(&self.link(label.trim(), target.trim()));
+                rest = &inner[end + 2..];
+            } else if let Some(attrs) = rest.strip_prefix("[img")
+                && let Some(open) = attrs.find('[')
+                && let Some(end) = attrs[open..].find("]]")
+            {
+                let body = &attrs[open + 1..open + end];
+                let (alt, source) = body.split_once('|').unwrap_or(("", body));
+                out.push_str(&self.image(alt.trim(), source.trim()));
+                rest = &attrs[open + end + 2..];
+            } else if let Some(inner) = rest.strip_prefix("{{{")
+                && let Some(end) = inner.find("}}}")
+            {
+                // 过滤器嵌入与宏调用一样不会被求值
+                rest = &inner[end + 3..];
+            } else if let Some(inner) = rest.strip_prefix("{{")
+                && let Some(end) = inner.find("}}")
+            {
+                out.push_str(&self.transclude(&inner[..end], false));
+                rest = &inner[end + 2..];
+            } else if let Some(inner) = rest.strip_prefix("<<")
+                && let Some(end) = inner.find(">>")
+            {
+                // 宏调用不会被执行
+                rest = &inner[end + 2..];
+            } else if (rest.starts_with("<$") || rest.starts_with("</$") || rest.starts_with("<!--"))
+                && let Some(end) = rest.find('>')
+            {
+                // 去掉微件标签和注释，保留其中的内容
+                let end = if rest.starts_with("<!--") { rest.find("-->").map_or(end, |e| e + 2) } else { end };
+                rest = &rest[end + 1..];
+            } else if let Some(after) = rest.strip_prefix("''") {
+                toggle(&mut out, "strong");
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("//") {
+                toggle(&mut out, "em");
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("__") {
+                toggle(&mut out, "u");
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("~~") {
+                toggle(&mut out, "s");
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("^^") {
+                toggle(&mut out, "sup");
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix(",,") {
+                toggle(&mut out, "sub");
+                rest = after;
+            } else {
+                out.push_str(&escape(&rest[..c.len_utf8()]));
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        // 关闭未配对的格式
+        while let Some(tag) = open.pop() {
+            out.push_str(&format!("</{}>", tag));
+        }
+        out
+    }
+}
+
+/// 列表行：返回 (标记, 内容)
+fn list_item(line: &str) -> Option<(&str, &str)> {
+    let depth = line.find(|c| c != '*' && c != '#')?;
+    (depth > 0 && line[depth..].starts_with(' ')).then(|| (&line[..depth], line[depth..].trim()))
+}
+
+/// 带样式表的完整页面
+pub(crate) fn page(title: &str, tags: &[String], body: &str) -> String {
+    let tags = if tags.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<p class=\"tags\">{}</p>\n",
+            tags.iter().map(|t| format!("<span>{}</span>", escape(t))).collect::<String>()
+        )
+    };
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{0}</title>\n<style>{1}</style>\n</head>\n<body>\n<article>\n<h1>{0}</h1>\n{2}{3}</article>\n</body>\n</html>\n",
+        escape(title),
+        STYLESHEET,
+        tags,
+        body
+    )
+}
+
+/// 文件条目的内容，用于 `<img>`；外部文件重定向到原地址
+pub(crate) async fn asset_response(tiddler: &Tiddler, state: &AppState, config: &ServerConfig) -> AppResult<Response> {
+    let data = match export::load_asset(tiddler, state, config).await? {
+        Some(data) => data,
+        None => match tiddler.field("_canonical_uri") {
+            Some(uri) if is_safe_url(uri) => {
+                return Ok((StatusCode::FOUND, [(header::LOCATION, uri.to_string())]).into_response());
+            }
+            Some(_) => return Ok(StatusCode::NOT_FOUND.into_response()),
+            // SVG 等文本类型的内容就是正文本身
+            None if !is_binary_type(tiddler.field("type").unwrap_or("")) => {
+                tiddler.field("text").unwrap_or("").as_bytes().to_vec()
+            }
+            None => general_purpose::STANDARD
+                .decode(tiddler.field("text").unwrap_or("").trim())
+                .map_err(|e| AppError::Response(format!("'{}' is not valid base64: {}", tiddler.title, e)))?,
+        },
+    };
+    Response::builder()
+        .header(header::CONTENT_TYPE, tiddler.field("type").unwrap_or("application/octet-stream"))
+        .header(header::CACHE_CONTROL, "private, max-age=3600")
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        // SVG 等文件被直接打开时也不能执行脚本
+        .header(header::CONTENT_SECURITY_POLICY, "sandbox")
+        .body(Body::from(data))
+        .map_err(|e| AppError::Response(format!("error building response: {}", e)))
+}
+
+pub(crate) fn html_response(html: String) -> AppResult<Response> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CONTENT_SECURITY_POLICY, "default-src 'none'; img-src * data:; style-src 'unsafe-inline'")
+        .body(Body::from(html))
+        .map_err(|e| AppError::Response(format!("error building response: {}", e)))
+}
+
+/// 未登录时可以访问的条目：匹配 `public_filter` 的条目及其引用的文件
+pub(crate) fn public_titles(wiki: &Wiki, config: &RenderConfig) -> HashSet<String> {
+    match &config.public_filter {
+        Some(filter) => match wiki.filter(filter) {
+            Ok(titles) => with_assets(wiki, &titles),
+            Err(e) => {
+                tracing::warn!("Invalid render.public_filter '{}': {}", filter, e);
+                HashSet::new()
+            }
+        },
+        None => HashSet::new(),
+    }
+}
+
+/// 按写入代数缓存的公开条目，匿名请求不必每次都读取整个 Wiki
+#[derive(Default)]
+pub(crate) struct PublicTitles(Mutex<Option<(u64, Arc<HashSet<String>>)>>);
+
+impl PublicTitles {
+    /// `generation` 须在开始读取之前从 [`DataStore`] 取得
+    pub(crate) fn get(&self, tiddlers: &Tiddlers, generation: u64, config: &RenderConfig) -> AppResult<Arc<HashSet<String>>> {
+        if let Ok(cached) = self.0.lock()
+            && let Some((cached, titles)) = cached.as_ref()
+            && *cached == generation
+        {
+            return Ok(titles.clone());
+        }
+        let titles = Arc::new(public_titles(&Wiki::new(tiddlers.all()?), config));
+        if let Ok(mut cached) = self.0.lock() {
+            *cached = Some((generation, titles.clone()));
+        }
+        Ok(titles)
+    }
+}
+
+/// 渲染 `title` 需要的条目：它本身，以及 (逐层嵌入的) 链接、嵌入和图片指向的条目，
+/// 这样渲染一个页面不必读取整个 Wiki
+pub(crate) fn referenced_wiki(tiddlers: &Tiddlers, title: &str) -> AppResult<Wiki> {
+    let mut loaded = Vec::new();
+    let mut seen = HashSet::from([title.to_string()]);
+    let mut queue = VecDeque::from([(title.to_string(), 0)]);
+    while let Some((title, depth)) = queue.pop_front() {
+        let Some(tiddler) = tiddlers.get(&title)? else { continue };
+        // 超过嵌入深度的条目不会被渲染，它引用的条目也就不需要了
+        if depth < MAX_TRANSCLUSION_DEPTH {
+            let images = wikitext::image_sources(tiddler.field("text").unwrap_or(""));
+            for target in wikitext::links(&tiddler).into_iter().chain(images) {
+                if seen.len() < MAX_REFERENCED && seen.insert(target.clone()) {
+                    queue.push_back((target, depth + 1));
+                }
+            }
+        }
+        loaded.push(tiddler);
+    }
+    Ok(Wiki::new(loaded))
+}
+
+fn login_required() -> Response {
+    (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Basic realm=\"TiddlyWiki Server\"")]).into_response()
+}
+
+// --- Handler: GET /render/{title} ---
+pub(crate) async fn render_tiddler(
+    Extension(ds): Extension<DataStore>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(config): Extension<ServerConfig>,
+    Extension(render_config): Extension<Arc<RenderConfig>>,
+    Extension(public): Extension<Arc<PublicTitles>>,
+    Extension(Authenticated(authenticated)): Extension<Authenticated>,
+    extract::Path(title): extract::Path<String>,
+) -> AppResult<Response> {
+    // 没有公开的条目时，未登录的请求不必读取数据库
+    if !authenticated && render_config.public_filter.is_none() {
+        return Ok(login_required());
+    }
+    let generation = ds.generation();
+    let rendered = ds
+        .read(move |tiddlers| {
+            let public = if authenticated {
+                Arc::default()
+            } else {
+                public.get(tiddlers, generation, &render_config)?
+            };
+            let visible = |t: &str| authenticated || public.contains(t);
+            if !visible(&title) {
+                return Ok(None);
+            }
+            let wiki = referenced_wiki(tiddlers, &title)?;
+            let Some(tiddler) = wiki.get(&title).cloned() else {
+                return Ok(None);
+            };
+            if is_asset(&tiddler) {
+                return Ok(Some((tiddler, None)));
+            }
+            let renderer = Renderer::new(&wiki, &visible, "/render/");
+            let html = page(&tiddler.title, &wikitext::tags(&tiddler), &renderer.body(&tiddler));
+            Ok(Some((tiddler, Some(html))))
+        })
+        .await?;
+
+    match rendered {
+        Some((_, Some(html))) => html_response(html),
+        Some((tiddler, None)) => asset_response(&tiddler, &state, &config).await,
+        // 未登录时不区分「不存在」和「不公开」，并提示登录
+        None if !authenticated => Ok(login_required()),
+        None => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn wiki() -> Wiki {
+        let tiddlers = [
+            json!({"title": "Home", "text": "x"}),
+            json!({"title": "Secret", "text": "hidden"}),
+            json!({"title": "Part", "text": "part ''one''", "caption": "Cap <b>"}),
+            json!({"title": "Loop", "text": "again {{Loop}}"}),
+            json!({"title": "Pic", "type": "image/png", "text": "AAAA"}),
+            json!({"title": "Code", "type": "application/javascript", "text": "a < b"}),
+            json!({"title": "Logo.svg", "type": "image/svg+xml", "text": "<svg/>"}),
+            json!({"title": "Post", "tags": "blog", "text": "[img[Logo.svg]] [[Pic]] [[Part]] [[Secret]]"}),
+        ];
+        Wiki::new(tiddlers.into_iter().map(|v| Tiddler::from_value(v).unwrap()).collect())
+    }
+
+    /// 渲染一段正文；`Secret` 不可见
+    fn render(text: &str) -> String {
+        let wiki = wiki();
+        let visible = |t: &str| t != "Secret";
+        let renderer = Renderer::new(&wiki, &visible, "/render/");
+        let tiddler = Tiddler::from_value(json!({ "title": "Home", "text": text })).unwrap();
+        renderer.body(&tiddler)
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(escape(r#"<a href="x">&'"#), "&lt;a href=&quot;x&quot;&gt;&amp;&#39;");
+        assert_eq!(render("<script>alert(1)</script>"), "<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>\n");
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(render("!! Title\n\ntext\nmore"), "<h2>Title</h2>\n<p>text\nmore</p>\n");
+        assert_eq!(render("* a\n** b\n# c"), "<ul><li>a<ul><li>b</li></ul></li></ul><ol><li>c</li></ol>\n");
+        assert_eq!(render("```rust\nlet x = 1 < 2;\n```"), "<pre><code class=\"language-rust\">let x = 1 &lt; 2;</code></pre>\n");
+        assert_eq!(render("<<<\nquoted\n<<<\n---"), "<blockquote><p>quoted</p>\n</blockquote>\n<hr>\n");
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(
+            render("|Caption|c\n|A|B|h\n|!x|[[Home|Home]]|\n|"),
+            "<table><caption>Caption</caption><tr><th>A</th><th>B</th></tr><tr><th>x</th><td><a href=\"/render/Home\">Home</a></td></tr></table>\n"
+        );
+    }
+
+    #[test]
+    fn links_respect_visibility() {
+        assert_eq!(render("[[Home]] [[go|Missing]] [[Secret]]"), "<p><a href=\"/render/Home\">Home</a> <a class=\"missing\">go</a> Secret</p>\n");
+        assert_eq!(render("[ext[site|https://x.org]] [[bad|javascript:alert(1)]]"), "<p><a href=\"https://x.org\" rel=\"noopener\">site</a> <a class=\"missing\">bad</a></p>\n");
+        assert_eq!(render("see https://x.org/a."), "<p>see <a href=\"https://x.org/a\" rel=\"noopener\">https://x.org/a</a>.</p>\n");
+    }
+
+    #[test]
+    fn transclusions() {
+        assert_eq!(render("{{Part}}"), "<div class=\"transclusion\"><p>part <strong>one</strong></p>\n</div>\n");
+        assert_eq!(render("a {{Part!!caption}} {{Secret}} {{{ [tag[x]] }}} <<now>>"), "<p>a Cap &lt;b&gt;   </p>\n");
+        // 循环嵌入只展开一次
+        assert_eq!(render("{{Loop}}"), "<div class=\"transclusion\"><p>again </p>\n</div>\n");
+        assert_eq!(render("{{Pic}} [img[Pic]] [img[Secret]]"), "<p><img src=\"/render/Pic\" alt=\"Pic\"> <img src=\"/render/Pic\" alt=\"\"> </p>\n");
+    }
+
+    #[test]
+    fn non_wikitext_bodies() {
+        let wiki = wiki();
+        let visible = |_: &str| true;
+        let renderer = Renderer::new(&wiki, &visible, "/share/t/");
+        assert_eq!(renderer.body(wiki.get("Code").unwrap()), "<pre>a &lt; b</pre>");
+        assert_eq!(renderer.body(wiki.get("Pic").unwrap()), "<p><img src=\"/share/t/Pic\" alt=\"Pic\"></p>");
+    }
+
+    #[test]
+    fn public_titles_include_referenced_assets() {
+        let wiki = wiki();
+        assert!(is_asset(wiki.get("Logo.svg").unwrap()) && is_asset(wiki.get("Pic").unwrap()));
+        assert!(!is_asset(wiki.get("Code").unwrap()));
+        let config = RenderConfig { public_filter: Some("[tag[blog]]".to_string()) };
+        let mut public: Vec<String> = public_titles(&wiki, &config).into_iter().collect();
+        public.sort();
+        assert_eq!(public, ["Logo.svg", "Pic", "Post"]);
+        assert!(public_titles(&wiki, &RenderConfig::default()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn public_titles_are_cached_per_generation() {
+        let tmp = crate::testutil::TempDir::new("render-public");
+        let ds = crate::testutil::datastore(&crate::testutil::server_config(tmp.path()));
+        let cache = Arc::new(PublicTitles::default());
+        let config = Arc::new(RenderConfig { public_filter: Some("[tag[blog]]".to_string()) });
+        let public = |cache: Arc<PublicTitles>, config: Arc<RenderConfig>| {
+            let generation = ds.generation();
+            ds.read(move |t| cache.get(t, generation, &config))
+        };
+
+        let before = public(cache.clone(), config.clone()).await.unwrap();
+        assert!(before.is_empty());
+        assert!(Arc::ptr_eq(&before, &public(cache.clone(), config.clone()).await.unwrap()));
+
+        let post = Tiddler::from_value(json!({"title": "Post", "tags": "blog"})).unwrap();
+        ds.write(move |t| t.put(post)).await.unwrap();
+        let after = public(cache, config).await.unwrap();
+        assert!(after.contains("Post"));
+    }
+}
@@ -0,0 +1,473 @@
+//! 分享链接：为单个条目或一个过滤器生成带签名、可过期、可撤销的只读链接
+//!
+//! 令牌形如 `<id>.<签名>`，签名是用数据库中的密钥对 id 计算的 HMAC-SHA256，
+//! 伪造或篡改的令牌无需查询数据库即可拒绝。`/share/` 下的页面不经过 HTTP 认证，
+//! 访问者只能看到分享的条目 (或过滤器选中的条目)，以及这些条目引用到的图片等二进制条目。
+//! 访问次数在内存中合并后定期写入，匿名访问不会每次都占用写连接。
+
+use crate::{
+    AppError, AppResult, DataStore, ServerConfig, Tiddlers, crypto,
+    filter::Wiki,
+    render::{self, Renderer, referenced_wiki},
+    wikitext,
+};
+use axum::{
+    Extension, extract,
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::OptionalExtension;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration as StdDuration,
+};
+use tokio::sync::mpsc;
+
+// 未指定时链接的有效期：7 天
+const DEFAULT_EXPIRES_IN: u64 = 7 * 24 * 3600;
+// 访问次数的写入间隔，以及两次写入之间最多暂存的访问数 (超出的不计)
+const VIEW_FLUSH_INTERVAL: StdDuration = StdDuration::from_secs(10);
+const VIEW_QUEUE: usize = 4096;
+
+#[derive(Deserialize)]
+pub(crate) struct ShareRequest {
+    // 分享单个条目
+    title: Option<String>,
+    // 或者分享过滤器选中的条目
+    filter: Option<String>,
+    label: Option<String>,
+    // 有效期 (秒)，0 表示永不过期
+    #[serde(default = "default_expires_in")]
+    expires_in: u64,
+}
+
+fn default_expires_in() -> u64 {
+    DEFAULT_EXPIRES_IN
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct ShareInfo {
+    id: String,
+    token: String,
+    url: String,
+    // 分享单个条目时为标题，否则为过滤器
+    title: Option<String>,
+    filter: Option<String>,
+    label: Option<String>,
+    created: String,
+    expires: Option<String>,
+    expired: bool,
+    revoked: bool,
+    views: u64,
+    last_viewed: Option<String>,
+}
+
+fn secret(tiddlers: &Tiddlers) -> AppResult<Vec<u8>> {
+    let value: String = tiddlers
+        .cxn
+        .query_row("SELECT value FROM settings WHERE key = 'share_secret'", [], |r| r.get(0))
+        .map_err(|e| AppError::Database(format!("Error reading share secret: {}", e)))?;
+    hex::decode(value).map_err(|e| AppError::Database(format!("Invalid share secret: {}", e)))
+}
+
+fn token(secret: &[u8], id: &str) -> String {
+    format!("{}.{}", id, general_purpose::URL_SAFE_NO_PAD.encode(crypto::sign(secret, id.as_bytes())))
+}
+
+/// 校验令牌签名，返回其中的 id
+fn verify_token<'a>(secret: &[u8], token: &'a str) -> Option<&'a str> {
+    let (id, signature) = token.split_once('.')?;
+    let signature = general_purpose::URL_SAFE_NO_PAD.decode(signature).ok()?;
+    crypto::verify(secret, id.as_bytes(), &signature).then_some(id)
+}
+
+fn is_expired(expires: Option<&str>) -> bool {
+    expires
+        .and_then(|e| DateTime::parse_from_rfc3339(e).ok())
+        .is_some_and(|e| e <= Utc::now())
+}
+
+const SELECT_SHARES: &str =
+    "SELECT id, title, NULLIF(filter, ''), label, created, expires, revoked, views, last_viewed FROM shares";
+
+fn read_share(row: &rusqlite::Row, secret: &[u8]) -> rusqlite::Result<ShareInfo> {
+    let id: String = row.get(0)?;
+    let expires: Option<String> = row.get(5)?;
+    let token = token(secret, &id);
+    Ok(ShareInfo {
+        url: format!("/share/{}", token),
+        token,
+        id,
+        title: row.get(1)?,
+        filter: row.get(2)?,
+        label: row.get(3)?,
+        created: row.get(4)?,
+        expired: is_expired(expires.as_deref()),
+        expires,
+        revoked: row.get(6)?,
+        views: row.get(7)?,
+        last_viewed: row.get(8)?,
+    })
+}
+
+fn bad_request(message: String) -> Response {
+    (StatusCode::BAD_REQUEST, axum::Json(json!({ "error": message }))).into_response()
+}
+
+// --- Handler: POST /api/shares ---
+pub(crate) async fn create_share(
+    Extension(ds): Extension<DataStore>,
+    extract::Json(request): extract::Json<ShareRequest>,
+) -> AppResult<Response> {
+    // 分享单个条目时条目必须存在，分享过滤器时过滤器必须合法
+    let (title, filter) = match (request.title, request.filter) {
+        (Some(title), None) => {
+            let lookup = title.clone();
+            if ds.read(move |tiddlers| tiddlers.get(&lookup)).await?.is_none() {
+                return Ok(bad_request(format!("tiddler '{}' does not exist", title)));
+            }
+            (Some(title), String::new())
+        }
+        (None, Some(filter)) => {
+            if let Err(e) = Wiki::new(Vec::new()).filter(&filter) {
+                return Ok(bad_request(format!("invalid filter: {}", e)));
+            }
+            (None, filter)
+        }
+        _ => return Ok(bad_request("specify exactly one of 'title' or 'filter'".to_string())),
+    };
+
+    let id = crypto::random_hex(16);
+    let expires = (request.expires_in > 0).then(|| {
+        let seconds = i64::try_from(request.expires_in).unwrap_or(i64::MAX / 1000);
+        (Utc::now() + Duration::try_seconds(seconds).unwrap_or(Duration::MAX)).format("%Y-%m-%dT%H:%M:%SZ").to_string()
+    });
+    let label = request.label;
+    let share = ds
+        .write(move |tiddlers| {
+            tiddlers
+                .cxn
+                .execute(
+                    "INSERT INTO shares (id, title, filter, label, expires) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    rusqlite::params![id, title, filter, label, expires],
+                )
+                .map_err(|e| AppError::Database(format!("Error creating share: {}", e)))?;
+            let secret = secret(tiddlers)?;
+            tiddlers
+                .cxn
+                .query_row(&format!("{} WHERE id = ?1", SELECT_SHARES), [&id], |row| read_share(row, &secret))
+                .map_err(AppError::from)
+        })
+        .await?;
+    tracing::info!(
+        "Created share {} for {}",
+        share.id,
+        share.title.as_deref().or(share.filter.as_deref()).unwrap_or_default()
+    );
+    Ok((StatusCode::CREATED, axum::Json(share)).into_response())
+}
+
+// --- Handler: GET /api/shares ---
+pub(crate) async fn list_shares(Extension(ds): Extension<DataStore>) -> AppResult<axum::Json<Vec<ShareInfo>>> {
+    let shares = ds
+        .read(|tiddlers| {
+            let secret = secret(tiddlers)?;
+            let mut stmt = tiddlers
+                .cxn
+                .prepare_cached(&format!("{} ORDER BY created DESC", SELECT_SHARES))
+                .map_err(AppError::from)?;
+            let rows = stmt.query_map([], |row| read_share(row, &secret)).map_err(AppError::from)?;
+            rows.collect::<Result<Vec<_>, _>>().map_err(AppError::from)
+        })
+        .await?;
+    Ok(axum::Json(shares))
+}
+
+// --- Handler: DELETE /api/shares/{id} ，撤销分享 ---
+pub(crate) async fn revoke_share(
+    Extension(ds): Extension<DataStore>,
+    extract::Path(id): extract::Path<String>,
+) -> AppResult<StatusCode> {
+    let updated = ds
+        .write(move |tiddlers| {
+            tiddlers
+                .cxn
+                .execute("UPDATE shares SET revoked = 1 WHERE id = ?1", [&id])
+                .map_err(|e| AppError::Database(format!("Error revoking share: {}", e)))
+        })
+        .await?;
+    Ok(if updated == 0 { StatusCode::NOT_FOUND } else { StatusCode::NO_CONTENT })
+}
+
+// --- 公开访问 ---
+
+/// 令牌对应的分享：选中的条目 (按过滤器顺序) 和允许访问的全部标题 (含引用的二进制条目)
+struct Shared {
+    id: String,
+    wiki: Wiki,
+    selected: Vec<String>,
+    visible: HashSet<String>,
+}
+
+async fn resolve(ds: &DataStore, token: String) -> AppResult<Result<Shared, StatusCode>> {
+    ds.read(move |tiddlers| {
+        let secret = secret(tiddlers)?;
+        let Some(id) = verify_token(&secret, &token) else {
+            return Ok(Err(StatusCode::NOT_FOUND));
+        };
+        let row: Option<(Option<String>, String, Option<String>, bool)> = tiddlers
+            .cxn
+            .query_row("SELECT title, filter, expires, revoked FROM shares WHERE id = ?1", [id], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?))
+            })
+            .optional()
+            .map_err(AppError::from)?;
+        let Some((title, filter, expires, revoked)) = row else {
+            return Ok(Err(StatusCode::NOT_FOUND));
+        };
+        if revoked || is_expired(expires.as_deref()) {
+            return Ok(Err(StatusCode::GONE));
+        }
+
+        // 只有求值过滤器时才需要整个 Wiki
+        let (wiki, selected) = match title {
+            Some(title) => (referenced_wiki(tiddlers, &title)?, vec![title]),
+            None => {
+                let wiki = Wiki::new(tiddlers.all()?);
+                let selected = wiki.filter(&filter).unwrap_or_default();
+                (wiki, selected)
+            }
+        };
+        let selected: Vec<String> = selected.into_iter().filter(|t| wiki.get(t).is_some()).collect();
+        let visible = render::with_assets(&wiki, &selected);
+        Ok(Ok(Shared { id: id.to_string(), wiki, selected, visible }))
+    })
+    .await
+}
+
+/// 访问计数：页面请求只把分享 id 放入队列，后台任务每隔一段时间合并后写入一次
+#[derive(Clone)]
+pub(crate) struct ViewCounter(mpsc::Sender<String>);
+
+impl ViewCounter {
+    pub(crate) fn spawn(ds: DataStore) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<String>(VIEW_QUEUE);
+        tokio::spawn(async move {
+            while let Some(id) = receiver.recv().await {
+                tokio::time::sleep(VIEW_FLUSH_INTERVAL).await;
+                let mut views = HashMap::from([(id, 1u64)]);
+                while let Ok(id) = receiver.try_recv() {
+                    *views.entry(id).or_default() += 1;
+                }
+                flush_views(&ds, views).await;
+            }
+        });
+        Self(sender)
+    }
+
+    fn count(&self, id: String) {
+        // 队列已满说明访问过多，丢弃计数即可
+        let _ = self.0.try_send(id);
+    }
+}
+
+async fn flush_views(ds: &DataStore, views: HashMap<String, u64>) {
+    let result = ds
+        .write(move |tiddlers| {
+            tiddlers.transaction(|tiddlers| {
+                for (id, n) in views {
+                    tiddlers
+                        .cxn
+                        .execute(
+                            "UPDATE shares SET views = views + ?2, last_viewed = strftime('%Y-%m-%dT%H:%M:%SZ', 'now') WHERE id = ?1",
+                            rusqlite::params![id, n],
+                        )
+                        .map_err(AppError::from)?;
+                }
+                Ok(())
+            })
+        })
+        .await;
+    if let Err(e) = result {
+        tracing::warn!("Failed to count share views: {:?}", e);
+    }
+}
+
+/// 分享页面不应被收录，也不应通过 Referer 泄露令牌
+fn private_page(mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    headers.insert("x-robots-tag", HeaderValue::from_static("noindex, nofollow"));
+    headers.insert(header::REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+    response
+}
+
+fn render_page(shared: &Shared, token: &str, title: &str) -> Option<String> {
+    let tiddler = shared.wiki.get(title)?;
+    let visible = |t: &str| shared.visible.contains(t);
+    let link_base = format!("/share/{}/", token);
+    let renderer = Renderer::new(&shared.wiki, &visible, &link_base);
+    Some(render::page(&tiddler.title, &wikitext::tags(tiddler), &renderer.body(tiddler)))
+}
+
+// --- Handler: GET /share/{token} ，只有一个条目时直接显示，否则显示目录 ---
+pub(crate) async fn view_share(
+    Extension(ds): Extension<DataStore>,
+    Extension(views): Extension<ViewCounter>,
+    extract::Path(token): extract::Path<String>,
+) -> AppResult<Response> {
+    let shared = match resolve(&ds, token.clone()).await? {
+        Ok(shared) => shared,
+        Err(status) => return Ok(status.into_response()),
+    };
+    let html = match shared.selected.as_slice() {
+        [title] => render_page(&shared, &token, title).unwrap_or_default(),
+        titles => {
+            let items: String = titles
+                .iter()
+                .map(|t| {
+                    format!(
+                        "<li><a href=\"/share/{}/{}\">{}</a></li>",
+                        token,
+                        render::escape(&urlencoding::encode(t)),
+                        render::escape(t)
+                    )
+                })
+                .collect();
+            render::page("Shared tiddlers", &[], &format!("<ul>{}</ul>\n", items))
+        }
+    };
+    views.count(shared.id);
+    Ok(private_page(render::html_response(html)?))
+}
+
+// --- Handler: GET /share/{token}/{title} ---
+pub(crate) async fn view_shared_tiddler(
+    Extension(ds): Extension<DataStore>,
+    Extension(state): Extension<Arc<crate::AppState>>,
+    Extension(config): Extension<ServerConfig>,
+    Extension(views): Extension<ViewCounter>,
+    extract::Path((token, title)): extract::Path<(String, String)>,
+) -> AppResult<Response> {
+    let shared = match resolve(&ds, token.clone()).await? {
+        Ok(shared) => shared,
+        Err(status) => return Ok(status.into_response()),
+    };
+    let Some(tiddler) = shared.wiki.get(&title).filter(|_| shared.visible.contains(&title)) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    // 图片等二进制条目只返回内容，不计入访问次数
+    if render::is_asset(tiddler) {
+        return Ok(private_page(render::asset_response(tiddler, &state, &config).await?));
+    }
+    let html = render_page(&shared, &token, &title).unwrap_or_default();
+    views.count(shared.id);
+    Ok(private_page(render::html_response(html)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Tiddler, testutil};
+    use axum::body::to_bytes;
+
+    #[test]
+    fn tokens() {
+        let secret = b"secret";
+        let token = token(secret, "abc");
+        assert_eq!(verify_token(secret, &token), Some("abc"));
+        assert_eq!(verify_token(b"other", &token), None);
+        let (id, signature) = token.split_once('.').unwrap();
+        assert_eq!(verify_token(secret, &format!("abd.{}", signature)), None);
+        let mut tampered = signature.to_string();
+        tampered.replace_range(..1, if signature.starts_with('A') { "B" } else { "A" });
+        assert_eq!(verify_token(secret, &format!("{}.{}", id, tampered)), None);
+        assert_eq!(verify_token(secret, &format!("{}.!!", id)), None);
+        assert_eq!(verify_token(secret, id), None);
+        assert_eq!(verify_token(secret, ""), None);
+    }
+
+    #[test]
+    fn expiry() {
+        assert!(!is_expired(None));
+        assert!(is_expired(Some("2000-01-01T00:00:00Z")));
+        assert!(!is_expired(Some("2999-01-01T00:00:00Z")));
+        // 无法解析的时间不算过期
+        assert!(!is_expired(Some("soon")));
+    }
+
+    async fn share(ds: &DataStore, request: serde_json::Value) -> (StatusCode, serde_json::Value) {
+        let resp = create_share(Extension(ds.clone()), extract::Json(serde_json::from_value(request).unwrap())).await.unwrap();
+        let status = resp.status();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn sorted(titles: &HashSet<String>) -> Vec<&str> {
+        let mut titles: Vec<&str> = titles.iter().map(String::as_str).collect();
+        titles.sort();
+        titles
+    }
+
+    #[tokio::test]
+    async fn resolve_visibility() {
+        let tmp = testutil::TempDir::new("share");
+        let ds = testutil::datastore(&testutil::server_config(tmp.path()));
+        let tiddlers = [
+            json!({"title": "Post", "tags": "blog", "text": "[img[Logo.svg]] [[Photo.png]] [[Secret]] {{Part}}"}),
+            json!({"title": "Other", "tags": "blog", "text": "plain"}),
+            json!({"title": "Logo.svg", "type": "image/svg+xml", "text": "<svg/>"}),
+            json!({"title": "Photo.png", "type": "image/png", "text": "AAAA"}),
+            json!({"title": "Part", "text": "part"}),
+            json!({"title": "Secret", "text": "hidden"}),
+        ];
+        for value in tiddlers {
+            let tiddler = Tiddler::from_value(value).unwrap();
+            ds.write(move |t| t.put(tiddler)).await.unwrap();
+        }
+
+        assert_eq!(share(&ds, json!({"title": "Missing"})).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(share(&ds, json!({"filter": "[tag[blog]"})).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(share(&ds, json!({"title": "Post", "filter": "[tag[blog]]"})).await.0, StatusCode::BAD_REQUEST);
+
+        // 单个条目：只有它本身和它引用的文件可见，链接和嵌入的普通条目不可见
+        let (status, body) = share(&ds, json!({"title": "Post"})).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let Ok(shared) = resolve(&ds, body["token"].as_str().unwrap().to_string()).await.unwrap() else {
+            panic!("title share did not resolve");
+        };
+        assert_eq!(shared.selected, ["Post"]);
+        assert_eq!(sorted(&shared.visible), ["Logo.svg", "Photo.png", "Post"]);
+
+        let (_, body) = share(&ds, json!({"filter": "[tag[blog]sort[]]", "expires_in": 0})).await;
+        let token = body["token"].as_str().unwrap().to_string();
+        let Ok(shared) = resolve(&ds, token.clone()).await.unwrap() else {
+            panic!("filter share did not resolve");
+        };
+        assert_eq!(shared.selected, ["Other", "Post"]);
+        assert_eq!(sorted(&shared.visible), ["Logo.svg", "Other", "Photo.png", "Post"]);
+
+        // 伪造、撤销和过期的分享
+        let forged = format!("{}x", token);
+        assert!(matches!(resolve(&ds, forged).await.unwrap(), Err(StatusCode::NOT_FOUND)));
+        let id = body["id"].as_str().unwrap().to_string();
+        assert_eq!(revoke_share(Extension(ds.clone()), extract::Path(id)).await.unwrap(), StatusCode::NO_CONTENT);
+        assert!(matches!(resolve(&ds, token).await.unwrap(), Err(StatusCode::GONE)));
+
+        let (_, body) = share(&ds, json!({"title": "Other"})).await;
+        let id = body["id"].as_str().unwrap().to_string();
+        ds.write(move |t| {
+            t.cxn
+                .execute("UPDATE shares SET expires = '2000-01-01T00:00:00Z' WHERE id = ?1", [id])
+                .map_err(AppError::from)
+        })
+        .await
+        .unwrap();
+        let token = body["token"].as_str().unwrap().to_string();
+        assert!(matches!(resolve(&ds, token).await.unwrap(), Err(StatusCode::GONE)));
+    }
+}
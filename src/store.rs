@@ -0,0 +1,263 @@
+//! 数据存储：一个专用的写连接 + 若干只读连接
+//!
+//! SQLite 在 WAL 模式下允许读写并发，因此读请求 (渲染 Wiki、获取条目) 从连接池中
+//! 取只读连接，写请求串行使用唯一的写连接。所有 SQLite 操作都在
+//! `spawn_blocking` 线程中执行，不占用 Tokio 的工作线程。
+//!
+//! 写操作产生的修改 ([`Change`]) 在写操作结束后统一用于更新预渲染页面的缓存，
+//! 并作为事件广播给订阅者。
+
+use crate::{
+    AppError, AppResult, LazyLoad, ServerConfig, Tiddler, Tiddlers, WikiTemplate,
+    cache::{self, RenderedPage, WikiCache},
+    db,
+    events::{self, TiddlerEvent},
+};
+use bytes::Bytes;
+use rusqlite::Connection;
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc, Mutex as StdMutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tokio::sync::{Mutex, Semaphore, broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// 每个连接都需要设置的 PRAGMA (这些设置不会持久化到数据库文件)
+const CONNECTION_PRAGMAS: &str = r#"
+    PRAGMA busy_timeout = 5000;
+    PRAGMA cache_size = -5000;
+    PRAGMA mmap_size = 67108864;
+    PRAGMA temp_store = MEMORY;
+"#;
+
+/// 一次写操作对单个 tiddler 造成的修改
+#[derive(Clone, Debug)]
+pub(crate) enum Change {
+    Put(Tiddler),
+    Delete(Tiddler),
+    // 通过 /api/inbox 收集的条目：与 Put 相同，只是事件类型为 Inbox
+    Inbox(Tiddler),
+}
+
+pub(crate) struct Store {
+    writer: Arc<Mutex<Tiddlers>>,
+    readers: ReaderPool,
+    cache: Option<WikiCache>,
+    lazy: LazyLoad,
+    events: broadcast::Sender<TiddlerEvent>,
+    // 每次有修改的写操作后递增，用于判断基于条目计算的缓存是否过期
+    generation: AtomicU64,
+}
+
+struct ReaderPool {
+    idle: StdMutex<Vec<Tiddlers>>,
+    permits: Arc<Semaphore>,
+    db_path: PathBuf,
+    passphrase: Option<String>,
+}
+
+impl ReaderPool {
+    fn checkout(&self) -> AppResult<Tiddlers> {
+        if let Some(t) = self.idle.lock().map_err(poisoned)?.pop() {
+            return Ok(t);
+        }
+        let cxn = db::open_connection(&self.db_path, self.passphrase.as_deref())?;
+        cxn.execute_batch(CONNECTION_PRAGMAS).map_err(AppError::from)?;
+        cxn.execute_batch("PRAGMA query_only = ON;").map_err(AppError::from)?;
+        tracing::debug!("Opened a new read connection");
+        Ok(Tiddlers::new(cxn))
+    }
+
+    fn checkin(&self, tiddlers: Tiddlers) {
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.push(tiddlers);
+        }
+    }
+}
+
+fn poisoned<T>(_: T) -> AppError {
+    AppError::Database("connection pool lock poisoned".to_string())
+}
+
+fn join_error(e: tokio::task::JoinError) -> AppError {
+    AppError::Database(format!("database task failed: {}", e))
+}
+
+impl Store {
+    /// `writer` 是已经完成迁移的写连接；只读连接按需打开，最多 `read_connections` 个
+    pub(crate) fn new(writer: Connection, config: &ServerConfig) -> AppResult<Self> {
+        writer.execute_batch(CONNECTION_PRAGMAS).map_err(AppError::from)?;
+        Ok(Self {
+            writer: Arc::new(Mutex::new(Tiddlers::new(writer))),
+            readers: ReaderPool {
+                idle: StdMutex::new(Vec::new()),
+                permits: Arc::new(Semaphore::new(config.read_connections.max(1))),
+                db_path: config.db_path.clone(),
+                passphrase: config.db_passphrase.clone(),
+            },
+            cache: config.cache_wiki.then(|| WikiCache::new(config.lazy_load)),
+            lazy: config.lazy_load,
+            events: broadcast::channel(events::CHANNEL_CAPACITY).0,
+            generation: AtomicU64::new(0),
+        })
+    }
+
+    /// 在只读连接上执行 `f`
+    pub(crate) async fn read<T, F>(self: &Arc<Self>, f: F) -> AppResult<T>
+    where
+        F: FnOnce(&Tiddlers) -> AppResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = self
+            .readers
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| AppError::Database(e.to_string()))?;
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let tiddlers = store.readers.checkout()?;
+            let result = f(&tiddlers);
+            store.readers.checkin(tiddlers);
+            result
+        })
+        .await
+        .map_err(join_error)?
+    }
+
+    /// 在唯一的写连接上执行 `f`，写操作之间互斥
+    pub(crate) async fn write<T, F>(self: &Arc<Self>, f: F) -> AppResult<T>
+    where
+        F: FnOnce(&mut Tiddlers) -> AppResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut guard = self.writer.clone().lock_owned().await;
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            guard.pending.clear();
+            let result = f(&mut guard);
+            let changes = std::mem::take(&mut guard.pending);
+            store.after_write(result.is_ok(), &changes);
+            result
+        })
+        .await
+        .map_err(join_error)?
+    }
+
+    fn after_write(&self, ok: bool, changes: &[Change]) {
+        if !changes.is_empty() {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+        if let Some(cache) = &self.cache {
+            if ok {
+                cache.apply(changes);
+            } else if !changes.is_empty() {
+                // 写操作失败时无法确定哪些修改已经生效，直接丢弃缓存
+                cache.invalidate();
+            }
+        }
+        if ok {
+            for change in changes {
+                self.publish(TiddlerEvent::from_change(change));
+            }
+        }
+    }
+
+    /// 写入代数：在读取之前取得，之后若不相等说明期间有过修改
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// 广播一个事件；没有订阅者时直接丢弃
+    pub(crate) fn publish(&self, event: TiddlerEvent) {
+        let _ = self.events.send(event);
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<TiddlerEvent> {
+        self.events.subscribe()
+    }
+
+    /// 模板被替换后丢弃已缓存的整页
+    pub(crate) fn invalidate_page(&self) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate_page();
+        }
+    }
+
+    pub(crate) fn caches_page(&self) -> bool {
+        self.cache.is_some()
+    }
+
+    /// 以流的形式输出页面：模板前缀、逐行序列化的 tiddler、模板后缀。
+    /// 只读连接在输出结束前一直被占用，客户端读取缓慢时数据库读取也随之暂停。
+    pub(crate) fn stream_page(self: &Arc<Self>, template: Arc<WikiTemplate>) -> ReceiverStream<std::io::Result<Bytes>> {
+        const CHUNK_SIZE: usize = 64 * 1024;
+        let (tx, rx) = mpsc::channel::<std::io::Result<Bytes>>(8);
+        let store = self.clone();
+        let lazy = self.lazy;
+        tokio::spawn(async move {
+            let sender = tx.clone();
+            let result = store
+                .read(move |tiddlers| {
+                    let send = |chunk: Vec<u8>| {
+                        sender
+                            .blocking_send(Ok(Bytes::from(chunk)))
+                            .map_err(|_| AppError::Response("client disconnected".to_string()))
+                    };
+                    send(template.prefix.as_bytes().to_vec())?;
+                    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+                    tiddlers.for_each(|t| {
+                        chunk.push(b',');
+                        chunk.extend(cache::fragment(&t, lazy)?.as_bytes());
+                        if chunk.len() >= CHUNK_SIZE {
+                            send(std::mem::replace(&mut chunk, Vec::with_capacity(CHUNK_SIZE)))?;
+                        }
+                        Ok(())
+                    })?;
+                    chunk.extend(template.suffix.as_bytes());
+                    send(chunk)
+                })
+                .await;
+            if let Err(e) = result {
+                tracing::warn!("Streaming wiki page aborted: {:?}", e);
+                let _ = tx.send(Err(std::io::Error::other(format!("{:?}", e)))).await;
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+
+    /// 生成 (或从缓存中取出) 完整的 Wiki 页面；仅在启用缓存时使用
+    pub(crate) async fn render_page(self: &Arc<Self>, template: Arc<WikiTemplate>) -> AppResult<Arc<RenderedPage>> {
+        let cache = match &self.cache {
+            Some(c) => c,
+            None => return Err(AppError::Response("wiki page cache is disabled".to_string())),
+        };
+        if let Some(page) = cache.page() {
+            return Ok(page);
+        }
+
+        let store = self.clone();
+        self.read(move |tiddlers| {
+            let cache = store.cache.as_ref().expect("cache is enabled");
+            if let Some(page) = cache.assemble_page(&template) {
+                return Ok(page);
+            }
+            let generation = cache.generation();
+            let lazy = store.lazy;
+            let mut fragments = std::collections::BTreeMap::new();
+            for t in tiddlers.all()? {
+                fragments.insert(t.title.clone(), cache::fragment(&t, lazy)?);
+            }
+            let page = Arc::new(RenderedPage::new(cache::assemble(&template, fragments.values())));
+            cache.install(generation, Some(fragments), page.clone());
+            tracing::debug!("Wiki page cache rebuilt");
+            Ok(page)
+        })
+        .await
+    }
+}
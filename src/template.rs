@@ -0,0 +1,181 @@
+//! Wiki 页面模板 (`empty.html`)
+//!
+//! 模板默认编译进程序，也可以通过 `server.template_path` 从磁盘加载，
+//! 或者用 `PUT /api/admin/template` 在运行时替换，从而无需重新编译即可升级 TiddlyWiki 核心。
+
+use crate::{AppError, AppResult, DataStore, ServerConfig};
+use axum::{Extension, body::Bytes};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+/// 编译进程序的默认模板
+pub(crate) const BUILTIN_TEMPLATE: &str = include_str!("../empty.html");
+
+pub(crate) const STORE_MARKER: &str = r#"<script class="tiddlywiki-tiddler-store" type="application/json">"#;
+pub(crate) const SYNC_PLUGIN: &str = "$:/plugins/tiddlywiki/tiddlyweb";
+
+// --- 预处理模板 ---
+
+#[derive(Clone)]
+pub(crate) struct WikiTemplate {
+    pub(crate) prefix: String,
+    pub(crate) suffix: String,
+    /// 从 `<meta name="tiddlywiki-version">` 中读取的核心版本
+    pub(crate) version: Option<String>,
+}
+
+impl WikiTemplate {
+    /// 解析并校验模板：必须包含 tiddler store 脚本，且其内容是合法的 JSON 数组
+    pub(crate) fn parse(html_content: &str) -> Result<Self, String> {
+        let start_tag_idx = html_content
+            .find(STORE_MARKER)
+            .ok_or("missing tiddler store script tag")?;
+        let content_start = start_tag_idx + STORE_MARKER.len();
+        let end_tag_idx = html_content[content_start..]
+            .find("</script>")
+            .map(|i| content_start + i)
+            .ok_or("missing closing script tag of the tiddler store")?;
+        let store = serde_json::from_str::<Vec<Value>>(&html_content[content_start..end_tag_idx])
+            .map_err(|e| format!("tiddler store is not a valid JSON array: {}", e))?;
+        // 页面拼接时在每个 tiddler 前加逗号，因此 store 中至少要有一个 tiddler
+        if store.is_empty() {
+            return Err("tiddler store must contain at least one tiddler".to_string());
+        }
+        let split_idx = html_content[..end_tag_idx]
+            .rfind(']')
+            .ok_or("tiddler store is not a valid JSON array")?;
+
+        Ok(Self {
+            prefix: html_content[..split_idx].to_string(),
+            suffix: html_content[split_idx..].to_string(),
+            version: detect_version(html_content),
+        })
+    }
+
+    /// 去掉模板自带的若干 tiddler (例如导出离线文件时去掉同步插件)
+    pub(crate) fn without(&self, titles: &[&str]) -> Result<Self, String> {
+        let html = format!("{}{}", self.prefix, self.suffix);
+        let start = html.find(STORE_MARKER).ok_or("missing tiddler store script tag")? + STORE_MARKER.len();
+        let end = start + html[start..].find("</script>").ok_or("missing closing script tag of the tiddler store")?;
+        let mut store: Vec<Value> = serde_json::from_str(&html[start..end])
+            .map_err(|e| format!("tiddler store is not a valid JSON array: {}", e))?;
+        store.retain(|t| !t.get("title").and_then(Value::as_str).is_some_and(|title| titles.contains(&title)));
+        let json = serde_json::to_string(&store)
+            .map_err(|e| format!("error serializing tiddler store: {}", e))?
+            .replace("</script>", "<\\/script>");
+        Self::parse(&format!("{}{}{}", &html[..start], json, &html[end..]))
+    }
+
+    /// 模板自带的 tiddler 标题 (核心、插件等)
+    pub(crate) fn store_titles(&self) -> HashSet<String> {
+        let start = self.prefix.find(STORE_MARKER).map_or(0, |i| i + STORE_MARKER.len());
+        serde_json::from_str::<Vec<Value>>(&format!("{}]", &self.prefix[start..]))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|t| t.get("title").and_then(Value::as_str).map(str::to_string))
+            .collect()
+    }
+}
+
+fn warn_if_not_syncing(html: &str) {
+    if !html.contains(SYNC_PLUGIN) {
+        tracing::warn!("Template does not include {}, changes will not be synced to the server", SYNC_PLUGIN);
+    }
+}
+
+fn detect_version(html: &str) -> Option<String> {
+    let meta_idx = html.find(r#"<meta name="tiddlywiki-version""#)?;
+    let tag = &html[meta_idx..meta_idx + html[meta_idx..].find('>')?];
+    let content = &tag[tag.find("content=\"")? + "content=\"".len()..];
+    Some(content[..content.find('"')?].to_string())
+}
+
+/// 可在运行时替换的模板
+pub(crate) type SharedTemplate = Arc<RwLock<Arc<WikiTemplate>>>;
+
+pub(crate) fn current(shared: &SharedTemplate) -> Arc<WikiTemplate> {
+    match shared.read() {
+        Ok(t) => t.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    }
+}
+
+/// 启动时加载模板：优先使用 `template_path`，否则使用内置模板
+pub(crate) fn load(config: &ServerConfig) -> AppResult<WikiTemplate> {
+    match &config.template_path {
+        Some(path) => {
+            let html = std::fs::read_to_string(path)
+                .map_err(|e| AppError::Response(format!("Failed to read template {:?}: {}", path, e)))?;
+            let template = WikiTemplate::parse(&html)
+                .map_err(|e| AppError::Response(format!("Invalid template {:?}: {}", path, e)))?;
+            warn_if_not_syncing(&html);
+            tracing::info!(
+                "Loaded template from {:?} (TiddlyWiki {})",
+                path,
+                template.version.as_deref().unwrap_or("unknown")
+            );
+            Ok(template)
+        }
+        None => WikiTemplate::parse(BUILTIN_TEMPLATE)
+            .map_err(|e| AppError::Response(format!("Invalid built-in template: {}", e))),
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct TemplateInfo {
+    tiddlywiki_version: Option<String>,
+    size: usize,
+    persisted: bool,
+}
+
+// --- Handler: PUT /api/admin/template ，校验后热替换模板 ---
+pub(crate) async fn replace_template(
+    Extension(shared): Extension<SharedTemplate>,
+    Extension(ds): Extension<DataStore>,
+    Extension(config): Extension<ServerConfig>,
+    body: Bytes,
+) -> AppResult<axum::Json<TemplateInfo>> {
+    let html = String::from_utf8(body.to_vec())
+        .map_err(|_| AppError::Response("template must be UTF-8 encoded HTML".to_string()))?;
+    let template = WikiTemplate::parse(&html).map_err(|e| AppError::Response(format!("Invalid template: {}", e)))?;
+    warn_if_not_syncing(&html);
+
+    // 配置了 template_path 时写回磁盘，重启后依然生效
+    let persisted = match &config.template_path {
+        Some(path) => {
+            let tmp = crate::db::sibling_path(path, "new");
+            let saved = async {
+                tokio::fs::write(&tmp, &html).await?;
+                tokio::fs::rename(&tmp, path).await
+            }
+            .await;
+            if let Err(e) = saved {
+                // 写入失败时不能留下半截的临时文件
+                let _ = tokio::fs::remove_file(&tmp).await;
+                return Err(AppError::Response(format!("Failed to save template to {:?}: {}", path, e)));
+            }
+            true
+        }
+        None => false,
+    };
+
+    let info = TemplateInfo {
+        tiddlywiki_version: template.version.clone(),
+        size: html.len(),
+        persisted,
+    };
+    match shared.write() {
+        Ok(mut t) => *t = Arc::new(template),
+        Err(poisoned) => *poisoned.into_inner() = Arc::new(template),
+    }
+    ds.invalidate_page();
+    tracing::info!(
+        "Template replaced (TiddlyWiki {})",
+        info.tiddlywiki_version.as_deref().unwrap_or("unknown")
+    );
+    Ok(axum::Json(info))
+}
@@ -0,0 +1,54 @@
+//! 单元测试共用的临时目录、配置和数据存储
+
+use crate::{AppState, DataStore, ServerConfig, crypto};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+/// 测试结束时自动删除的临时目录
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("tw-test-{}-{}", name, crypto::random_hex(6)));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// 数据库和文件目录都在 `dir` 中的配置
+pub(crate) fn server_config(dir: &Path) -> ServerConfig {
+    serde_json::from_value(json!({
+        "bind": "127.0.0.1",
+        "port": 0,
+        "db_path": dir.join("tiddlers.sqlite3"),
+        "files_dir": dir.join("files"),
+    }))
+    .unwrap()
+}
+
+pub(crate) fn datastore(config: &ServerConfig) -> DataStore {
+    crate::initialize_datastore(config).unwrap()
+}
+
+
+/// 不启用 S3 和加密的应用状态
+pub(crate) fn app_state() -> AppState {
+    AppState {
+        s3_name: String::new(),
+        s3_client: None,
+        bucket_name: String::new(),
+        public_url_base: String::new(),
+        sse_c: None,
+        file_cipher: None,
+    }
+}
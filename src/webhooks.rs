@@ -0,0 +1,341 @@
+//! 对外的 webhook：条目写入 / 删除 / 收件箱事件发生时，向配置的 URL POST 一段 JSON
+//!
+//! 每个 webhook 可以限定事件类型，并用过滤器 (只作用于被修改的那个条目) 按标题或标签筛选。
+//! 设置了 `secret` 时请求体用 HMAC-SHA256 签名，写在 `X-Webhook-Signature` 头中。
+//! 失败时按指数退避重试，每次投递的结果记录在 `webhook_deliveries` 表中，
+//! 可以通过 `GET /api/webhooks/deliveries` 查看。
+
+use crate::{
+    AppError, AppResult, DataStore, Tiddler, crypto,
+    events::{EventKind, TiddlerEvent},
+    filter::Wiki,
+};
+use axum::{
+    Extension,
+    body::Bytes,
+    extract,
+    http::{Request, StatusCode, header},
+};
+use http_body_util::Full;
+use hyper_rustls::HttpsConnector;
+use hyper_util::{
+    client::legacy::{Client, connect::HttpConnector},
+    rt::TokioExecutor,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+// 投递记录最多保留的行数
+const LOG_KEEP: i64 = 1000;
+
+type HttpClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct WebhookConfig {
+    // 投递记录中使用的名字，默认为 URL
+    #[serde(default)]
+    pub(crate) name: Option<String>,
+    pub(crate) url: String,
+    // 签名密钥；不设置时不签名
+    #[serde(default)]
+    pub(crate) secret: Option<String>,
+    #[serde(default = "default_events")]
+    pub(crate) events: Vec<EventKind>,
+    // TiddlyWiki 过滤器，如 `[tag[Incident]]`；只对被修改的条目求值
+    #[serde(default)]
+    pub(crate) filter: Option<String>,
+    // `$:/` 系统条目变化频繁 (如 `$:/StoryList`)，默认不触发
+    #[serde(default)]
+    pub(crate) include_system: bool,
+    #[serde(default = "default_max_attempts")]
+    pub(crate) max_attempts: u32,
+}
+
+fn default_events() -> Vec<EventKind> {
+    vec![EventKind::Put, EventKind::Delete, EventKind::Inbox]
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+impl WebhookConfig {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.url)
+    }
+
+    fn matches(&self, event: &TiddlerEvent) -> bool {
+        // 收件箱条目也是一次写入，只订阅 put 的 webhook 同样会收到
+        let subscribed = self.events.contains(&event.kind)
+            || event.kind == EventKind::Inbox && self.events.contains(&EventKind::Put);
+        if !subscribed || !self.include_system && event.title.starts_with("$:/") {
+            return false;
+        }
+        let Some(filter) = &self.filter else {
+            return true;
+        };
+        let Ok(tiddler) = Tiddler::from_value((*event.skinny).clone()) else {
+            return false;
+        };
+        Wiki::new(vec![tiddler]).filter(filter).is_ok_and(|titles| titles.contains(&event.title))
+    }
+}
+
+/// 第 `attempt` 次失败后等待的时间：2s、4s、8s……，最多 5 分钟
+fn backoff(attempt: u32) -> Duration {
+    INITIAL_BACKOFF.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))).min(MAX_BACKOFF)
+}
+
+/// 对方暂时不可用时才重试；其它 4xx 重试也不会成功
+fn retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::REQUEST_TIMEOUT
+}
+
+/// 一次请求：返回响应状态码，网络错误或超时时返回错误
+async fn send(client: &HttpClient, hook: &WebhookConfig, id: i64, event: &str, body: &Bytes) -> Result<StatusCode, String> {
+    let mut request = Request::post(&hook.url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::USER_AGENT, "tiddly-wiki-server")
+        .header("x-webhook-id", id.to_string())
+        .header("x-webhook-event", event);
+    if let Some(secret) = &hook.secret {
+        let signature = hex::encode(crypto::sign(secret.as_bytes(), body));
+        request = request.header("x-webhook-signature", format!("sha256={}", signature));
+    }
+    let request = request.body(Full::new(body.clone())).map_err(|e| e.to_string())?;
+    match tokio::time::timeout(REQUEST_TIMEOUT, client.request(request)).await {
+        Ok(Ok(response)) => Ok(response.status()),
+        // hyper 的错误信息很简短，具体原因在 source 中
+        Ok(Err(e)) => Err(match std::error::Error::source(&e) {
+            Some(source) => format!("{}: {}", e, source),
+            None => e.to_string(),
+        }),
+        Err(_) => Err(format!("timed out after {}s", REQUEST_TIMEOUT.as_secs())),
+    }
+}
+
+async fn log_delivery(ds: &DataStore, hook: &str, event: &TiddlerEvent) -> AppResult<i64> {
+    let (hook, name, title) = (hook.to_string(), event.name(), event.title.clone());
+    ds.write(move |tiddlers| {
+        tiddlers
+            .cxn
+            .execute(
+                "INSERT INTO webhook_deliveries (webhook, event, title) VALUES (?1, ?2, ?3)",
+                rusqlite::params![hook, name, title],
+            )
+            .map_err(|e| AppError::Database(format!("Error logging webhook delivery: {}", e)))?;
+        let id = tiddlers.cxn.last_insert_rowid();
+        tiddlers
+            .cxn
+            .execute("DELETE FROM webhook_deliveries WHERE id <= ?1", [id - LOG_KEEP])
+            .map_err(AppError::from)?;
+        Ok(id)
+    })
+    .await
+}
+
+async fn update_delivery(ds: &DataStore, id: i64, status: &'static str, attempts: u32, response: Option<StatusCode>, error: Option<String>) {
+    let response = response.map(|s| s.as_u16());
+    let result = ds
+        .write(move |tiddlers| {
+            tiddlers
+                .cxn
+                .execute(
+                    "UPDATE webhook_deliveries
+                     SET status = ?2, attempts = ?3, response_status = ?4, error = ?5,
+                         updated = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+                     WHERE id = ?1",
+                    rusqlite::params![id, status, attempts, response, error],
+                )
+                .map_err(AppError::from)
+        })
+        .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to update webhook delivery {}: {:?}", id, e);
+    }
+}
+
+/// 投递一个事件，失败时按退避时间重试
+async fn deliver(ds: DataStore, client: HttpClient, hook: Arc<WebhookConfig>, event: TiddlerEvent) {
+    let id = match log_delivery(&ds, hook.name(), &event).await {
+        Ok(id) => id,
+        Err(e) => {
+            tracing::error!("Webhook {}: {:?}", hook.name(), e);
+            return;
+        }
+    };
+    let payload = json!({
+        "id": id,
+        "event": event.name(),
+        "title": event.title,
+        "revision": event.revision,
+        "timestamp": chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        "tiddler": *event.skinny,
+    });
+    let body = Bytes::from(payload.to_string());
+
+    let max_attempts = hook.max_attempts.max(1);
+    for attempt in 1..=max_attempts {
+        let (response, error) = match send(&client, &hook, id, event.name(), &body).await {
+            Ok(status) if status.is_success() => {
+                update_delivery(&ds, id, "delivered", attempt, Some(status), None).await;
+                return;
+            }
+            Ok(status) => (Some(status), format!("HTTP {}", status)),
+            Err(e) => (None, e),
+        };
+        let last = attempt == max_attempts || response.is_some_and(|s| !retryable(s));
+        update_delivery(&ds, id, if last { "failed" } else { "pending" }, attempt, response, Some(error.clone())).await;
+        if last {
+            tracing::warn!("Webhook {} failed for '{}' after {} attempt(s): {}", hook.name(), event.title, attempt, error);
+            return;
+        }
+        tokio::time::sleep(backoff(attempt)).await;
+    }
+}
+
+/// 启动分发任务；配置有误的 webhook 会被跳过
+pub(crate) fn spawn(ds: DataStore, hooks: Vec<WebhookConfig>) {
+    let hooks: Vec<Arc<WebhookConfig>> = hooks
+        .into_iter()
+        .filter(|hook| match &hook.filter {
+            Some(filter) => match Wiki::new(Vec::new()).filter(filter) {
+                Ok(_) => true,
+                Err(e) => {
+                    tracing::error!("Webhook {} disabled, invalid filter: {}", hook.name(), e);
+                    false
+                }
+            },
+            None => true,
+        })
+        .map(Arc::new)
+        .collect();
+    if hooks.is_empty() {
+        return;
+    }
+    let connector = match hyper_rustls::HttpsConnectorBuilder::new().with_native_roots() {
+        Ok(builder) => builder.https_or_http().enable_http1().build(),
+        Err(e) => {
+            tracing::error!("Webhooks disabled, could not load root certificates: {}", e);
+            return;
+        }
+    };
+    let client: HttpClient = Client::builder(TokioExecutor::new()).build(connector);
+
+    let mut events = ds.subscribe();
+    tracing::info!("Delivering tiddler events to {} webhook(s)", hooks.len());
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(n)) => {
+                    tracing::warn!("Webhooks fell behind, {} event(s) were not delivered", n);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            for hook in hooks.iter().filter(|hook| hook.matches(&event)) {
+                tokio::spawn(deliver(ds.clone(), client.clone(), hook.clone(), event.clone()));
+            }
+        }
+    });
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DeliveriesQuery {
+    webhook: Option<String>,
+    status: Option<String>,
+    #[serde(default = "default_limit")]
+    limit: i64,
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+#[derive(Serialize)]
+pub(crate) struct Delivery {
+    id: i64,
+    webhook: String,
+    event: String,
+    title: String,
+    status: String,
+    attempts: u32,
+    response_status: Option<u16>,
+    error: Option<String>,
+    created: String,
+    updated: Option<String>,
+}
+
+// --- Handler: GET /api/webhooks/deliveries ，最近的投递记录 ---
+pub(crate) async fn list_deliveries(
+    Extension(ds): Extension<DataStore>,
+    extract::Query(query): extract::Query<DeliveriesQuery>,
+) -> AppResult<axum::Json<Vec<Delivery>>> {
+    let deliveries = ds
+        .read(move |tiddlers| {
+            let mut stmt = tiddlers
+                .cxn
+                .prepare_cached(
+                    "SELECT id, webhook, event, title, status, attempts, response_status, error, created, updated
+                     FROM webhook_deliveries
+                     WHERE (?1 IS NULL OR webhook = ?1) AND (?2 IS NULL OR status = ?2)
+                     ORDER BY id DESC LIMIT ?3",
+                )
+                .map_err(AppError::from)?;
+            let rows = stmt
+                .query_map(rusqlite::params![query.webhook, query.status, query.limit.clamp(1, LOG_KEEP)], |row| {
+                    Ok(Delivery {
+                        id: row.get(0)?,
+                        webhook: row.get(1)?,
+                        event: row.get(2)?,
+                        title: row.get(3)?,
+                        status: row.get(4)?,
+                        attempts: row.get(5)?,
+                        response_status: row.get(6)?,
+                        error: row.get(7)?,
+                        created: row.get(8)?,
+                        updated: row.get(9)?,
+                    })
+                })
+                .map_err(AppError::from)?;
+            rows.collect::<Result<Vec<_>, _>>().map_err(AppError::from)
+        })
+        .await?;
+    Ok(axum::Json(deliveries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn hook(config: serde_json::Value) -> WebhookConfig {
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn inbox_items_send_one_event() {
+        let tmp = testutil::TempDir::new("webhook-inbox");
+        let ds = testutil::datastore(&testutil::server_config(tmp.path()));
+        let mut events = ds.subscribe();
+        let tiddler = Tiddler::from_value(json!({"title": "Inbox 1", "tags": "Inbox"})).unwrap();
+        ds.write(move |t| t.put_inbox(tiddler)).await.unwrap();
+        let event = events.recv().await.unwrap();
+        assert_eq!((event.kind, event.title.as_str()), (EventKind::Inbox, "Inbox 1"));
+        assert!(events.try_recv().is_err());
+
+        let defaults = hook(json!({"url": "http://x"}));
+        assert_eq!(defaults.events, [EventKind::Put, EventKind::Delete, EventKind::Inbox]);
+        assert!(defaults.matches(&event));
+        // 收件箱条目也是一次写入
+        assert!(hook(json!({"url": "http://x", "events": ["put"]})).matches(&event));
+        assert!(!hook(json!({"url": "http://x", "events": ["delete"]})).matches(&event));
+        assert!(!hook(json!({"url": "http://x", "filter": "[tag[Other]]"})).matches(&event));
+    }
+}
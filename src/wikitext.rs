@@ -0,0 +1,310 @@
+//! 最小化的 wikitext 解析：标题列表 (tags / list 字段) 与条目之间的链接
+//!
+//! 只识别会指向其它条目的语法：`[[Title]]`、`[[文字|Title]]`、`<$link to=...>`
+//! 以及嵌入 `{{Title}}` / `{{Title||Template}}`；代码块和行内代码中的内容会被忽略。
+
+use crate::Tiddler;
+
+/// 解析 TiddlyWiki 的标题列表，如 `foo [[bar baz]] qux` (对应 `$tw.utils.parseStringArray`)
+pub(crate) fn parse_string_array(value: &str) -> Vec<String> {
+    let mut titles: Vec<String> = Vec::new();
+    let mut rest = value;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let title = if let Some(inner) = rest.strip_prefix("[[") {
+            let end = inner.find("]]").unwrap_or(inner.len());
+            rest = inner.get(end + 2..).unwrap_or("");
+            &inner[..end]
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let word = &rest[..end];
+            rest = &rest[end..];
+            word
+        };
+        if !title.is_empty() && !titles.iter().any(|t| t == title) {
+            titles.push(title.to_string());
+        }
+    }
+    titles
+}
+
+/// 把标题列表序列化回字符串，含空格的标题用 `[[...]]` 包裹
+pub(crate) fn stringify_list(titles: &[String]) -> String {
+    titles
+        .iter()
+        .map(|t| if t.contains(char::is_whitespace) { format!("[[{}]]", t) } else { t.clone() })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 条目的标签；兼容数组和字符串两种存储形式
+pub(crate) fn tags(tiddler: &Tiddler) -> Vec<String> {
+    let value = tiddler.meta.get("tags").or_else(|| tiddler.meta.get("fields").and_then(|f| f.get("tags")));
+    match value {
+        Some(serde_json::Value::String(s)) => parse_string_array(s),
+        Some(serde_json::Value::Array(arr)) => arr.iter().filter_map(|v| v.as_str()).map(str::to_string).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// 条目正文是否是 wikitext (未设置 type 时也视为 wikitext)
+pub(crate) fn is_wikitext(tiddler: &Tiddler) -> bool {
+    matches!(tiddler.field("type"), None | Some("") | Some("text/vnd.tiddlywiki"))
+}
+
+/// 条目正文中链接 / 嵌入的其它条目，按出现顺序去重
+pub(crate) fn links(tiddler: &Tiddler) -> Vec<String> {
+    if !is_wikitext(tiddler) {
+        return Vec::new();
+    }
+    let text = tiddler.field("text").unwrap_or("");
+    let mut found: Vec<String> = Vec::new();
+    let mut push = |title: &str| {
+        let title = title.trim();
+        if !title.is_empty() && title != tiddler.title && !found.iter().any(|t| t == title) {
+            found.push(title.to_string());
+        }
+    };
+
+    for (_, segment) in split_code(text).into_iter().filter(|(code, _)| !code) {
+        let mut rest = segment;
+        while let Some(idx) = rest.find(['[', '{', '<']) {
+            let tail = &rest[idx..];
+            let consumed = if let Some(inner) = tail.strip_prefix("[[") {
+                match inner.find("]]") {
+                    Some(end) => {
+                        let body = &inner[..end];
+                        let target = body.split_once('|').map_or(body, |(_, target)| target);
+                        if !is_external(target) {
+                            push(target);
+                        }
+                        2 + end + 2
+                    }
+                    None => 2,
+                }
+            } else if let Some(inner) = tail.strip_prefix("{{") {
+                match inner.find("}}") {
+                    // {{{ filter }}} 是过滤器嵌入，不是条目
+                    Some(end) if !inner.starts_with('{') => {
+                        let body = &inner[..end];
+                        let (target, template) = body.split_once("||").unwrap_or((body, ""));
+                        let target = target.split("!!").next().unwrap_or("").split("##").next().unwrap_or("");
+                        push(target);
+                        push(template);
+                        2 + end + 2
+                    }
+                    _ => 2,
+                }
+            } else if tail.starts_with("<$link") {
+                let end = tail.find('>').unwrap_or(tail.len());
+                if let Some(target) = attribute(&tail[..end], "to") {
+                    push(target);
+                }
+                end.max(1)
+            } else {
+                1
+            };
+            rest = &tail[consumed..];
+        }
+    }
+    found
+}
+
+fn is_external(target: &str) -> bool {
+    target.contains("://") || target.starts_with("mailto:")
+}
+
+/// 正文中 `[img[...]]` 引用的条目 (不含外部地址)
+pub(crate) fn image_sources(text: &str) -> Vec<String> {
+    let mut sources = Vec::new();
+    let mut rest = text;
+    while let Some(idx) = rest.find("[img") {
+        let attrs = &rest[idx + 4..];
+        let Some(open) = attrs.find('[') else { break };
+        let Some(end) = attrs[open..].find("]]") else { break };
+        let body = &attrs[open + 1..open + end];
+        let source = body.split_once('|').map_or(body, |(_, s)| s).trim();
+        if !source.contains("://") && !source.starts_with("data:") {
+            sources.push(source.to_string());
+        }
+        rest = &attrs[open + end..];
+    }
+    sources
+}
+
+/// 读取形如 `to="X"`、`to='X'`、`to=[[X]]` 或 `to=X` 的属性值
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{}=", name);
+    let start = tag.match_indices(&pattern).find_map(|(idx, _)| {
+        let before = tag[..idx].chars().last();
+        before.is_some_and(char::is_whitespace).then_some(idx + pattern.len())
+    })?;
+    let value = &tag[start..];
+    for (open, close) in [("\"\"\"", "\"\"\""), ("\"", "\""), ("'", "'"), ("[[", "]]")] {
+        if let Some(inner) = value.strip_prefix(open) {
+            return inner.find(close).map(|end| &inner[..end]);
+        }
+    }
+    // 不带引号的值；以 {{ 或 < 开头的是间接引用，无法静态解析
+    if value.starts_with("{{") || value.starts_with('<') {
+        return None;
+    }
+    let end = value.find(|c: char| c.is_whitespace() || c == '/' || c == '>').unwrap_or(value.len());
+    Some(&value[..end])
+}
+
+/// 按 `|` 切分表格行中的单元格；链接、图片和嵌入 (如 `[[文字|标题]]`) 中的 `|` 不是分隔符
+pub(crate) fn split_cells(row: &str) -> Vec<&str> {
+    let mut cells = Vec::new();
+    let (mut start, mut i) = (0, 0);
+    while let Some(c) = row[i..].chars().next() {
+        let rest = &row[i..];
+        let skip = [("[[", "]]"), ("[img", "]]"), ("[ext[", "]]"), ("{{", "}}")]
+            .iter()
+            .find_map(|(open, close)| rest.strip_prefix(open)?.find(close).map(|end| open.len() + end + close.len()));
+        match skip {
+            Some(len) => i += len,
+            None if c == '|' => {
+                cells.push(&row[start..i]);
+                i += 1;
+                start = i;
+            }
+            None => i += c.len_utf8(),
+        }
+    }
+    cells.push(&row[start..]);
+    cells
+}
+
+/// 把文本切分为 (是否为代码, 片段)，``` 代码块和 `行内代码` 中的内容不会被当作链接
+pub(crate) fn split_code(text: &str) -> Vec<(bool, &str)> {
+    let mut segments = Vec::new();
+    let mut rest = text;
+    while let Some(idx) = rest.find('`') {
+        segments.push((false, &rest[..idx]));
+        let fence = if rest[idx..].starts_with("```") { "```" } else if rest[idx..].starts_with("``") { "``" } else { "`" };
+        let after = &rest[idx + fence.len()..];
+        let end = after.find(fence).map_or(rest.len(), |end| idx + fence.len() + end + fence.len());
+        segments.push((true, &rest[idx..end]));
+        rest = &rest[end..];
+    }
+    segments.push((false, rest));
+    segments
+}
+
+/// 把正文中指向 `from` 的 `[[from]]` / `[[文字|from]]` 链接改为指向 `to`；没有改动时返回 `None`
+pub(crate) fn relink_text(text: &str, from: &str, to: &str) -> Option<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut changed = false;
+    for (code, segment) in split_code(text) {
+        if code {
+            out.push_str(segment);
+            continue;
+        }
+        let mut rest = segment;
+        while let Some(idx) = rest.find("[[") {
+            out.push_str(&rest[..idx]);
+            let inner = &rest[idx + 2..];
+            let Some(end) = inner.find("]]") else {
+                out.push_str(&rest[idx..]);
+                rest = "";
+                break;
+            };
+            let body = &inner[..end];
+            let (label, target) = match body.split_once('|') {
+                Some((label, target)) => (Some(label), target),
+                None => (None, body),
+            };
+            out.push_str("[[");
+            if target.trim() == from {
+                changed = true;
+                if let Some(label) = label {
+                    out.push_str(label);
+                    out.push('|');
+                }
+                out.push_str(to);
+            } else {
+                out.push_str(body);
+            }
+            out.push_str("]]");
+            rest = &inner[end + 2..];
+        }
+        out.push_str(rest);
+    }
+    changed.then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tiddler(text: &str) -> Tiddler {
+        Tiddler::from_value(json!({ "title": "Here", "text": text })).unwrap()
+    }
+
+    #[test]
+    fn string_arrays() {
+        assert_eq!(parse_string_array("foo [[bar baz]]  qux foo"), ["foo", "bar baz", "qux"]);
+        assert_eq!(parse_string_array("[[unterminated"), ["unterminated"]);
+        assert!(parse_string_array("   ").is_empty());
+        let titles = ["a".to_string(), "b c".to_string()];
+        assert_eq!(stringify_list(&titles), "a [[b c]]");
+        assert_eq!(parse_string_array(&stringify_list(&titles)), titles);
+    }
+
+    #[test]
+    fn tags_in_both_forms() {
+        let string = Tiddler::from_value(json!({ "title": "A", "tags": "x [[y z]]" })).unwrap();
+        let array = Tiddler::from_value(json!({ "title": "B", "tags": ["x", "y z"] })).unwrap();
+        assert_eq!(tags(&string), ["x", "y z"]);
+        assert_eq!(tags(&array), ["x", "y z"]);
+    }
+
+    #[test]
+    fn links_and_transclusions() {
+        let t = tiddler("[[A]] [[label|B]] {{C}} {{D!!caption}} {{E##key}} {{F||Tmpl}} <$link to=\"G\">g</$link> [[A]]");
+        assert_eq!(links(&t), ["A", "B", "C", "D", "E", "F", "Tmpl", "G"]);
+        // 与 TiddlyWiki 一样，第一个 `|` 分开文字和目标
+        assert_eq!(links(&tiddler("[[a|b|c]]")), ["b|c"]);
+    }
+
+    #[test]
+    fn links_skip_code_external_and_self() {
+        let t = tiddler("`[[InCode]]`\n```\n{{Block}}\n```\n[[https://example.com]] [[mail|mailto:a@b.c]] {{{ [tag[x]] }}} [[Here]] [[Real]]");
+        assert_eq!(links(&t), ["Real"]);
+        let plain = Tiddler::from_value(json!({ "title": "P", "type": "text/plain", "text": "[[A]]" })).unwrap();
+        assert!(links(&plain).is_empty());
+    }
+
+    #[test]
+    fn link_attributes() {
+        let t = tiddler("<$link to='A'/> <$link to=[[B C]]>x</$link> <$link to=D>d</$link> <$link to={{!!target}}/>");
+        assert_eq!(links(&t), ["A", "B C", "D"]);
+    }
+
+    #[test]
+    fn image_references() {
+        let text = "[img[Local.png]] [img width=32 [Alt|Other.png]] [img[https://x/y.png]] [img[data:image/png;base64,AA]]";
+        assert_eq!(image_sources(text), ["Local.png", "Other.png"]);
+    }
+
+    #[test]
+    fn table_cells() {
+        assert_eq!(split_cells("a| [[x|y]] |[img[alt|Pic]]|{{T||Tmpl}}|[ext[l|https://x]]"), ["a", " [[x|y]] ", "[img[alt|Pic]]", "{{T||Tmpl}}", "[ext[l|https://x]]"]);
+        assert_eq!(split_cells("[[open|"), ["[[open", ""]);
+        assert_eq!(split_cells(""), [""]);
+    }
+
+    #[test]
+    fn relink() {
+        assert_eq!(relink_text("[[Old]] [[see|Old]] [[Other]]", "Old", "New").unwrap(), "[[New]] [[see|New]] [[Other]]");
+        assert_eq!(relink_text("`[[Old]]` [[Old]]", "Old", "New").unwrap(), "`[[Old]]` [[New]]");
+        assert!(relink_text("[[Other]] [[Old", "Old", "New").is_none());
+        assert_eq!(relink_text("[[x|Old|er]] [[Old|er]]", "Old|er", "New").unwrap(), "[[x|New]] [[Old|er]]");
+        assert_eq!(relink_text("[[x|Old|er]]", "Old", "New"), None);
+    }
+}
@@ -4,7 +4,7 @@
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use serde_json::{json, Value};
 
 #[derive(Deserialize, Debug)]
@@ -84,7 +84,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }))?;
 
     // 4. 构建最终的插件 Tiddler
-    let mut plugin_final = json!({
+    let plugin_final = json!({
         "title": manifest.title,
         "name": manifest.name.as_deref().unwrap_or("Custom Plugin"),
         "description": manifest.description.as_deref().unwrap_or(""),